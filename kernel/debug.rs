@@ -5,10 +5,14 @@
 use core::fmt::{Error, Write};
 
 use machine::{inb, outb};
+use time::uptime;
 
 /// Port to output to serial console
 const PORT: u16 = 0x3F8;
 
+/// Is the next byte written with `write_str` the start of a line?
+static mut LINE_START: bool = true;
+
 /// A struct to write data to the console port
 pub struct Debug;
 
@@ -22,14 +26,46 @@ impl Debug {
             }
         }
     }
+
+    /// Read a byte from the port if one has arrived
+    pub fn read_byte(&self) -> Option<u8> {
+        unsafe {
+            if inb(PORT + 5) & 0x01 == 0 {
+                None
+            } else {
+                Some(inb(PORT))
+            }
+        }
+    }
 }
 
-/// Implement `Write` so that we can use format strings
+/// Implement `Write` so that we can use format strings. Every line starts with the time since
+/// boot, in seconds.
 impl Write for Debug {
     /// Take a string slice and write to the serial console
-    #[inline]
     fn write_str(&mut self, data: &str) -> Result<(), Error> {
-        self.write_bytes(data.as_bytes());
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            let end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+
+            unsafe {
+                if LINE_START {
+                    LINE_START = false;
+                    let (secs, micros) = uptime();
+                    write!(self, "[{:5}.{:06}] ", secs, micros)?;
+                }
+            }
+
+            self.write_bytes(rest[..end].as_bytes());
+
+            unsafe {
+                LINE_START = rest[..end].ends_with('\n');
+            }
+
+            rest = &rest[end..];
+        }
+
         Result::Ok(())
     }
 }
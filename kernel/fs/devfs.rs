@@ -0,0 +1,526 @@
+//! A device file system (devfs), which lets processes use devices through files
+//!
+//! The root of a devfs links to these files:
+//!
+//! - `console`: writing draws on the VGA screen, and reading reads the keyboard
+//! - `kbd`: reading reads the keyboard. Reading gives the current process keyboard focus, and
+//!   blocks until at least one key has been typed.
+//! - `ttyS0`: the serial port (COM1). Reading blocks until at least one byte has arrived.
+//! - `hda`, `hdb`, `hdc`, `hdd`: the raw IDE drives, for the drives that are present. The size of
+//!   the file is the size of the drive. A drive that holds the root file system or swap can only
+//!   be opened for reading, since writing to it would corrupt them.
+//!
+//! The set of files is fixed when the devfs is created, so files cannot be created, deleted, or
+//! linked.
+
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::Vec;
+
+use core::cmp::min;
+use core::ptr;
+use core::slice;
+use core::usize;
+
+use super::error::Error;
+use super::now;
+use super::vfs::{FileSystem, Inode, OpenFile, Stat};
+use debug::Debug;
+use errno::Errno;
+use interrupts::no_interrupts;
+use io::block::{BlockDataBuffer, BlockDevice};
+use io::ide::{IDE, SECTOR_SIZE};
+use process::focus::focus;
+use process::{proc_yield, CURRENT_PROCESS};
+use sync::Semaphore;
+use vga::rectangle::{Rectangle, COLS, ROWS};
+
+/// The inode number of `console`
+const CONSOLE: usize = 1;
+
+/// The inode number of `kbd`
+const KBD: usize = 2;
+
+/// The inode number of `ttyS0`
+const SERIAL: usize = 3;
+
+/// The `i`th drive that is present has inode number `DISK_BASE + i`
+const DISK_BASE: usize = 4;
+
+/// The names of the IDE drives, indexed by drive number
+const DISK_NAMES: [&'static str; 4] = ["hda", "hdb", "hdc", "hdd"];
+
+/// The capacity of the keyboard buffer of a process that reads the keyboard
+const KBD_BUFFER_SIZE: usize = 64;
+
+/// A device in a devfs
+#[derive(Clone, Copy, PartialEq)]
+enum Device {
+    Root,
+    Console,
+    Kbd,
+    Serial,
+    Disk(usize),
+}
+
+/// An IDE drive that is present
+struct Disk {
+    drive: u16,
+    sectors: usize,
+}
+
+/// The VGA screen, with a cursor that wraps at the end of each line. There is no scrolling, so
+/// writing past the last line clears the screen and starts again at the top.
+struct Console {
+    screen: Rectangle,
+    cursor: (usize, usize),
+}
+
+/// The state shared by a devfs and its open files
+struct Devices {
+    disks: Vec<Disk>,
+    console: Semaphore<Console>,
+}
+
+/// A file system of devices
+pub struct DevFs {
+    devs: Arc<Devices>,
+}
+
+/// A handle on a file in a devfs
+struct DevInode {
+    device: Device,
+    inode_num: usize,
+    devs: Arc<Devices>,
+}
+
+/// An open file in a devfs
+struct DevFile {
+    device: Device,
+    devs: Arc<Devices>,
+}
+
+impl DevFs {
+    /// Create a new devfs. This probes for IDE drives.
+    pub fn new() -> DevFs {
+        let mut disks = Vec::new();
+
+        for drive in 0..DISK_NAMES.len() as u16 {
+            if let Some(sectors) = IDE::new(drive).sectors() {
+                disks.push(Disk {
+                    drive: drive,
+                    sectors: sectors,
+                });
+            }
+        }
+
+        let console = Console {
+            screen: Rectangle::new(COLS, ROWS, (0, 0)),
+            cursor: (0, 0),
+        };
+
+        DevFs {
+            devs: Arc::new(Devices {
+                disks: disks,
+                console: Semaphore::new(console, 1),
+            }),
+        }
+    }
+}
+
+impl Devices {
+    /// Get the device with the given inode number, if there is one
+    fn get(&self, inode: usize) -> Result<Device, Error<'static>> {
+        match inode {
+            0 => Ok(Device::Root),
+            CONSOLE => Ok(Device::Console),
+            KBD => Ok(Device::Kbd),
+            SERIAL => Ok(Device::Serial),
+            _ if inode >= DISK_BASE && inode - DISK_BASE < self.disks.len() => {
+                Ok(Device::Disk(inode - DISK_BASE))
+            }
+            _ => Err(Error::new(Errno::ENOENT, "No such file or directory")),
+        }
+    }
+
+    /// The name of the given device
+    fn name(&self, device: Device) -> String {
+        match device {
+            Device::Root => String::new(),
+            Device::Console => "console".to_string(),
+            Device::Kbd => "kbd".to_string(),
+            Device::Serial => "ttyS0".to_string(),
+            Device::Disk(i) => DISK_NAMES[self.disks[i].drive as usize].to_string(),
+        }
+    }
+
+    /// The size of the given device in bytes. Only disks have a size. Only the first 4 GiB of a
+    /// larger disk can be reached, since offsets are 32-bit.
+    fn size(&self, device: Device) -> usize {
+        match device {
+            Device::Disk(i) => self.disks[i]
+                .sectors
+                .checked_mul(SECTOR_SIZE)
+                .unwrap_or(usize::MAX / SECTOR_SIZE * SECTOR_SIZE),
+            _ => 0,
+        }
+    }
+}
+
+impl Console {
+    /// Draw the given byte at the cursor and move the cursor
+    fn put_byte(&mut self, byte: u8) {
+        let (mut row, mut col) = self.cursor;
+
+        match byte {
+            b'\n' => {
+                row += 1;
+                col = 0;
+            }
+            // backspace
+            8 => {
+                if col > 0 {
+                    col -= 1;
+                    self.screen.set_cursor((row, col));
+                    self.screen.put_char(' ');
+                }
+            }
+            _ => {
+                self.screen.set_cursor((row, col));
+                self.screen.put_char(byte as char);
+
+                col += 1;
+                if col == COLS {
+                    row += 1;
+                    col = 0;
+                }
+            }
+        }
+
+        if row == ROWS {
+            self.screen.paint();
+            row = 0;
+        }
+
+        self.cursor = (row, col);
+        self.screen.set_cursor(self.cursor);
+    }
+}
+
+/// Read bytes into the buffer with `next` until the buffer is full or `next` returns `None`.
+/// Blocks until there is at least one byte. Returns the number of bytes read.
+fn read_blocking<F>(buf: &mut BlockDataBuffer, mut next: F) -> usize
+where
+    F: FnMut() -> Option<u8>,
+{
+    let room = buf.size() - buf.offset();
+    let mut num_read = 0;
+
+    while num_read < room {
+        match next() {
+            Some(byte) => {
+                let buf_offset = buf.offset();
+                unsafe {
+                    *buf.get_ref_mut::<u8>(buf_offset) = byte;
+                }
+                buf.set_offset(buf_offset + 1);
+                num_read += 1;
+            }
+            None if num_read > 0 => break,
+            None => proc_yield(None),
+        }
+    }
+
+    num_read
+}
+
+/// Read keys typed while the current process has keyboard focus
+fn read_kbd(buf: &mut BlockDataBuffer) -> usize {
+    let me = unsafe { &mut *CURRENT_PROCESS };
+    me.accept_kbd(KBD_BUFFER_SIZE);
+    focus(None);
+
+    // the keyboard only produces ASCII
+    read_blocking(buf, || {
+        me.buffer
+            .as_mut()
+            .and_then(|buffer| buffer.next())
+            .map(|key| key as u8)
+    })
+}
+
+/// Read `bytes` bytes from the given disk at `offset` into the buffer
+fn read_disk(drive: u16, offset: usize, bytes: usize, buf: &mut BlockDataBuffer) {
+    let mut ide = IDE::new(drive);
+    let mut sector = BlockDataBuffer::new(SECTOR_SIZE);
+    let mut done = 0;
+
+    while done < bytes {
+        let pos = offset + done;
+        let in_sector = pos % SECTOR_SIZE;
+        let n = min(bytes - done, SECTOR_SIZE - in_sector);
+
+        ide.read_block(pos / SECTOR_SIZE, &mut sector);
+
+        let buf_offset = buf.offset();
+        unsafe {
+            ptr::copy(
+                sector.get_ptr::<u8>(in_sector),
+                buf.get_ptr_mut::<u8>(buf_offset),
+                n,
+            );
+        }
+        buf.set_offset(buf_offset + n);
+
+        done += n;
+    }
+}
+
+/// Write `bytes` bytes from the buffer to the given disk at `offset`
+fn write_disk(drive: u16, offset: usize, bytes: usize, buf: &mut BlockDataBuffer) {
+    let mut ide = IDE::new(drive);
+    let mut sector = BlockDataBuffer::new(SECTOR_SIZE);
+    let mut done = 0;
+
+    while done < bytes {
+        let pos = offset + done;
+        let in_sector = pos % SECTOR_SIZE;
+        let n = min(bytes - done, SECTOR_SIZE - in_sector);
+
+        // keep the rest of the sector if only part of it is written
+        if n < SECTOR_SIZE {
+            ide.read_block(pos / SECTOR_SIZE, &mut sector);
+        }
+
+        let buf_offset = buf.offset();
+        unsafe {
+            ptr::copy(
+                buf.get_ptr::<u8>(buf_offset),
+                sector.get_ptr_mut::<u8>(in_sector),
+                n,
+            );
+        }
+        buf.set_offset(buf_offset + n);
+
+        ide.write_block(pos / SECTOR_SIZE, &sector);
+
+        done += n;
+    }
+}
+
+/// The error returned when trying to change the set of files
+fn read_only() -> Error<'static> {
+    Error::new(Errno::EROFS, "devfs is read-only")
+}
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn inode(&self, inode: usize) -> Result<Box<Inode>, Error<'static>> {
+        Ok(Box::new(DevInode {
+            device: self.devs.get(inode)?,
+            inode_num: inode,
+            devs: self.devs.clone(),
+        }))
+    }
+
+    fn create(
+        &self,
+        _name: &str,
+        _mode: u32,
+        _uid: u32,
+        _gid: u32,
+    ) -> Result<usize, Error<'static>> {
+        Err(read_only())
+    }
+
+    fn delete(&self, _inode: usize) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+}
+
+impl Inode for DevInode {
+    fn number(&self) -> usize {
+        self.inode_num
+    }
+
+    fn stat(&self) -> Result<Stat, Error<'static>> {
+        // only the owner may use the disks directly
+        let (user_perm, other_perm) = match self.device {
+            Device::Root => (5, 5),
+            Device::Console | Device::Serial => (6, 6),
+            Device::Kbd => (4, 4),
+            Device::Disk(_) => (6, 0),
+        };
+
+        Ok(Stat {
+            inode: self.inode_num,
+            size: self.devs.size(self.device),
+            uid: 0,
+            gid: 0,
+            user_perm: user_perm,
+            group_perm: other_perm,
+            all_perm: other_perm,
+            created: now(),
+            modified: now(),
+            is_dir: self.device == Device::Root,
+            is_symlink: false,
+            // only the root links to the other files
+            nlink: if self.device == Device::Root { 0 } else { 1 },
+        })
+    }
+
+    fn name(&self) -> Result<String, Error<'static>> {
+        Ok(self.devs.name(self.device))
+    }
+
+    fn links(&self) -> Result<Vec<usize>, Error<'static>> {
+        let mut links = Vec::new();
+
+        if self.device == Device::Root {
+            links.push(CONSOLE);
+            links.push(KBD);
+            links.push(SERIAL);
+            links.extend(DISK_BASE..DISK_BASE + self.devs.disks.len());
+        }
+
+        Ok(links)
+    }
+
+    fn lookup(&self, name: &str) -> Result<usize, Error<'static>> {
+        for link in self.links()? {
+            if self.devs.name(self.devs.get(link)?) == name {
+                return Ok(link);
+            }
+        }
+
+        Err(Error::new(Errno::ENOENT, "No such file or directory"))
+    }
+
+    fn link(&self, _other: usize) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+
+    fn unlink(&self, _other: usize) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+
+    fn mkdir(&self, _name: &str, _uid: u32, _gid: u32) -> Result<usize, Error<'static>> {
+        Err(read_only())
+    }
+
+    fn rmdir(&self, _name: &str) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+
+    fn symlink(
+        &self,
+        _name: &str,
+        _target: &str,
+        _uid: u32,
+        _gid: u32,
+    ) -> Result<usize, Error<'static>> {
+        Err(read_only())
+    }
+
+    fn readlink(&self) -> Result<String, Error<'static>> {
+        Err(Error::new(Errno::EINVAL, "Not a symbolic link"))
+    }
+
+    fn chmod(&self, _user: u8, _group: u8, _all: u8) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+
+    fn chown(&self, _uid: u32, _gid: u32) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+
+    fn open(&self, writable: bool) -> Result<Box<OpenFile>, Error<'static>> {
+        if writable && self.device == Device::Kbd {
+            return Err(Error::new(Errno::EACCES, "The keyboard cannot be written"));
+        }
+
+        if let Device::Disk(i) = self.device {
+            if writable && IDE::new(self.devs.disks[i].drive).is_claimed() {
+                return Err(Error::new(Errno::EBUSY, "Drive in use by the kernel"));
+            }
+        }
+
+        Ok(Box::new(DevFile {
+            device: self.device,
+            devs: self.devs.clone(),
+        }))
+    }
+}
+
+impl OpenFile for DevFile {
+    /// The terminals and the keyboard have no offset, and reading them blocks until there is
+    /// input
+    fn read(&self, offset: usize, buf: &mut BlockDataBuffer) -> Result<usize, Error<'static>> {
+        let size = self.devs.size(self.device);
+
+        Ok(match self.device {
+            Device::Root => 0,
+            Device::Console | Device::Kbd => read_kbd(buf),
+            Device::Serial => read_blocking(buf, || Debug.read_byte()),
+            Device::Disk(i) => {
+                let offset = min(offset, size);
+                let bytes = min(buf.size() - buf.offset(), size - offset);
+                read_disk(self.devs.disks[i].drive, offset, bytes, buf);
+                bytes
+            }
+        })
+    }
+
+    fn write(
+        &self,
+        offset: usize,
+        bytes: usize,
+        buf: &mut BlockDataBuffer,
+    ) -> Result<usize, Error<'static>> {
+        let buf_offset = buf.offset();
+
+        match self.device {
+            Device::Root => Err(read_only()),
+            Device::Kbd => Err(Error::new(Errno::EACCES, "The keyboard cannot be written")),
+            Device::Console => {
+                let mut console = self.devs.console.down();
+                for i in 0..bytes {
+                    console.put_byte(unsafe { *buf.get_ref::<u8>(buf_offset + i) });
+                }
+                buf.set_offset(buf_offset + bytes);
+                Ok(bytes)
+            }
+            Device::Serial => {
+                let src = unsafe { slice::from_raw_parts(buf.get_ptr::<u8>(buf_offset), bytes) };
+
+                // keep kernel messages from landing in the middle
+                no_interrupts(|| Debug.write_bytes(src));
+
+                buf.set_offset(buf_offset + bytes);
+                Ok(bytes)
+            }
+            Device::Disk(i) => {
+                let size = self.devs.size(self.device);
+                let num_written = min(bytes, size - min(offset, size));
+                if bytes > 0 && num_written == 0 {
+                    return Err(Error::new(Errno::ENOSPC, "Write past the end of the disk"));
+                }
+
+                write_disk(self.devs.disks[i].drive, offset, num_written, buf);
+                Ok(num_written)
+            }
+        }
+    }
+
+    fn size(&self) -> Result<usize, Error<'static>> {
+        Ok(self.devs.size(self.device))
+    }
+
+    fn name(&self) -> String {
+        self.devs.name(self.device)
+    }
+}
@@ -1,11 +1,33 @@
 //! A simple Error object for FS errors
 
+use errno::Errno;
+
 pub struct Error<'err> {
+    errno: Errno,
     msg: &'err str,
 }
 
 impl<'err> Error<'err> {
-    pub fn new(msg: &'err str) -> Error {
-        Error { msg: msg }
+    pub fn new(errno: Errno, msg: &'err str) -> Error {
+        Error {
+            errno: errno,
+            msg: msg,
+        }
+    }
+
+    /// Get the error code to report to user processes
+    pub fn errno(&self) -> Errno {
+        self.errno
+    }
+
+    /// Get a description of the error
+    pub fn msg(&self) -> &'err str {
+        self.msg
+    }
+}
+
+impl<'err> From<Error<'err>> for Errno {
+    fn from(err: Error<'err>) -> Errno {
+        err.errno
     }
 }
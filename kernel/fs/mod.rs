@@ -1,21 +1,446 @@
 //! A module for file system stuff
+//!
+//! All file systems are reached through the VFS traits in `vfs` and the mount table in `mount`.
+//! The root file system is OFS on the hard disk. A ramfs is mounted on `/tmp` for scratch files,
+//! a procfs is mounted on `/proc` to show the state of the kernel, and a devfs is mounted on `/dev`
+//! to give access to devices.
 
+pub mod devfs;
 pub mod error;
+pub mod mount;
 pub mod ofs;
+pub mod procfs;
+pub mod ramfs;
+pub mod vfs;
 
+use alloc::arc::Arc;
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::Vec;
 
+use self::devfs::DevFs;
+use self::error::Error;
+use self::mount::{MountTable, MOUNT_TABLE};
 use self::ofs::OFSHandle;
+use self::procfs::ProcFs;
+use self::ramfs::RamFs;
+use self::vfs::{split_mode, PERM_EXEC, PERM_WRITE};
+use self::vfs::{Credentials, DirEntry, FileSystem, Inode, Stat, VNode, ROOT, SUPERUSER};
+use errno::Errno;
 use io::ide::IDE;
+use memory::heap_usage;
+use time;
 
-pub static mut ROOT_FS: *mut OFSHandle<IDE> = 0 as *mut OFSHandle<IDE>;
+/// The maximum number of symbolic links followed by a single lookup
+const MAX_SYMLINKS: usize = 8;
+
+/// Get the current time in seconds since the Unix epoch, for file dates
+pub fn now() -> u32 {
+    time::now()
+}
 
 /// Initialize the root file system from the given device
 pub fn init(device: IDE) {
+    device.claim();
+
     unsafe {
-        let ofs = box OFSHandle::new(device);
-        ROOT_FS = Box::into_raw(ofs);
+        let table = box MountTable::new(OFSHandle::new(device));
+        MOUNT_TABLE = Box::into_raw(table);
+    }
+
+    // the ramfs may use up to half of the heap that is free now
+    let (_, heap_free) = heap_usage();
+    if let Err(err) = mount("/tmp", RamFs::new(heap_free / 2)) {
+        printf!("Cannot mount ramfs on /tmp: {}\n", err.msg());
+    }
+
+    if let Err(err) = mount("/proc", ProcFs) {
+        printf!("Cannot mount procfs on /proc: {}\n", err.msg());
+    }
+
+    if let Err(err) = mount("/dev", DevFs::new()) {
+        printf!("Cannot mount devfs on /dev: {}\n", err.msg());
     }
 
     printf!("filesystem inited\n");
 }
+
+/// Mount the file system on the file with the given absolute path, and return its index in the
+/// mount table.
+pub fn mount<F: FileSystem + 'static>(path: &str, fs: F) -> Result<usize, Error<'static>> {
+    let on = lookup(path, ROOT, &[], &SUPERUSER)?;
+    unsafe { (*MOUNT_TABLE).mount(fs, on) }
+}
+
+/// Get a handle on the given file
+pub fn inode(node: VNode) -> Result<Box<Inode>, Error<'static>> {
+    file_system(node.mount)?.inode(node.inode)
+}
+
+/// Get the file system with the given index in the mount table
+fn file_system(mount: usize) -> Result<Arc<FileSystem>, Error<'static>> {
+    unsafe { (*MOUNT_TABLE).get(mount) }
+        .ok_or(Error::new(Errno::ENOENT, "No such file or directory"))
+}
+
+/// Check that a process with the given credentials may use the file in all the ways in `want`
+/// (`PERM_*` bits), and return the metadata of the file
+pub fn access(file: &Inode, creds: &Credentials, want: u8) -> Result<Stat, Error<'static>> {
+    let stat = file.stat()?;
+
+    if stat.permits(creds, want) {
+        Ok(stat)
+    } else {
+        Err(Error::new(Errno::EACCES, "Permission denied"))
+    }
+}
+
+/// Return the file that the given file leads to: the root of the file system mounted on it if
+/// there is one, or the file itself.
+pub fn cross(node: VNode) -> VNode {
+    unsafe { (*MOUNT_TABLE).cross(node) }
+}
+
+/// Return the file with the given path.
+///
+/// Each component of the path names a file linked from the file before it. Paths starting with
+/// `/` start at the root. Other paths start at `cwf`, and `parents` is the stack of files taken
+/// to reach it, not including `cwf` (see `Process::path`). `.` stays at the current file, and
+/// `..` goes back to the previous file on the stack. Reaching a file that has a file system
+/// mounted on it continues at the root of that file system.
+///
+/// A symbolic link is replaced by the path it points to, which is looked up from the file linking
+/// to the symbolic link (or from the root if it is absolute). At most `MAX_SYMLINKS` symbolic
+/// links are followed, so that loops end with `ELOOP`.
+///
+/// Looking up a name in a file needs permission to execute the file under the given credentials.
+pub fn lookup(
+    path: &str,
+    cwf: VNode,
+    parents: &[VNode],
+    creds: &Credentials,
+) -> Result<VNode, Error<'static>> {
+    let mut followed = 0;
+    let (node, _) = walk(path, cwf, parents.to_vec(), creds, true, &mut followed)?;
+    Ok(node)
+}
+
+/// Like `lookup`, but if the last file of the path is a symbolic link, return the link itself
+fn lookup_link(
+    path: &str,
+    cwf: VNode,
+    parents: &[VNode],
+    creds: &Credentials,
+) -> Result<VNode, Error<'static>> {
+    let mut followed = 0;
+    let (node, _) = walk(path, cwf, parents.to_vec(), creds, false, &mut followed)?;
+    Ok(node)
+}
+
+/// Follow the path from `cwf` (see `lookup`), and return the file reached and the stack of files
+/// taken to reach it. The last file is only followed if it is a symbolic link and `follow_last`
+/// is true. `followed` counts the symbolic links followed so far.
+fn walk(
+    path: &str,
+    cwf: VNode,
+    parents: Vec<VNode>,
+    creds: &Credentials,
+    follow_last: bool,
+    followed: &mut usize,
+) -> Result<(VNode, Vec<VNode>), Error<'static>> {
+    if path.is_empty() {
+        return Err(Error::new(Errno::ENOENT, "No such file or directory"));
+    }
+
+    let (mut current, mut stack) = if path.starts_with('/') {
+        (cross(ROOT), Vec::new())
+    } else {
+        (cwf, parents)
+    };
+
+    let mut names = path.split('/').filter(|name| !name.is_empty()).peekable();
+
+    while let Some(name) = names.next() {
+        match name {
+            "." => {}
+            ".." => {
+                // the root is its own parent
+                if let Some(parent) = stack.pop() {
+                    current = parent;
+                }
+            }
+            _ => {
+                let dir = inode(current)?;
+                access(&*dir, creds, PERM_EXEC)?;
+
+                let next = cross(VNode::new(current.mount, dir.lookup(name)?));
+                let file = inode(next)?;
+                let last = names.peek().is_none();
+
+                if (follow_last || !last) && file.stat()?.is_symlink {
+                    *followed += 1;
+                    if *followed > MAX_SYMLINKS {
+                        return Err(Error::new(
+                            Errno::ELOOP,
+                            "Too many levels of symbolic links",
+                        ));
+                    }
+
+                    let target = file.readlink()?;
+                    let (node, node_stack) = walk(&target, current, stack, creds, true, followed)?;
+
+                    current = node;
+                    stack = node_stack;
+                } else {
+                    stack.push(current);
+                    current = next;
+                }
+            }
+        }
+    }
+
+    Ok((current, stack))
+}
+
+/// List the files linked from the given directory
+pub fn readdir(node: VNode) -> Result<Vec<DirEntry>, Error<'static>> {
+    let dir = inode(node)?;
+
+    if !dir.stat()?.is_dir {
+        return Err(Error::new(Errno::ENOTDIR, "Not a directory"));
+    }
+
+    let mut entries = Vec::new();
+
+    for link in dir.links()? {
+        // skip files that are deleted while we look
+        let file = match inode(VNode::new(node.mount, link)) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+
+        if let (Ok(name), Ok(stat)) = (file.name(), file.stat()) {
+            entries.push(DirEntry {
+                inode: link,
+                name: name,
+                is_dir: stat.is_dir,
+                is_symlink: stat.is_symlink,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Create an empty directory with the given path (see `lookup`), and return it. The directory is
+/// owned by the effective user and group of the given credentials.
+pub fn mkdir(
+    path: &str,
+    cwf: VNode,
+    parents: &[VNode],
+    creds: &Credentials,
+) -> Result<VNode, Error<'static>> {
+    let (parent, name) = split_path(path)?;
+    let dir = lookup(parent, cwf, parents, creds)?;
+    let dir_inode = inode(dir)?;
+
+    access(&*dir_inode, creds, PERM_WRITE | PERM_EXEC)?;
+
+    let new = dir_inode.mkdir(name, creds.euid, creds.egid)?;
+    Ok(VNode::new(dir.mount, new))
+}
+
+/// Create an empty regular file with the given path (see `lookup`) and permissions (`mode`, e.g.
+/// `0o640`), and return it. The file is owned by the effective user and group of the given
+/// credentials.
+pub fn create(
+    path: &str,
+    mode: u32,
+    cwf: VNode,
+    parents: &[VNode],
+    creds: &Credentials,
+) -> Result<VNode, Error<'static>> {
+    let (parent, name) = split_path(path)?;
+    let dir = lookup(parent, cwf, parents, creds)?;
+    let dir_inode = inode(dir)?;
+
+    if !access(&*dir_inode, creds, PERM_WRITE | PERM_EXEC)?.is_dir {
+        return Err(Error::new(Errno::ENOTDIR, "Not a directory"));
+    }
+
+    let fs = file_system(dir.mount)?;
+    let new = fs.create(name, mode, creds.euid, creds.egid)?;
+
+    // nothing else links to the new file, so it goes away if it cannot be linked here
+    if let Err(err) = dir_inode.link(new) {
+        let _ = fs.delete(new);
+        return Err(err);
+    }
+
+    Ok(VNode::new(dir.mount, new))
+}
+
+/// Remove the empty directory with the given path (see `lookup`)
+pub fn rmdir(
+    path: &str,
+    cwf: VNode,
+    parents: &[VNode],
+    creds: &Credentials,
+) -> Result<(), Error<'static>> {
+    let (parent, name) = split_path(path)?;
+    let parent = lookup(parent, cwf, parents, creds)?;
+    let dir = inode(parent)?;
+
+    access(&*dir, creds, PERM_WRITE | PERM_EXEC)?;
+
+    let node = VNode::new(parent.mount, dir.lookup(name)?);
+    if cross(node) != node {
+        return Err(Error::new(
+            Errno::EBUSY,
+            "A file system is mounted on the directory",
+        ));
+    }
+
+    dir.rmdir(name)
+}
+
+/// Create a symbolic link with the given path (see `lookup`) to the path `target`, and return it.
+/// The link is owned by the effective user and group of the given credentials.
+pub fn symlink(
+    target: &str,
+    path: &str,
+    cwf: VNode,
+    parents: &[VNode],
+    creds: &Credentials,
+) -> Result<VNode, Error<'static>> {
+    if target.is_empty() {
+        return Err(Error::new(Errno::ENOENT, "No such file or directory"));
+    }
+
+    let (parent, name) = split_path(path)?;
+    let dir = lookup(parent, cwf, parents, creds)?;
+    let dir_inode = inode(dir)?;
+
+    access(&*dir_inode, creds, PERM_WRITE | PERM_EXEC)?;
+
+    let new = dir_inode.symlink(name, target, creds.euid, creds.egid)?;
+    Ok(VNode::new(dir.mount, new))
+}
+
+/// Get the path the symbolic link with the given path (see `lookup`) points to
+pub fn readlink(
+    path: &str,
+    cwf: VNode,
+    parents: &[VNode],
+    creds: &Credentials,
+) -> Result<String, Error<'static>> {
+    inode(lookup_link(path, cwf, parents, creds)?)?.readlink()
+}
+
+/// Link the file with the given path (see `lookup`) from the directory with the path `dir`. The
+/// file keeps its name, and both must be on the same file system. A symbolic link is linked
+/// itself rather than the file it points to.
+pub fn link(
+    path: &str,
+    dir: &str,
+    cwf: VNode,
+    parents: &[VNode],
+    creds: &Credentials,
+) -> Result<(), Error<'static>> {
+    let node = lookup_link(path, cwf, parents, creds)?;
+    let dir = lookup(dir, cwf, parents, creds)?;
+
+    if node.mount != dir.mount {
+        return Err(Error::new(Errno::EXDEV, "Invalid cross-device link"));
+    }
+
+    let dir_inode = inode(dir)?;
+    access(&*dir_inode, creds, PERM_WRITE | PERM_EXEC)?;
+
+    dir_inode.link(node.inode)
+}
+
+/// Remove the link to the file with the given path (see `lookup`) from the file before it, and
+/// delete the file if nothing links to it anymore. Directories are removed with `rmdir` instead.
+pub fn unlink(
+    path: &str,
+    cwf: VNode,
+    parents: &[VNode],
+    creds: &Credentials,
+) -> Result<(), Error<'static>> {
+    let (parent, name) = split_path(path)?;
+    let parent = lookup(parent, cwf, parents, creds)?;
+    let dir = inode(parent)?;
+
+    access(&*dir, creds, PERM_WRITE | PERM_EXEC)?;
+
+    // the file is deleted by the same operation if this was its last link
+    let file = dir.lookup(name)?;
+    dir.unlink(file)
+}
+
+/// Set the permissions of the file with the given path (see `lookup`) to the `rwx` bits of the
+/// owner, the group, and everyone else in `mode` (e.g. `0o750`). Only the owner and the superuser
+/// may do this.
+pub fn chmod(
+    path: &str,
+    mode: u32,
+    cwf: VNode,
+    parents: &[VNode],
+    creds: &Credentials,
+) -> Result<(), Error<'static>> {
+    let file = inode(lookup(path, cwf, parents, creds)?)?;
+    let stat = file.stat()?;
+
+    if creds.euid != 0 && creds.euid != stat.uid {
+        return Err(Error::new(Errno::EPERM, "Operation not permitted"));
+    }
+
+    let (user_perm, group_perm, all_perm) = split_mode(mode);
+    file.chmod(user_perm, group_perm, all_perm)
+}
+
+/// Set the owner and group of the file with the given path (see `lookup`). `None` keeps the
+/// current owner or group. Only the superuser may give a file away, but the owner may change the
+/// group to their effective group.
+pub fn chown(
+    path: &str,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    cwf: VNode,
+    parents: &[VNode],
+    creds: &Credentials,
+) -> Result<(), Error<'static>> {
+    let file = inode(lookup(path, cwf, parents, creds)?)?;
+    let stat = file.stat()?;
+
+    let uid = uid.unwrap_or(stat.uid);
+    let gid = gid.unwrap_or(stat.gid);
+
+    let allowed = creds.euid == 0
+        || (creds.euid == stat.uid && uid == stat.uid && (gid == stat.gid || gid == creds.egid));
+
+    if !allowed {
+        return Err(Error::new(Errno::EPERM, "Operation not permitted"));
+    }
+
+    file.chown(uid, gid)
+}
+
+/// Split the path into the path of the parent directory and the name of the last file
+fn split_path(path: &str) -> Result<(&str, &str), Error<'static>> {
+    if path.is_empty() {
+        return Err(Error::new(Errno::ENOENT, "No such file or directory"));
+    }
+
+    let path = path.trim_right_matches('/');
+    let (parent, name) = match path.rfind('/') {
+        Some(i) => (&path[..i + 1], &path[i + 1..]),
+        None => (".", path),
+    };
+
+    match name {
+        "" | "." | ".." => Err(Error::new(Errno::EINVAL, "Invalid file name")),
+        _ => Ok((parent, name)),
+    }
+}
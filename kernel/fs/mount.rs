@@ -0,0 +1,96 @@
+//! The mount table, which joins all mounted file systems into one namespace
+
+use alloc::arc::Arc;
+use alloc::Vec;
+
+use super::error::Error;
+use super::vfs::{FileSystem, VNode};
+use errno::Errno;
+use sync::Semaphore;
+
+/// The mount table of the kernel, created by `fs::init`
+pub static mut MOUNT_TABLE: *mut MountTable = 0 as *mut MountTable;
+
+/// A mounted file system
+struct Mount {
+    /// The file system
+    fs: Arc<FileSystem>,
+
+    /// The file that the root of the file system covers, or None for the root file system
+    on: Option<VNode>,
+}
+
+/// A table of mounted file systems. A `VNode` names a file system by its index in the table.
+/// File systems cannot be unmounted yet, so the indices never change.
+pub struct MountTable {
+    mounts: Semaphore<Vec<Mount>>,
+}
+
+impl MountTable {
+    /// Create a new mount table with the given root file system as mount 0
+    pub fn new<F: FileSystem + 'static>(root: F) -> MountTable {
+        let mut mounts = Vec::new();
+        mounts.push(Mount {
+            fs: Arc::new(root),
+            on: None,
+        });
+
+        MountTable {
+            mounts: Semaphore::new(mounts, 1),
+        }
+    }
+
+    /// Mount the file system on the given file, and return its index in the table. Looking up the
+    /// file after that gives the root of the file system instead.
+    pub fn mount<F: FileSystem + 'static>(
+        &self,
+        fs: F,
+        on: VNode,
+    ) -> Result<usize, Error<'static>> {
+        let mut mounts = self.mounts.down();
+
+        if on.mount >= mounts.len() {
+            return Err(Error::new(Errno::ENOENT, "No such file or directory"));
+        }
+
+        if mounts.iter().any(|mount| mount.on == Some(on)) {
+            return Err(Error::new(
+                Errno::EBUSY,
+                "Something is already mounted there",
+            ));
+        }
+
+        mounts.push(Mount {
+            fs: Arc::new(fs),
+            on: Some(on),
+        });
+
+        printf!(
+            "{} mounted on {}:{} as mount {}\n",
+            mounts[mounts.len() - 1].fs.name(),
+            on.mount,
+            on.inode,
+            mounts.len() - 1
+        );
+
+        Ok(mounts.len() - 1)
+    }
+
+    /// Get the file system with the given index, if there is one
+    pub fn get(&self, mount: usize) -> Option<Arc<FileSystem>> {
+        let mounts = self.mounts.down();
+        mounts.get(mount).map(|mount| mount.fs.clone())
+    }
+
+    /// If a file system is mounted on the given file, return its root instead (and so on, if
+    /// something is mounted on that root too). Otherwise, return the file itself.
+    pub fn cross(&self, mut node: VNode) -> VNode {
+        let mounts = self.mounts.down();
+
+        while let Some(i) = mounts.iter().position(|mount| mount.on == Some(node)) {
+            node = VNode::new(i, 0);
+        }
+
+        node
+    }
+}
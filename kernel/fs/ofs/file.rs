@@ -3,289 +3,182 @@
 use alloc::{arc::Arc, string::String};
 
 use core::cmp::min;
-use core::mem;
-use core::ptr::copy;
+use core::slice;
 
+use super::super::error::Error;
+use super::super::vfs::OpenFile;
 use super::hw::*;
 use super::internals::*;
+use super::no_such_file;
+use errno::Errno;
 use io::block::{BlockDataBuffer, BlockDevice};
+use ofs_core::DNODE_SIZE;
 use sync::Semaphore;
 
 /// A handle on the file for all needed operations.
+///
+/// The inode is read again under the fs lock by every operation, since other handles on the same
+/// file may change it, and the file may be deleted while the handle is open.
 pub struct File<B: BlockDevice> {
     // TODO: make these private
     // TODO: need marker if this file is read_only
     pub inode_num: usize,
-    pub inode: Inode,
-    pub offset: usize,
-    pub offset_dnode: usize,
+    pub generation: usize,
     pub ofs: Arc<Semaphore<OFS<B>>>,
 }
 
 impl<B: BlockDevice> File<B> {
-    /// Read from the file at the file offset into the buffer at the buffer offset.  This method
-    /// will not overflow the buffer or read past the end of the file, but it might not read as
-    /// much as possible from the file, even if the buffer is not full. This updates both the
-    /// file offset and the buffer offset.
-    fn read_part(&mut self, buf: &mut BlockDataBuffer) -> usize {
-        // EOF
-        if self.inode.size == self.offset {
-            return 0;
-        }
-
-        // lock the file system
-        let mut fs = self.ofs.down();
-
-        let dnode_size = mem::size_of::<Dnode>();
-
-        // Where are we in the current dnode?
-        // How many bytes left in dnode?
-        let dnode = fs.dnode_num_to_block_num(self.offset_dnode);
-        let dnode_offset = self.offset % (dnode_size - 4);
-
-        // Is this the last dnode of the file?
-        let last_dnode = self.inode.size - self.offset <= dnode_size - dnode_offset;
-
-        if last_dnode {
-            let bytes_left = self.inode.size - self.offset;
-            let num_read = min(bytes_left, buf.size() - buf.offset());
-            fs.device.read_exactly(dnode, dnode_offset, bytes_left, buf);
-            self.offset += num_read;
-            num_read
+    /// Get the inode of the file, if it still exists
+    fn get(&self, fs: &mut OFS<B>) -> Result<Inode, Error<'static>> {
+        if !fs.is_current(self.inode_num, self.generation) {
+            Err(no_such_file())
         } else {
-            let bytes_left = dnode_size - (self.offset % (dnode_size - 4)) - 4;
-
-            if buf.size() - buf.offset() < bytes_left {
-                let num_read = buf.size() - buf.offset();
-                fs.device.read_exactly(dnode, dnode_offset, num_read, buf);
-                self.offset += num_read;
-                num_read
-            } else {
-                let tmp = &mut BlockDataBuffer::new(bytes_left + 4);
-                fs.device.read_fully(dnode, dnode_offset, tmp);
-                self.offset += bytes_left;
-                self.offset_dnode = unsafe { *tmp.get_ptr(bytes_left / 4) };
-                let buf_offset = buf.offset();
-                unsafe {
-                    copy(
-                        tmp.get_ptr::<u8>(0),
-                        buf.get_ptr_mut::<u8>(buf_offset),
-                        bytes_left,
-                    );
-                }
-                buf.set_offset(buf_offset + bytes_left);
-                bytes_left
-            }
+            Ok(fs.get_inode(self.inode_num))
         }
     }
 
-    /// Write from the buffer at the buffer offset into the file at the file offset, overwriting
-    /// any exisiting content. This method may extend the length of the file. This method might not
-    /// write as much as possible from the file. This updates both the file offset and the buffer
-    /// offset.
-    fn write_part(&mut self, bytes: usize, buf: &mut BlockDataBuffer) -> usize {
-        // TODO update modified time
-
+    /// Write from the buffer at the buffer offset into the file at `offset`, overwriting any
+    /// exisiting content. This method may extend the length of the file. This method might not
+    /// write as much as possible from the file. This updates the buffer offset.
+    ///
+    /// Each part is written in its own transaction, together with the inode.
+    fn write_part(
+        &self,
+        offset: usize,
+        bytes: usize,
+        buf: &mut BlockDataBuffer,
+    ) -> Result<usize, Error<'static>> {
         // lock the file system
         let mut fs = self.ofs.down();
 
-        // How many bytes left in dnode?
-        let dnode_size = mem::size_of::<Dnode>();
+        // Write up to the end of the dnode
+        let num_write = min(DNODE_SIZE - offset % DNODE_SIZE, bytes);
 
-        // Where are we in the current dnode?
-        let dnode = fs.dnode_num_to_block_num(self.offset_dnode);
-        let dnode_offset = self.offset % (dnode_size - 4);
+        let buf_offset = buf.offset();
+        let data = unsafe { slice::from_raw_parts(buf.get_ptr::<u8>(buf_offset), num_write) };
 
-        // Is this the last dnode of the file?
-        let last_dnode = self.inode.size - self.offset <= dnode_size - dnode_offset;
+        // The dnode is allocated if the file does not have it yet. The inode is only updated if
+        // the transaction is committed.
+        let mut inode = self.get(&mut fs)?;
+        if let Err(err) = fs.write_file(&mut inode, offset, data) {
+            fs.device.abort();
+            return Err(err.into());
+        }
 
-        // Will the write stay within this dnode?
-        let within = if last_dnode {
-            dnode_size >= dnode_offset + bytes
-        } else {
-            dnode_size - 4 >= dnode_offset + bytes
-        };
+        // the inode is part of the same transaction
+        inode.modified = now();
+        fs.write_inode(self.inode_num, &inode);
+        fs.device.commit()?;
 
-        // Do the write
-        if within {
-            let num_write = bytes;
-            fs.device.write_exactly(dnode, dnode_offset, num_write, buf);
-            let eof_offset = self.inode.size % (dnode_size - 4);
-            if last_dnode && eof_offset < dnode_offset + num_write {
-                self.inode.size += dnode_offset + num_write - eof_offset;
-            }
-            num_write
-        } else {
-            let num_write = min(dnode_size - 4 - dnode_offset, bytes);
-            let curr_dnode = fs.get_dnode(self.offset_dnode);
-            let tmp = &mut BlockDataBuffer::new(dnode_size);
-            unsafe {
-                copy(
-                    (&curr_dnode.data[0]) as *const usize as *const u8,
-                    tmp.get_ptr_mut::<u8>(0),
-                    dnode_size,
-                );
-            }
-            let next_dnode = if last_dnode {
-                let new_dnode = fs.alloc_dnode();
-                unsafe {
-                    *tmp.get_ptr_mut::<usize>((dnode_size / mem::size_of::<usize>()) - 1) =
-                        new_dnode;
-                }
-                self.inode.size += num_write + 1; // +1 so this is no longer the last dnode
-                new_dnode
-            } else {
-                curr_dnode.get_next()
-            };
-            let old_buf_offset = buf.offset();
-            unsafe {
-                copy(
-                    buf.get_ptr::<u8>(old_buf_offset),
-                    tmp.get_ptr_mut::<u8>(dnode_offset),
-                    num_write,
-                );
-            }
-            let dnode_blk = fs.dnode_num_to_block_num(self.offset_dnode);
-            let dnode_offset = fs.dnode_num_to_block_offset(self.offset_dnode);
-            fs.device.write_fully(dnode_blk, dnode_offset, tmp);
-            self.offset += num_write;
-            self.offset_dnode = next_dnode;
-            buf.set_offset(old_buf_offset + num_write);
-            num_write
-        }
+        buf.set_offset(buf_offset + num_write);
+
+        Ok(num_write)
     }
 
-    /// Seek into the file to the given `offset`.
-    pub fn seek(&mut self, mut offset: usize) {
-        // TODO: optimizations
+    fn add_link() {
+        // TODO
+    }
 
-        if offset >= self.inode.size {
-            // seek past EOF
-            offset = self.inode.size;
-        }
+    fn remove_link() {
+        // TODO
+    }
 
-        let dnode_size = mem::size_of::<Dnode>();
-        let dnode_offset = self.offset % (dnode_size - 4);
-        let last_dnode = self.inode.size - self.offset <= dnode_size - dnode_offset;
-        let dnode_rem = if last_dnode {
-            dnode_size - dnode_offset
-        } else {
-            dnode_size - 4 - dnode_offset
-        };
+    fn grow() {
+        // TODO
+    }
 
-        if (offset > self.offset && offset - self.offset < dnode_rem)
-            || (offset < self.offset && self.offset - offset < dnode_offset)
-        {
-            // in the same dnode
-            self.offset = offset;
-        } else if offset > self.offset {
-            // Seek forwards
-
-            {
-                // lock fs
-                let mut fs = self.ofs.down();
-
-                // get the next dnode
-                let curr_dnode = fs.get_dnode(self.offset_dnode);
-                let next_dnode = curr_dnode.get_next();
-
-                self.offset += dnode_size - 4 - dnode_offset;
-                self.offset_dnode = next_dnode;
-            } // unlock
-
-            self.seek(offset);
-        } else if offset < self.offset {
-            // Seek backwards
-            self.offset = 0;
-            self.offset_dnode = self.inode.data;
-
-            self.seek(offset);
-        }
+    fn change_metadata() {
+        // TODO
     }
+}
 
-    /// Fill the buffer starting at the buffer offset from the file starting at the file offset.
-    /// This reads as much as possible from the file without overflowing the buffer or reading past
-    /// the EOF. This updates both the file and buffer offsets.
-    pub fn read(&mut self, buf: &mut BlockDataBuffer) -> usize {
-        // EOF
-        if self.inode.size == self.offset {
-            return 0;
+impl<B: BlockDevice + 'static> OpenFile for File<B> {
+    /// Fill the buffer starting at the buffer offset from the file starting at `offset`. This
+    /// reads as much as possible from the file without overflowing the buffer or reading past the
+    /// EOF. This updates the buffer offset.
+    fn read(&self, offset: usize, buf: &mut BlockDataBuffer) -> Result<usize, Error<'static>> {
+        // lock the file system
+        let mut fs = self.ofs.down();
+        let inode = self.get(&mut fs)?;
+
+        // EOF (the file may also have been truncated by another handle)
+        if offset >= inode.size {
+            return Ok(0);
         }
 
         // How many bytes can fit in buf?
         let max_bytes = buf.size() - buf.offset();
 
         // How many bytes left in the file?
-        let bytes_left = self.inode.size - self.offset;
+        let bytes_left = inode.size - offset;
 
         // Number of bytes we can read to buf
         let num_read = min(max_bytes, bytes_left);
 
-        let new_offset = buf.offset() + num_read;
-
-        while buf.offset() < new_offset {
-            self.read_part(buf);
+        // full buffer
+        if num_read == 0 {
+            return Ok(0);
         }
 
-        num_read
+        let buf_offset = buf.offset();
+        let data =
+            unsafe { slice::from_raw_parts_mut(buf.get_ptr_mut::<u8>(buf_offset), num_read) };
+
+        // holes in the file are read as zeros
+        fs.read_file(&inode, offset, data);
+
+        buf.set_offset(buf_offset + num_read);
+
+        Ok(num_read)
     }
 
-    /// Write `bytes` bytes from the buffer at the buffer offset to the file at the file offset,
+    /// Write `bytes` bytes from the buffer at the buffer offset to the file at `offset`,
     /// overwriting any existing content at the offset.
-    /// This will increase the length of the file if necessary.  This updates both the file and
-    /// buffer offsets.
-    pub fn write(&mut self, bytes: usize, buf: &mut BlockDataBuffer) {
-        panic!("OFS is read-only until I think about consistency...");
+    /// This will increase the length of the file if necessary.  This updates the buffer offset.
+    /// Returns the number of bytes written.
+    ///
+    /// If the file system runs out of space, the number of bytes written so far is returned, or
+    /// the error if nothing was written.
+    fn write(
+        &self,
+        offset: usize,
+        bytes: usize,
+        buf: &mut BlockDataBuffer,
+    ) -> Result<usize, Error<'static>> {
         let mut written = 0;
         while written < bytes {
-            written += self.write_part(bytes - written, buf);
+            match self.write_part(offset + written, bytes - written, buf) {
+                Ok(num_write) => written += num_write,
+                Err(_) if written > 0 => break,
+                Err(err) => return Err(err),
+            }
         }
+        Ok(written)
     }
 
-    fn get_next_dnode() {
-        // TODO
-    }
-
-    fn get_prev_dnode() {
-        // TODO
-    }
-
-    fn add_link() {
-        // TODO
-    }
-
-    fn remove_link() {
-        // TODO
+    fn size(&self) -> Result<usize, Error<'static>> {
+        let mut fs = self.ofs.down();
+        Ok(self.get(&mut fs)?.size)
     }
 
-    fn grow() {
-        // TODO
-    }
+    /// Return the filename, or an empty name if the file no longer exists
+    fn name(&self) -> String {
+        let mut fs = self.ofs.down();
+        let inode = match self.get(&mut fs) {
+            Ok(inode) => inode,
+            Err(_) => return String::new(),
+        };
 
-    fn change_metadata() {
-        // TODO
-    }
+        let mut name = [0; NAME_MAX];
+        let len = fs.read_name(&inode, &mut name);
 
-    // TODO: how to access metadata?
-    /// Return the filename
-    pub fn get_filename(&self) -> String {
-        self.inode.get_filename()
+        String::from_utf8_lossy(&name[..len]).into_owned()
     }
 }
 
 impl<B: BlockDevice> Drop for File<B> {
-    /// Close the file. Write back the inode
-    fn drop(&mut self) {
-        // TODO: should only write back if opened as writeable
-        // let mut fs = self.ofs.down();
-
-        // let inode_start_offset = fs.get_inode_offset(self.inode_num);
-
-        // let mut tmp = BlockDataBuffer::new(mem::size_of::<Inode>());
-        // unsafe {
-        //    *tmp.get_ptr(0) = self.inode.clone();
-        // }
-
-        // fs.device.write_fully(inode_start_offset, &mut tmp);
-    }
+    /// Close the file.
+    ///
+    /// NOTE: the inode does not need to be written back, since every write commits it.
+    fn drop(&mut self) {}
 }
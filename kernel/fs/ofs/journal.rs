@@ -0,0 +1,256 @@
+//! A write-ahead journal for OFS
+//!
+//! All changes to an OFS volume are made in transactions. The blocks written during a transaction
+//! are buffered in memory until it is committed. Committing first writes the blocks to the journal
+//! area at the end of the volume, and then writes the journal header, which records where each
+//! block belongs. Writing the header is the commit point: afterwards, the blocks are copied to
+//! their real locations and the header is cleared again.
+//!
+//! If the system crashes before the header is written, nothing of the transaction reached its
+//! real location. If it crashes after, the transaction is replayed the next time the volume is
+//! mounted. Either way, the bitmaps, inodes, and dnode indices on disk are always consistent.
+//!
+//! ```text
+//! journal block 0      [ magic | number of blocks n | target 0 | ... | target n-1 ]
+//! journal block 1..n   [ new contents of target 0 ] ... [ new contents of target n-1 ]
+//! ```
+//!
+//! A volume without a journal (i.e. made by an older mkfs) is read-only, and so is a volume whose
+//! journal header is corrupt, since the transaction in it cannot be replayed safely. A transaction
+//! that writes more blocks than the journal holds fails with `ENOSPC` when it is committed.
+
+use alloc::Vec;
+
+use core::cmp::min;
+use core::mem;
+use core::ptr::copy;
+
+use super::super::error::Error;
+use errno::Errno;
+use io::block::{BlockDataBuffer, BlockDevice};
+use ofs_core::{self, JOURNAL_MAGIC};
+
+/// A block device whose writes are buffered until they are committed through the journal
+pub struct Journal<B: BlockDevice> {
+    /// The underlying device
+    device: B,
+
+    /// The block number of the journal header
+    start: usize,
+
+    /// The number of blocks in the journal area, including the header
+    size: usize,
+
+    /// The blocks written by the current transaction: (block number, new contents)
+    pending: Vec<(usize, BlockDataBuffer)>,
+
+    /// Did the current transaction write more blocks than fit in the journal?
+    overflowed: bool,
+}
+
+impl<B: BlockDevice> Journal<B> {
+    /// Wrap the given device. There is no journal area until `open` is called.
+    pub fn new(device: B) -> Journal<B> {
+        Journal {
+            device: device,
+            start: 0,
+            size: 0,
+            pending: Vec::new(),
+            overflowed: false,
+        }
+    }
+
+    /// Use the `size` blocks starting at block `start` as the journal area, and replay the
+    /// transaction recorded there if it was committed but not completed. Returns the number of
+    /// blocks replayed.
+    ///
+    /// If the header is corrupt, nothing is replayed, the journal is not used, and so the volume
+    /// is read-only.
+    pub fn open(&mut self, start: usize, size: usize) -> Result<usize, Error<'static>> {
+        self.start = start;
+        self.size = size;
+
+        if !self.is_writable() {
+            return Ok(0);
+        }
+
+        let header = self.read_header();
+        let count = unsafe { *header.get_ptr::<usize>(1) };
+
+        if unsafe { *header.get_ptr::<usize>(0) } != JOURNAL_MAGIC || count == 0 {
+            return Ok(0);
+        }
+
+        // the metadata block is never part of a transaction, and neither is the journal
+        let valid = count <= self.capacity()
+            && (0..count).all(|i| {
+                let block_num = unsafe { *header.get_ptr::<usize>(2 + i) };
+                block_num != 0 && block_num < self.start
+            });
+
+        if !valid {
+            self.size = 0;
+            return Err(Error::new(Errno::EIO, "Corrupt OFS journal"));
+        }
+
+        self.checkpoint(&header, count);
+
+        Ok(count)
+    }
+
+    /// Returns true if the volume has a journal, so it can be written
+    pub fn is_writable(&self) -> bool {
+        self.size > 1
+    }
+
+    /// The maximum number of blocks a single transaction can write
+    pub fn capacity(&self) -> usize {
+        let header_words = self.device.get_block_size() / mem::size_of::<usize>();
+        min(self.size - 1, header_words - 2)
+    }
+
+    /// Atomically write all blocks written since the last commit to their real locations. If they
+    /// do not fit in the journal, the transaction is aborted instead.
+    pub fn commit(&mut self) -> Result<(), Error<'static>> {
+        if self.overflowed {
+            self.abort();
+            return Err(Error::new(
+                Errno::ENOSPC,
+                "OFS transaction does not fit in the journal",
+            ));
+        }
+
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let blk_size = self.device.get_block_size();
+        let mut header = BlockDataBuffer::new(blk_size);
+
+        unsafe {
+            *header.get_ptr_mut::<usize>(0) = JOURNAL_MAGIC;
+            *header.get_ptr_mut::<usize>(1) = self.pending.len();
+        }
+
+        // write ahead
+        for (i, &(block_num, ref contents)) in self.pending.iter().enumerate() {
+            self.device.write_block(self.start + 1 + i, contents);
+            unsafe {
+                *header.get_ptr_mut::<usize>(2 + i) = block_num;
+            }
+        }
+
+        // commit
+        self.device.write_block(self.start, &header);
+
+        // write the blocks in place
+        for (block_num, contents) in self.pending.drain(..) {
+            self.device.write_block(block_num, &contents);
+        }
+
+        self.clear_header();
+
+        Ok(())
+    }
+
+    /// Drop all blocks written since the last commit.
+    pub fn abort(&mut self) {
+        self.pending.clear();
+        self.overflowed = false;
+    }
+
+    /// Read the journal header
+    fn read_header(&mut self) -> BlockDataBuffer {
+        let mut header = BlockDataBuffer::new(self.device.get_block_size());
+        self.device.read_block(self.start, &mut header);
+        header
+    }
+
+    /// Copy the `count` blocks in the journal to the targets recorded in the given header, and
+    /// clear the header. The targets must have been checked by `open`.
+    fn checkpoint(&mut self, header: &BlockDataBuffer, count: usize) {
+        let mut contents = BlockDataBuffer::new(self.device.get_block_size());
+
+        for i in 0..count {
+            let block_num = unsafe { *header.get_ptr::<usize>(2 + i) };
+            self.device.read_block(self.start + 1 + i, &mut contents);
+            self.device.write_block(block_num, &contents);
+        }
+
+        self.clear_header();
+    }
+
+    /// Mark the journal empty because the last transaction was completed
+    fn clear_header(&mut self) {
+        let mut header = BlockDataBuffer::new(self.device.get_block_size());
+
+        unsafe {
+            *header.get_ptr_mut::<usize>(0) = JOURNAL_MAGIC;
+            *header.get_ptr_mut::<usize>(1) = 0;
+        }
+
+        self.device.write_block(self.start, &header);
+    }
+}
+
+impl<B: BlockDevice> ofs_core::BlockDevice for Journal<B> {
+    /// Read the given block, including changes made by the current transaction
+    fn read_block(&mut self, block_num: usize, buf: &mut [u8]) {
+        let pending = self
+            .pending
+            .iter()
+            .position(|pending| pending.0 == block_num);
+
+        match pending {
+            Some(i) => unsafe {
+                copy(
+                    self.pending[i].1.get_ptr::<u8>(0),
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                );
+            },
+            None => {
+                let mut contents = BlockDataBuffer::new(self.device.get_block_size());
+                self.device.read_block(block_num, &mut contents);
+                unsafe {
+                    copy(contents.get_ptr::<u8>(0), buf.as_mut_ptr(), buf.len());
+                }
+            }
+        }
+    }
+
+    /// Add the given block to the current transaction. Nothing is written to the device until the
+    /// transaction is committed. If the transaction does not fit in the journal, the block is
+    /// dropped, and the transaction fails when it is committed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the volume is read-only.
+    fn write_block(&mut self, block_num: usize, buf: &[u8]) {
+        if !self.is_writable() {
+            panic!("Attempt to write to a read-only OFS volume");
+        }
+
+        let blk_size = self.device.get_block_size();
+        let mut contents = BlockDataBuffer::new(blk_size);
+        unsafe {
+            copy(buf.as_ptr(), contents.get_ptr_mut::<u8>(0), blk_size);
+        }
+
+        if let Some(pending) = self
+            .pending
+            .iter_mut()
+            .find(|pending| pending.0 == block_num)
+        {
+            pending.1 = contents;
+            return;
+        }
+
+        if self.pending.len() == self.capacity() {
+            self.overflowed = true;
+            return;
+        }
+
+        self.pending.push((block_num, contents));
+    }
+}
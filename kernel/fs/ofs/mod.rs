@@ -1,21 +1,36 @@
 #![allow(warnings)] // TODO
 
 //! A module for os1 FS (OFS)
+//!
+//! Directories are inodes with `FLAG_DIR` set, and each inode counts the links to it in `nlink`.
+//! Linking files that are not directories with `link` and `unlink` is an OFS extension. Symbolic
+//! links have `FLAG_SYMLINK` set, and their contents are the path they point to.
+//!
+//! Permissions are checked by the callers (see `fs`), against the metadata reported by `stat`.
+//!
+//! New regular files are made by `open` with `O_CREAT` (see `fs::create`). A file that nothing
+//! links to after a crash is freed the next time the volume is mounted.
 
 pub mod file;
 
 mod hw;
 mod internals;
+mod journal;
 
 use alloc::arc::Arc;
-
-use core::mem;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::Vec;
 
 use self::file::File;
 use self::hw::*;
 use self::internals::*;
+use self::journal::Journal;
 use super::error::Error;
-use io::block::{BlockDataBuffer, BlockDevice};
+use super::vfs::{self, FileSystem, OpenFile, Stat};
+use errno::Errno;
+use io::block::BlockDevice;
+use ofs_core::{self, Volume};
 use sync::Semaphore;
 
 /// A safe handle on the file system for all needed operations.
@@ -27,177 +42,567 @@ impl<B: BlockDevice> OFSHandle<B> {
     /// Create a new handle on the fs using the device
     ///
     /// NOTE: we do not have to lock the device now, because ownership transfers to the handle :D
-    pub fn new(mut device: B) -> OFSHandle<B> {
-        let mut buf = BlockDataBuffer::new(device.get_block_size());
+    pub fn new(device: B) -> OFSHandle<B> {
+        // read the metadata sector and check the magic and version
+        let mut ofs = match Volume::open(Journal::new(device)) {
+            Ok(ofs) => ofs,
+            Err(ofs_core::Error::UnsupportedVersion(version)) => panic!(
+                "Unsupported OFS version {}, rebuild the image with mkfs",
+                version
+            ),
+            Err(_) => panic!("This is not an OFS volume!"),
+        };
 
-        device.read_fully(0, 0, &mut buf);
+        // finish the last transaction if the system crashed in the middle of it
+        let journal_block = ofs.journal_block();
+        let num_journal = ofs.meta.num_journal;
+        match ofs.device.open(journal_block, num_journal) {
+            Ok(0) => {}
+            Ok(replayed) => printf!("OFS journal replayed - {} blocks\n", replayed),
+            Err(err) => printf!("{}, mounting read-only\n", err.msg()),
+        }
 
-        // get the metadata sector
-        let meta = unsafe { buf.get_ref::<Metadata>(0).clone() };
+        let mut ofs = OFS::new(ofs);
 
-        // check the magic
-        if &*meta.get_magic_str() != "OFS\x00" {
-            panic!("This is not an OFS volume!");
+        if ofs.device.is_writable() {
+            let freed = free_orphans(&mut ofs);
+            if freed > 0 {
+                printf!("OFS orphans freed - {} files\n", freed);
+            }
+        } else if num_journal < 2 {
+            printf!("OFS volume has no journal, mounting read-only\n");
         }
 
         OFSHandle {
-            fs: Arc::new(Semaphore::new(
-                OFS {
-                    device: device,
-                    meta: meta,
-                },
-                1,
-            )),
+            fs: Arc::new(Semaphore::new(ofs, 1)),
         }
     }
+}
 
-    /// Open the file with the given inode number and return a handle to it.
-    pub fn open_read(&mut self, inode: usize) -> Result<File<B>, Error> {
-        // TODO mark as read
-        // TODO check permissions
-        // TODO check that the file is not already opened as write
+impl<B: BlockDevice + 'static> FileSystem for OFSHandle<B> {
+    fn name(&self) -> &'static str {
+        "ofs"
+    }
 
-        // lock the fs
+    /// Get a handle on the file with the given inode number
+    fn inode(&self, inode: usize) -> Result<Box<vfs::Inode>, Error<'static>> {
         let mut fs = self.fs.down();
 
         if fs.is_free_inode(inode) {
-            Err(Error::new("No such file or directory"))
+            Err(no_such_file())
         } else {
-            let i = fs.get_inode(inode);
-            let d = i.data;
-            // printf!("Open file {:?}: i {}, d {}\n", i.name, inode, d);
-            Ok(File {
+            Ok(Box::new(OFSInode {
                 inode_num: inode,
-                inode: i,
-                offset: 0,
-                offset_dnode: d,
+                generation: fs.generation(inode),
                 ofs: self.fs.clone(),
-            })
+            }))
         }
     }
 
-    /// Open the file with the given inode number and return a handle to it.
-    pub fn open_write(&mut self, inode: usize) -> Result<File<B>, Error> {
-        // TODO mark as write
-        // TODO check permissions
-        // TODO check that the file is not already opened as write
+    /// Create a new file with the given name, permissions, and owner, and return its inode
+    /// number. Nothing links to the file yet.
+    fn create(&self, name: &str, mode: u32, uid: u32, gid: u32) -> Result<usize, Error<'static>> {
+        let mut fs = self.fs.down();
 
-        // lock the fs
+        if !fs.device.is_writable() {
+            return Err(read_only());
+        }
+
+        match new_inode(&mut fs, name, 0, &[], mode, uid, gid) {
+            Ok(inode_num) => {
+                fs.device.commit()?;
+                Ok(inode_num)
+            }
+            Err(err) => {
+                fs.device.abort();
+                Err(err)
+            }
+        }
+    }
+
+    /// Delete file `a`. `a` is the inode number of the file.
+    fn delete(&self, a: usize) -> Result<(), Error<'static>> {
+        // TODO make sure file is not opened at all
         let mut fs = self.fs.down();
 
-        if fs.is_free_inode(inode) {
-            Err(Error::new("No such file or directory"))
+        if !fs.device.is_writable() {
+            return Err(read_only());
+        }
+
+        if fs.is_free_inode(a) {
+            return Err(no_such_file());
+        }
+
+        if a == 0 {
+            return Err(Error::new(Errno::EBUSY, "Cannot delete the root"));
+        }
+
+        let inode = fs.get_inode(a);
+
+        if inode.is_dir() && inode.links.iter().any(|&link| link != 0) {
+            return Err(Error::new(Errno::ENOTEMPTY, "Directory not empty"));
+        }
+
+        // remove links to the file
+        for other in 0..fs.meta.num_inode {
+            if fs.is_free_inode(other) {
+                continue;
+            }
+
+            let mut inode = fs.get_inode(other);
+            if inode.remove_link(a) {
+                fs.write_inode(other, &inode);
+            }
+        }
+
+        free_file(&mut fs, a, &inode);
+
+        fs.device.commit()?;
+        fs.retire(a);
+
+        Ok(())
+    }
+}
+
+/// A handle on a file in OFS
+pub struct OFSInode<B: BlockDevice> {
+    inode_num: usize,
+    generation: usize,
+    ofs: Arc<Semaphore<OFS<B>>>,
+}
+
+impl<B: BlockDevice> OFSInode<B> {
+    /// Get the inode of the file, if it still exists
+    fn get(&self, fs: &mut OFS<B>) -> Result<Inode, Error<'static>> {
+        if !fs.is_current(self.inode_num, self.generation) {
+            Err(no_such_file())
         } else {
-            let i = fs.get_inode(inode);
-            let d = i.data;
-            // printf!("Open file {:?}: i {}, d {}\n", i.name, inode, d);
-            Ok(File {
-                inode_num: inode,
-                inode: i,
-                offset: 0,
-                offset_dnode: d,
-                ofs: self.fs.clone(),
-            })
+            Ok(fs.get_inode(self.inode_num))
+        }
+    }
+
+    /// Create a file with the given name, flags, contents, permissions, and owner, linked from
+    /// this directory, and return its inode number
+    fn add_entry(
+        &self,
+        name: &str,
+        flags: u8,
+        contents: &[u8],
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    ) -> Result<usize, Error<'static>> {
+        let mut fs = self.ofs.down();
+
+        if !fs.device.is_writable() {
+            return Err(read_only());
+        }
+
+        let inode = self.get(&mut fs)?;
+
+        if !inode.is_dir() {
+            return Err(not_a_dir());
+        }
+
+        if name.len() > NAME_MAX {
+            return Err(Error::new(Errno::ENAMETOOLONG, "File name too long"));
+        }
+
+        if fs.find_link(self.inode_num, name.as_bytes()).is_some() {
+            return Err(Error::new(Errno::EEXIST, "File exists"));
+        }
+
+        if !inode.links.contains(&0) {
+            return Err(Error::new(Errno::ENOSPC, "Too many links"));
+        }
+
+        match new_inode(&mut fs, name, flags, contents, mode, uid, gid) {
+            Ok(new) => {
+                fs.add_link(self.inode_num, new);
+                fs.device.commit()?;
+                Ok(new)
+            }
+            Err(err) => {
+                fs.device.abort();
+                Err(err)
+            }
         }
     }
+}
+
+impl<B: BlockDevice + 'static> vfs::Inode for OFSInode<B> {
+    fn number(&self) -> usize {
+        self.inode_num
+    }
+
+    fn stat(&self) -> Result<Stat, Error<'static>> {
+        let inode = self.get(&mut self.ofs.down())?;
+
+        Ok(Stat {
+            inode: self.inode_num,
+            size: inode.size,
+            uid: inode.uid,
+            gid: inode.gid,
+            user_perm: inode.user_perm,
+            group_perm: inode.group_perm,
+            all_perm: inode.all_perm,
+            created: inode.created.0,
+            modified: inode.modified.0,
+            is_dir: inode.is_dir(),
+            is_symlink: inode.is_symlink(),
+            nlink: inode.nlink,
+        })
+    }
+
+    fn name(&self) -> Result<String, Error<'static>> {
+        let mut fs = self.ofs.down();
+        let inode = self.get(&mut fs)?;
+
+        let mut name = [0; NAME_MAX];
+        let len = fs.read_name(&inode, &mut name);
+
+        Ok(String::from_utf8_lossy(&name[..len]).into_owned())
+    }
+
+    fn links(&self) -> Result<Vec<usize>, Error<'static>> {
+        let inode = self.get(&mut self.ofs.down())?;
 
-    /// Create a link (directed edge) from file `a` to file `b`. `a` and `b` are the inode number
-    /// of the files.
-    pub fn link(&mut self, a: usize, b: usize) -> Option<Error> {
-        // TODO implement
-        // TODO check permissions
-        // TODO: make sure that they are not already linked
+        Ok(inode
+            .links
+            .iter()
+            .cloned()
+            .filter(|&link| link != 0)
+            .collect())
+    }
+
+    fn lookup(&self, name: &str) -> Result<usize, Error<'static>> {
+        if name.len() > NAME_MAX {
+            return Err(Error::new(Errno::ENAMETOOLONG, "File name too long"));
+        }
+
+        let mut fs = self.ofs.down();
+        self.get(&mut fs)?;
+
+        fs.find_link(self.inode_num, name.as_bytes())
+            .ok_or(no_such_file())
+    }
+
+    /// Create a link (directed edge) from this file to file `b`. `b` is the inode number of the
+    /// file.
+    fn link(&self, b: usize) -> Result<(), Error<'static>> {
         // TODO: what if file is already opened as write?
-        Some(Error::new(
-            "OFS is read-only until I think about consistency...",
-        ))
+        let mut fs = self.ofs.down();
+
+        if !fs.device.is_writable() {
+            return Err(read_only());
+        }
+
+        let inode = self.get(&mut fs)?;
+
+        if fs.is_free_inode(b) {
+            return Err(no_such_file());
+        }
+
+        // NOTE: 0 marks an unused link, so nothing can link to the root
+        if b == 0 {
+            return Err(Error::new(Errno::EINVAL, "Cannot link to the root"));
+        }
+
+        // a directory only has the parent it was made in
+        if fs.get_inode(b).is_dir() {
+            return Err(Error::new(Errno::EPERM, "Cannot link to a directory"));
+        }
+
+        if inode.is_linked(b) {
+            return Err(Error::new(Errno::EEXIST, "Files are already linked"));
+        }
+
+        // names in a directory are unique
+        if inode.is_dir() {
+            let mut name = [0; NAME_MAX];
+            let linked = fs.get_inode(b);
+            let len = fs.read_name(&linked, &mut name);
+
+            if fs.find_link(self.inode_num, &name[..len]).is_some() {
+                return Err(Error::new(Errno::EEXIST, "File exists"));
+            }
+        }
+
+        if !fs.add_link(self.inode_num, b) {
+            return Err(Error::new(Errno::ENOSPC, "Too many links"));
+        }
+
+        fs.device.commit()?;
+
+        Ok(())
     }
 
-    /// Remove a link (directed edge) from file `a` to file `b`. `a` and `b` are the inode number
-    /// of the files.
-    pub fn unlink(&mut self, a: usize, b: usize) -> Option<Error> {
-        // TODO implement
-        // TODO check permissions
-        // TODO: make sure that they are already linked
+    /// Remove a link (directed edge) from this file to file `b`, and free `b` in the same
+    /// transaction if nothing links to it anymore. `b` is the inode number of the file.
+    fn unlink(&self, b: usize) -> Result<(), Error<'static>> {
         // TODO: what if file is already opened as write?
-        Some(Error::new(
-            "OFS is read-only until I think about consistency...",
-        ))
+        let mut fs = self.ofs.down();
+
+        if !fs.device.is_writable() {
+            return Err(read_only());
+        }
+
+        let inode = self.get(&mut fs)?;
+
+        if !inode.is_linked(b) {
+            return Err(Error::new(Errno::ENOENT, "Files are not linked"));
+        }
+
+        if fs.get_inode(b).is_dir() {
+            return Err(Error::new(Errno::EISDIR, "Is a directory"));
+        }
+
+        fs.remove_link(self.inode_num, b);
+
+        let linked = fs.get_inode(b);
+        let freed = linked.nlink == 0;
+        if freed {
+            free_file(&mut fs, b, &linked);
+        }
+
+        fs.device.commit()?;
+        if freed {
+            fs.retire(b);
+        }
+
+        Ok(())
     }
 
-    /// Return metadata for the file with inode `a` or None if the file does not exist
-    pub fn stat(&self, a: usize) -> Option<Inode> {
-        // TODO: check permissions
-        let mut fs = self.fs.down();
-        if !fs.is_free_inode(a) {
-            Some(fs.get_inode(a))
-        } else {
-            None
+    /// Create an empty directory linked from this directory and return its inode number
+    fn mkdir(&self, name: &str, uid: u32, gid: u32) -> Result<usize, Error<'static>> {
+        // directories may be listed by everyone
+        self.add_entry(name, FLAG_DIR, &[], 0o755, uid, gid)
+    }
+
+    /// Remove the empty directory with the given name linked from this directory
+    fn rmdir(&self, name: &str) -> Result<(), Error<'static>> {
+        let mut fs = self.ofs.down();
+
+        if !fs.device.is_writable() {
+            return Err(read_only());
         }
+
+        if !self.get(&mut fs)?.is_dir() {
+            return Err(not_a_dir());
+        }
+
+        let dir_num = fs
+            .find_link(self.inode_num, name.as_bytes())
+            .ok_or(no_such_file())?;
+        let dir = fs.get_inode(dir_num);
+
+        if !dir.is_dir() {
+            return Err(not_a_dir());
+        }
+
+        if dir.links.iter().any(|&link| link != 0) {
+            return Err(Error::new(Errno::ENOTEMPTY, "Directory not empty"));
+        }
+
+        fs.remove_link(self.inode_num, dir_num);
+        fs.free_dnodes(&dir);
+        fs.free_inode(dir_num);
+        fs.device.commit()?;
+        fs.retire(dir_num);
+
+        Ok(())
     }
 
-    /// Create a new file and a link from `a` to it. `a` is the inode number of the file. Return
-    /// the inode number of the new file.
-    pub fn new_file(&mut self) -> Result<usize, Error> {
-        // TODO: check permissions
-        // TODO: check that file does not already exist
-        // TODO: take some metadata as input (e.g. name, permissions)
+    /// Create a symbolic link to `target` linked from this directory and return its inode number
+    fn symlink(
+        &self,
+        name: &str,
+        target: &str,
+        uid: u32,
+        gid: u32,
+    ) -> Result<usize, Error<'static>> {
+        // the permissions of a symbolic link are those of the file it points to
+        self.add_entry(name, FLAG_SYMLINK, target.as_bytes(), 0o777, uid, gid)
+    }
 
-        return Err(Error::new(
-            "OFS is read-only until I think about consistency...",
-        ));
+    fn readlink(&self) -> Result<String, Error<'static>> {
+        let mut fs = self.ofs.down();
+        let inode = self.get(&mut fs)?;
 
-        let mut fs = self.fs.down();
+        if !inode.is_symlink() {
+            return Err(Error::new(Errno::EINVAL, "Not a symbolic link"));
+        }
 
-        let inode_num = fs.alloc_inode();
-        let dnode_num = fs.alloc_dnode();
-        let inode = Inode {
-            name: UNNAMED,
-            uid: 0,
-            gid: 0,
-            user_perm: 7,
-            group_perm: 0,
-            all_perm: 0,
-            flags: 0,
-            size: 0,
-            data: dnode_num,
-            created: OFSDate::now(),
-            modified: OFSDate::now(),
-            links: [0; 22],
-        };
+        let mut target = Vec::new();
+        target.resize(inode.size, 0);
+        fs.read_file(&inode, 0, &mut target);
+
+        Ok(String::from_utf8_lossy(&target).into_owned())
+    }
+
+    fn chmod(&self, user_perm: u8, group_perm: u8, all_perm: u8) -> Result<(), Error<'static>> {
+        let mut fs = self.ofs.down();
 
-        let mut tmp = BlockDataBuffer::new(mem::size_of::<Inode>());
-        unsafe {
-            *tmp.get_ref_mut(0) = inode;
+        if !fs.device.is_writable() {
+            return Err(read_only());
         }
 
-        let inode_blk = fs.inode_num_to_block_num(inode_num);
-        let inode_offset = fs.inode_num_to_block_offset(inode_num);
-        fs.device.write_fully(inode_blk, inode_offset, &mut tmp);
+        let mut inode = self.get(&mut fs)?;
+        inode.user_perm = user_perm;
+        inode.group_perm = group_perm;
+        inode.all_perm = all_perm;
 
-        // TODO: link the file to the the current working file of the creating process
-        // let pwd = current_proc.pwd;
-        // fs.link(pwd, inode_num);
+        fs.write_inode(self.inode_num, &inode);
+        fs.device.commit()?;
 
-        Ok::<usize, Error>(inode_num)
+        Ok(())
     }
 
-    /// Delete file `a`. `a` is the inode number of the file.
-    pub fn delete_file(&mut self, a: usize) -> Option<Error> {
-        // TODO implement
-        // TODO check permissions
-        // TODO make sure file is not opened at all
-        // TODO Remove links
-        // TODO Remove Inode
-        // TODO Remove Dnodes
-        Some(Error::new(
-            "OFS is read-only until I think about consistency...",
-        ))
+    fn chown(&self, uid: u32, gid: u32) -> Result<(), Error<'static>> {
+        let mut fs = self.ofs.down();
+
+        if !fs.device.is_writable() {
+            return Err(read_only());
+        }
+
+        let mut inode = self.get(&mut fs)?;
+        inode.uid = uid;
+        inode.gid = gid;
+
+        fs.write_inode(self.inode_num, &inode);
+        fs.device.commit()?;
+
+        Ok(())
+    }
+
+    /// Open the file and return a handle to it.
+    fn open(&self, writable: bool) -> Result<Box<OpenFile>, Error<'static>> {
+        // TODO check that the file is not already opened as write
+
+        // lock the fs
+        let mut fs = self.ofs.down();
+
+        if writable && !fs.device.is_writable() {
+            return Err(read_only());
+        }
+
+        self.get(&mut fs)?;
+
+        Ok(Box::new(File {
+            inode_num: self.inode_num,
+            generation: self.generation,
+            ofs: self.ofs.clone(),
+        }))
+    }
+}
+
+/// Allocate and write a new inode with the given name, flags, contents, permissions (`mode`), and
+/// owner, and return its number. Nothing links to it yet. The caller must commit or abort the
+/// transaction.
+fn new_inode<B: BlockDevice>(
+    fs: &mut OFS<B>,
+    name: &str,
+    flags: u8,
+    contents: &[u8],
+    mode: u32,
+    uid: u32,
+    gid: u32,
+) -> Result<usize, Error<'static>> {
+    let (user_perm, group_perm, all_perm) = vfs::split_mode(mode);
+
+    let inode_num = fs.alloc_inode()?;
+    let mut inode = Inode {
+        name: UNNAMED,
+        uid: uid,
+        gid: gid,
+        user_perm: user_perm,
+        group_perm: group_perm,
+        all_perm: all_perm,
+        flags: flags,
+        size: 0,
+        data: 0, // no dnodes until the file is written
+        created: now(),
+        modified: now(),
+        links: [0; NUM_LINKS],
+        nlink: 0,
+    };
+
+    // long names take a dnode
+    fs.set_name(&mut inode, name.as_bytes())?;
+    fs.write_file(&mut inode, 0, contents)?;
+    fs.write_inode(inode_num, &inode);
+
+    Ok(inode_num)
+}
+
+/// Uncount the links from file `a`, whose inode is `inode`, and free its dnodes and inode. Nothing
+/// may link to the file anymore. The caller must commit or abort the transaction.
+fn free_file<B: BlockDevice>(fs: &mut OFS<B>, a: usize, inode: &Inode) {
+    for &link in inode.links.iter().filter(|&&link| link != 0) {
+        if !fs.is_free_inode(link) {
+            let mut linked = fs.get_inode(link);
+            linked.nlink = linked.nlink.saturating_sub(1);
+            fs.write_inode(link, &linked);
+        }
+    }
+
+    fs.free_dnodes(inode);
+    fs.free_inode(a);
+}
+
+/// Free every file that nothing links to, except the root, and return how many were freed. Files
+/// are only unlinked between `create` and linking them from their directory (see `fs::create`),
+/// which are separate transactions, so these were left behind by a crash.
+fn free_orphans<B: BlockDevice>(fs: &mut OFS<B>) -> usize {
+    let num_inode = fs.meta.num_inode;
+
+    let mut linked = Vec::new();
+    linked.resize(num_inode, false);
+    linked[0] = true;
+
+    for inode in 0..num_inode {
+        if fs.is_free_inode(inode) {
+            continue;
+        }
+
+        for &link in fs.get_inode(inode).links.iter() {
+            if link < num_inode {
+                linked[link] = true;
+            }
+        }
     }
 
-    pub fn get_inode_number(&mut self, path: &str) -> Option<usize> {
-        // TODO: get inode number from absolute file path
-        // TODO: Path object?
-        None
+    let mut freed = 0;
+
+    for inode in 0..num_inode {
+        if linked[inode] || fs.is_free_inode(inode) {
+            continue;
+        }
+
+        // each file is freed in its own transaction, so that it fits in the journal
+        let orphan = fs.get_inode(inode);
+        fs.free_dnodes(&orphan);
+        fs.free_inode(inode);
+
+        if fs.device.commit().is_ok() {
+            freed += 1;
+        }
     }
+
+    freed
+}
+
+/// The error returned when a file does not exist
+fn no_such_file() -> Error<'static> {
+    Error::new(Errno::ENOENT, "No such file or directory")
+}
+
+/// The error returned when a directory is expected
+fn not_a_dir() -> Error<'static> {
+    Error::new(Errno::ENOTDIR, "Not a directory")
+}
+
+/// The error returned when trying to change a volume without a journal
+fn read_only() -> Error<'static> {
+    Error::new(Errno::EROFS, "OFS volume has no journal and is read-only")
 }
@@ -0,0 +1,352 @@
+//! A process file system (procfs), which shows the state of the kernel as files
+//!
+//! The root of a procfs links to these files:
+//!
+//! - `uptime`: the time since boot
+//! - `meminfo`: how much of the kernel heap and of physical memory is in use
+//! - `<pid>`: the status of the process with that PID, for every process in the process table
+//!
+//! Every file is made of `key: value` lines. The contents are generated when the file is opened,
+//! so an open file is a snapshot. Nothing in a procfs can be changed.
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::Vec;
+
+use core::cmp::min;
+use core::fmt::Write;
+use core::slice;
+
+use super::error::Error;
+use super::now;
+use super::vfs::{FileSystem, Inode, OpenFile, Stat};
+use errno::Errno;
+use interrupts::no_interrupts;
+use interrupts::pit::{self, JIFFIES};
+use io::block::BlockDataBuffer;
+use memory::{frame_usage, heap_usage};
+use process::proc_table::PROCESS_TABLE;
+use process::CURRENT_PROCESS;
+
+/// The inode number of the root
+const ROOT_INODE: usize = 0;
+
+/// The inode number of `uptime`
+const UPTIME: usize = 1;
+
+/// The inode number of `meminfo`
+const MEMINFO: usize = 2;
+
+/// The status of the process with PID `pid` has inode number `PID_BASE + pid`
+const PID_BASE: usize = 16;
+
+/// A file system showing the state of the kernel
+pub struct ProcFs;
+
+/// A handle on a file in a procfs
+struct ProcInode {
+    inode_num: usize,
+}
+
+/// An open file in a procfs
+struct ProcFile {
+    name: String,
+    contents: Vec<u8>,
+}
+
+/// Returns true if there is a file with the given inode number
+fn exists(inode: usize) -> bool {
+    match inode {
+        ROOT_INODE | UPTIME | MEMINFO => true,
+        _ if inode >= PID_BASE => unsafe { PROCESS_TABLE.get(inode - PID_BASE).is_some() },
+        _ => false,
+    }
+}
+
+/// Get the name of the file with the given inode number
+fn name(inode: usize) -> String {
+    match inode {
+        ROOT_INODE => String::new(),
+        UPTIME => "uptime".to_string(),
+        MEMINFO => "meminfo".to_string(),
+        _ => (inode - PID_BASE).to_string(),
+    }
+}
+
+/// Generate the contents of the file with the given inode number
+fn contents(inode: usize) -> Result<String, Error<'static>> {
+    match inode {
+        ROOT_INODE => Ok(String::new()),
+        UPTIME => Ok(uptime()),
+        MEMINFO => Ok(meminfo()),
+        _ => status(inode - PID_BASE).ok_or(no_such_file()),
+    }
+}
+
+/// The contents of `uptime`
+fn uptime() -> String {
+    let jiffies = unsafe { JIFFIES };
+    let hz = pit::hz();
+    let (secs, ticks) = pit::clock();
+
+    let mut s = String::new();
+    let _ = write!(s, "jiffies: {}\n", jiffies);
+    let _ = write!(s, "hz:      {}\n", hz);
+    let _ = write!(s, "uptime:  {}.{:03} s\n", secs, ticks * 1000 / hz);
+    s
+}
+
+/// The contents of `meminfo`
+fn meminfo() -> String {
+    let (heap_used, heap_free) = heap_usage();
+    let (frames_used, frames_free) = frame_usage();
+
+    let mut s = String::new();
+    let _ = write!(s, "heap used:   {} B\n", heap_used);
+    let _ = write!(s, "heap free:   {} B\n", heap_free);
+    let _ = write!(s, "frames used: {}\n", frames_used);
+    let _ = write!(s, "frames free: {}\n", frames_free);
+    s
+}
+
+/// The contents of the status file of the process with the given PID, if there is one
+fn status(pid: usize) -> Option<String> {
+    // NOTE: interrupts are off so that the process is not reaped while we look at it
+    no_interrupts(|| unsafe {
+        let process = &*PROCESS_TABLE.get(pid)?;
+
+        // the process reading its own status has interrupts disabled once more than usual
+        let disable_cnt = if pid == (*CURRENT_PROCESS).get_pid() {
+            process.disable_cnt - 1
+        } else {
+            process.disable_cnt
+        };
+
+        let (resident, swapped) = process.addr_space.usage();
+
+        let mut s = String::new();
+        let _ = write!(s, "pid:         {}\n", pid);
+        let _ = write!(s, "name:        {}\n", process.get_name());
+        let _ = write!(s, "state:       {:?}\n", process.get_state());
+        let _ = write!(
+            s,
+            "cwf:         {}:{}\n",
+            process.cwf.mount, process.cwf.inode
+        );
+        let _ = write!(
+            s,
+            "uid:         {} (effective {})\n",
+            process.creds.uid, process.creds.euid
+        );
+        let _ = write!(
+            s,
+            "gid:         {} (effective {})\n",
+            process.creds.gid, process.creds.egid
+        );
+        let _ = write!(s, "disable_cnt: {}\n", disable_cnt);
+        let _ = write!(
+            s,
+            "pages:       {} resident, {} swapped\n",
+            resident, swapped
+        );
+        Some(s)
+    })
+}
+
+/// The error returned when a file does not exist
+fn no_such_file() -> Error<'static> {
+    Error::new(Errno::ENOENT, "No such file or directory")
+}
+
+/// The error returned when trying to change anything
+fn read_only() -> Error<'static> {
+    Error::new(Errno::EROFS, "procfs is read-only")
+}
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn inode(&self, inode: usize) -> Result<Box<Inode>, Error<'static>> {
+        if exists(inode) {
+            Ok(Box::new(ProcInode { inode_num: inode }))
+        } else {
+            Err(no_such_file())
+        }
+    }
+
+    fn create(
+        &self,
+        _name: &str,
+        _mode: u32,
+        _uid: u32,
+        _gid: u32,
+    ) -> Result<usize, Error<'static>> {
+        Err(read_only())
+    }
+
+    fn delete(&self, _inode: usize) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+}
+
+impl Inode for ProcInode {
+    fn number(&self) -> usize {
+        self.inode_num
+    }
+
+    fn stat(&self) -> Result<Stat, Error<'static>> {
+        let size = contents(self.inode_num)?.len();
+
+        // everyone may read (and list the root)
+        let perm = if self.inode_num == ROOT_INODE { 5 } else { 4 };
+
+        Ok(Stat {
+            inode: self.inode_num,
+            size: size,
+            uid: 0,
+            gid: 0,
+            user_perm: perm,
+            group_perm: perm,
+            all_perm: perm,
+            created: now(),
+            modified: now(),
+            is_dir: self.inode_num == ROOT_INODE,
+            is_symlink: false,
+            // only the root links to the other files
+            nlink: if self.inode_num == ROOT_INODE { 0 } else { 1 },
+        })
+    }
+
+    fn name(&self) -> Result<String, Error<'static>> {
+        if exists(self.inode_num) {
+            Ok(name(self.inode_num))
+        } else {
+            Err(no_such_file())
+        }
+    }
+
+    fn links(&self) -> Result<Vec<usize>, Error<'static>> {
+        if self.inode_num != ROOT_INODE {
+            return Ok(Vec::new());
+        }
+
+        let mut links = Vec::new();
+        links.push(UPTIME);
+        links.push(MEMINFO);
+        links.extend(
+            unsafe { PROCESS_TABLE.pids() }
+                .iter()
+                .map(|pid| PID_BASE + pid),
+        );
+        Ok(links)
+    }
+
+    fn lookup(&self, name: &str) -> Result<usize, Error<'static>> {
+        if self.inode_num != ROOT_INODE {
+            return Err(no_such_file());
+        }
+
+        let inode = match name {
+            "uptime" => UPTIME,
+            "meminfo" => MEMINFO,
+            _ => match name.parse::<usize>() {
+                Ok(pid) => pid.checked_add(PID_BASE).ok_or(no_such_file())?,
+                Err(_) => return Err(no_such_file()),
+            },
+        };
+
+        if exists(inode) {
+            Ok(inode)
+        } else {
+            Err(no_such_file())
+        }
+    }
+
+    fn link(&self, _other: usize) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+
+    fn unlink(&self, _other: usize) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+
+    fn mkdir(&self, _name: &str, _uid: u32, _gid: u32) -> Result<usize, Error<'static>> {
+        Err(read_only())
+    }
+
+    fn rmdir(&self, _name: &str) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+
+    fn symlink(
+        &self,
+        _name: &str,
+        _target: &str,
+        _uid: u32,
+        _gid: u32,
+    ) -> Result<usize, Error<'static>> {
+        Err(read_only())
+    }
+
+    fn readlink(&self) -> Result<String, Error<'static>> {
+        Err(Error::new(Errno::EINVAL, "Not a symbolic link"))
+    }
+
+    fn chmod(&self, _user: u8, _group: u8, _all: u8) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+
+    fn chown(&self, _uid: u32, _gid: u32) -> Result<(), Error<'static>> {
+        Err(read_only())
+    }
+
+    fn open(&self, writable: bool) -> Result<Box<OpenFile>, Error<'static>> {
+        if writable {
+            return Err(read_only());
+        }
+
+        Ok(Box::new(ProcFile {
+            name: name(self.inode_num),
+            contents: contents(self.inode_num)?.into_bytes(),
+        }))
+    }
+}
+
+impl OpenFile for ProcFile {
+    fn read(&self, offset: usize, buf: &mut BlockDataBuffer) -> Result<usize, Error<'static>> {
+        let offset = min(offset, self.contents.len());
+        let num_read = min(buf.size() - buf.offset(), self.contents.len() - offset);
+
+        if num_read == 0 {
+            return Ok(0);
+        }
+
+        let buf_offset = buf.offset();
+        let dest =
+            unsafe { slice::from_raw_parts_mut(buf.get_ptr_mut::<u8>(buf_offset), num_read) };
+        dest.copy_from_slice(&self.contents[offset..offset + num_read]);
+
+        buf.set_offset(buf_offset + num_read);
+
+        Ok(num_read)
+    }
+
+    fn write(
+        &self,
+        _offset: usize,
+        _bytes: usize,
+        _buf: &mut BlockDataBuffer,
+    ) -> Result<usize, Error<'static>> {
+        Err(read_only())
+    }
+
+    fn size(&self) -> Result<usize, Error<'static>> {
+        Ok(self.contents.len())
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
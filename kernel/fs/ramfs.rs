@@ -0,0 +1,578 @@
+//! An in-memory file system (ramfs) for scratch files
+//!
+//! Files live on the kernel heap and disappear when the system is shut down. Otherwise, a ramfs
+//! behaves like OFS: files are linked to each other by directed edges, the root is inode 0, and
+//! directories are made with `mkdir`. The contents of a symbolic link are the path it points to.
+//!
+//! The inode of a deleted file is reused by the next file created, so every file has a generation
+//! number, and handles fail with `ENOENT` once the file they were made for is gone.
+//!
+//! A ramfs holds at most `limit` bytes (counting file contents, names, and a fixed overhead per
+//! file), and it never lets the kernel heap drop below `HEAP_RESERVE` free bytes, so filling it
+//! fails with `ENOSPC` instead of taking down the kernel.
+
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::Vec;
+
+use core::cmp::min;
+use core::mem;
+use core::slice;
+
+use super::error::Error;
+use super::now;
+use super::vfs::{split_mode, FileSystem, Inode, OpenFile, Stat, NAME_MAX};
+use errno::Errno;
+use io::block::BlockDataBuffer;
+use memory::heap_usage;
+use sync::Semaphore;
+
+/// The number of bytes of the kernel heap that a ramfs always leaves free
+const HEAP_RESERVE: usize = 256 << 10;
+
+/// A file in a ramfs
+struct RamFile {
+    name: String,
+    uid: u32,
+    gid: u32,
+    user_perm: u8,
+    group_perm: u8,
+    all_perm: u8,
+    created: u32,
+    modified: u32,
+    dir: bool,
+    symlink: bool,
+    links: Vec<usize>,
+    data: Vec<u8>,
+
+    /// Tells this file apart from other files that had the same inode number
+    generation: usize,
+}
+
+/// The files of a ramfs, indexed by inode number. `None` marks a free inode.
+struct RamFiles {
+    files: Vec<Option<RamFile>>,
+
+    /// The generation of the next file created
+    next_generation: usize,
+
+    /// The maximum number of bytes the files may take up
+    limit: usize,
+}
+
+/// A heap-backed file system
+pub struct RamFs {
+    fs: Arc<Semaphore<RamFiles>>,
+}
+
+/// A handle on a file in a ramfs
+struct RamInode {
+    inode_num: usize,
+    generation: usize,
+    fs: Arc<Semaphore<RamFiles>>,
+}
+
+/// An open file in a ramfs
+struct RamOpenFile {
+    inode_num: usize,
+    generation: usize,
+    fs: Arc<Semaphore<RamFiles>>,
+}
+
+impl RamFs {
+    /// Create a new ramfs that holds at most `limit` bytes. It only has an empty root.
+    pub fn new(limit: usize) -> RamFs {
+        let mut files = RamFiles {
+            files: Vec::new(),
+            next_generation: 1,
+            limit: limit,
+        };
+
+        // everyone may use the root
+        let root = RamFile::new(String::new(), 0o777, true);
+        files.files.push(Some(root));
+
+        RamFs {
+            fs: Arc::new(Semaphore::new(files, 1)),
+        }
+    }
+}
+
+impl RamFile {
+    fn new(name: String, mode: u32, dir: bool) -> RamFile {
+        let (user_perm, group_perm, all_perm) = split_mode(mode);
+
+        RamFile {
+            name: name,
+            uid: 0,
+            gid: 0,
+            user_perm: user_perm,
+            group_perm: group_perm,
+            all_perm: all_perm,
+            created: now(),
+            modified: now(),
+            dir: dir,
+            symlink: false,
+            links: Vec::new(),
+            data: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    /// The number of bytes counted against the limit of the ramfs for this file
+    fn footprint(&self) -> usize {
+        mem::size_of::<RamFile>()
+            + self.name.len()
+            + self.links.capacity() * mem::size_of::<usize>()
+            + self.data.capacity()
+    }
+}
+
+impl RamFiles {
+    /// Get the file with the given inode number, if it exists
+    fn get(&mut self, inode: usize) -> Result<&mut RamFile, Error<'static>> {
+        match self.files.get_mut(inode) {
+            Some(&mut Some(ref mut file)) => Ok(file),
+            _ => Err(Error::new(Errno::ENOENT, "No such file or directory")),
+        }
+    }
+
+    /// Get the file with the given inode number, if it is still the file of the given generation,
+    /// i.e. the one a handle was made for
+    fn get_handle(
+        &mut self,
+        inode: usize,
+        generation: usize,
+    ) -> Result<&mut RamFile, Error<'static>> {
+        let file = self.get(inode)?;
+
+        if file.generation == generation {
+            Ok(file)
+        } else {
+            Err(Error::new(Errno::ENOENT, "No such file or directory"))
+        }
+    }
+
+    /// Find the file with the given name linked from the file with the given inode number
+    fn find_link(&mut self, inode: usize, name: &str) -> Result<usize, Error<'static>> {
+        let links = self.get(inode)?.links.clone();
+
+        for link in links {
+            match self.get(link) {
+                Ok(ref file) if file.name == name => return Ok(link),
+                _ => {}
+            }
+        }
+
+        Err(Error::new(Errno::ENOENT, "No such file or directory"))
+    }
+
+    /// Add a file with the given name, and return its inode number. Nothing links to it yet.
+    fn add(&mut self, name: &str, mode: u32, dir: bool) -> Result<usize, Error<'static>> {
+        if name.len() > NAME_MAX {
+            return Err(Error::new(Errno::ENAMETOOLONG, "File name too long"));
+        }
+
+        let mut file = RamFile::new(String::from(name), mode, dir);
+        self.check_space(file.footprint())?;
+
+        file.generation = self.next_generation;
+        self.next_generation += 1;
+
+        // reuse a free inode if there is one
+        let free = self.files.iter().position(|file| file.is_none());
+        match free {
+            Some(inode) => {
+                self.files[inode] = Some(file);
+                Ok(inode)
+            }
+            None => {
+                self.files.push(Some(file));
+                Ok(self.files.len() - 1)
+            }
+        }
+    }
+
+    /// Add a file with the given name, permissions, and owner linked from the directory with the given inode
+    /// number, and return its inode number
+    fn add_entry(
+        &mut self,
+        dir: usize,
+        name: &str,
+        mode: u32,
+        is_dir: bool,
+        uid: u32,
+        gid: u32,
+    ) -> Result<usize, Error<'static>> {
+        if !self.get(dir)?.dir {
+            return Err(Error::new(Errno::ENOTDIR, "Not a directory"));
+        }
+
+        if self.find_link(dir, name).is_ok() {
+            return Err(Error::new(Errno::EEXIST, "File exists"));
+        }
+
+        self.check_space(mem::size_of::<usize>())?;
+        let inode = self.add(name, mode, is_dir)?;
+        self.get(dir)?.links.push(inode);
+
+        let file = self.get(inode)?;
+        file.uid = uid;
+        file.gid = gid;
+
+        Ok(inode)
+    }
+
+    /// The number of files linking to the file with the given inode number
+    fn nlink(&self, inode: usize) -> usize {
+        self.files
+            .iter()
+            .filter_map(|file| file.as_ref())
+            .filter(|file| file.links.contains(&inode))
+            .count()
+    }
+
+    /// The number of bytes the files take up
+    fn used(&self) -> usize {
+        self.files
+            .iter()
+            .filter_map(|file| file.as_ref())
+            .map(|file| file.footprint())
+            .sum()
+    }
+
+    /// Check that the files can take up `bytes` more bytes
+    fn check_space(&self, bytes: usize) -> Result<(), Error<'static>> {
+        let (_, heap_free) = heap_usage();
+
+        if self.used() + bytes > self.limit || heap_free < HEAP_RESERVE + bytes {
+            Err(Error::new(Errno::ENOSPC, "Out of space in ramfs"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl FileSystem for RamFs {
+    fn name(&self) -> &'static str {
+        "ramfs"
+    }
+
+    fn inode(&self, inode: usize) -> Result<Box<Inode>, Error<'static>> {
+        let generation = self.fs.down().get(inode)?.generation;
+
+        Ok(Box::new(RamInode {
+            inode_num: inode,
+            generation: generation,
+            fs: self.fs.clone(),
+        }))
+    }
+
+    fn create(&self, name: &str, mode: u32, uid: u32, gid: u32) -> Result<usize, Error<'static>> {
+        let mut fs = self.fs.down();
+
+        let inode = fs.add(name, mode, false)?;
+        let file = fs.get(inode)?;
+        file.uid = uid;
+        file.gid = gid;
+
+        Ok(inode)
+    }
+
+    fn delete(&self, inode: usize) -> Result<(), Error<'static>> {
+        let mut fs = self.fs.down();
+
+        let file = fs.get(inode)?;
+
+        if file.dir && !file.links.is_empty() {
+            return Err(Error::new(Errno::ENOTEMPTY, "Directory not empty"));
+        }
+
+        if inode == 0 {
+            return Err(Error::new(Errno::EBUSY, "Cannot delete the root"));
+        }
+
+        // remove links to the file
+        for file in fs.files.iter_mut() {
+            if let Some(ref mut file) = *file {
+                file.links.retain(|&link| link != inode);
+            }
+        }
+
+        fs.files[inode] = None;
+
+        Ok(())
+    }
+}
+
+impl Inode for RamInode {
+    fn number(&self) -> usize {
+        self.inode_num
+    }
+
+    fn stat(&self) -> Result<Stat, Error<'static>> {
+        let mut fs = self.fs.down();
+        let nlink = fs.nlink(self.inode_num);
+        let file = fs.get_handle(self.inode_num, self.generation)?;
+
+        Ok(Stat {
+            inode: self.inode_num,
+            size: file.data.len(),
+            uid: file.uid,
+            gid: file.gid,
+            user_perm: file.user_perm,
+            group_perm: file.group_perm,
+            all_perm: file.all_perm,
+            created: file.created,
+            modified: file.modified,
+            is_dir: file.dir,
+            is_symlink: file.symlink,
+            nlink: nlink,
+        })
+    }
+
+    fn name(&self) -> Result<String, Error<'static>> {
+        let mut fs = self.fs.down();
+        let file = fs.get_handle(self.inode_num, self.generation)?;
+
+        Ok(file.name.clone())
+    }
+
+    fn links(&self) -> Result<Vec<usize>, Error<'static>> {
+        let mut fs = self.fs.down();
+        let file = fs.get_handle(self.inode_num, self.generation)?;
+
+        Ok(file.links.clone())
+    }
+
+    fn lookup(&self, name: &str) -> Result<usize, Error<'static>> {
+        let mut fs = self.fs.down();
+
+        fs.get_handle(self.inode_num, self.generation)?;
+        fs.find_link(self.inode_num, name)
+    }
+
+    fn link(&self, other: usize) -> Result<(), Error<'static>> {
+        let mut fs = self.fs.down();
+
+        fs.get_handle(self.inode_num, self.generation)?;
+        let name = fs.get(other)?.name.clone();
+
+        // as in OFS, nothing can link to the root
+        if other == 0 {
+            return Err(Error::new(Errno::EINVAL, "Cannot link to the root"));
+        }
+
+        // a directory only has the parent it was made in
+        if fs.get(other)?.dir {
+            return Err(Error::new(Errno::EPERM, "Cannot link to a directory"));
+        }
+
+        if fs.get(self.inode_num)?.links.contains(&other) {
+            return Err(Error::new(Errno::EEXIST, "Files are already linked"));
+        }
+
+        // names in a directory are unique
+        let is_dir = fs.get(self.inode_num)?.dir;
+        if is_dir && fs.find_link(self.inode_num, &name).is_ok() {
+            return Err(Error::new(Errno::EEXIST, "File exists"));
+        }
+
+        fs.check_space(mem::size_of::<usize>())?;
+        fs.get(self.inode_num)?.links.push(other);
+
+        Ok(())
+    }
+
+    fn unlink(&self, other: usize) -> Result<(), Error<'static>> {
+        let mut fs = self.fs.down();
+
+        let is_dir = fs.get(other).map(|file| file.dir).unwrap_or(false);
+        if is_dir {
+            return Err(Error::new(Errno::EISDIR, "Is a directory"));
+        }
+
+        {
+            let file = fs.get_handle(self.inode_num, self.generation)?;
+
+            let i = file.links.iter().position(|&link| link == other);
+            match i {
+                Some(i) => {
+                    file.links.remove(i);
+                }
+                None => return Err(Error::new(Errno::ENOENT, "Files are not linked")),
+            }
+        }
+
+        // free the file if this was the last link to it
+        if fs.nlink(other) == 0 {
+            fs.files[other] = None;
+        }
+
+        Ok(())
+    }
+
+    fn mkdir(&self, name: &str, uid: u32, gid: u32) -> Result<usize, Error<'static>> {
+        let mut fs = self.fs.down();
+
+        fs.get_handle(self.inode_num, self.generation)?;
+        fs.add_entry(self.inode_num, name, 0o755, true, uid, gid)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), Error<'static>> {
+        let mut fs = self.fs.down();
+
+        if !fs.get_handle(self.inode_num, self.generation)?.dir {
+            return Err(Error::new(Errno::ENOTDIR, "Not a directory"));
+        }
+
+        let dir = fs.find_link(self.inode_num, name)?;
+
+        {
+            let file = fs.get(dir)?;
+
+            if !file.dir {
+                return Err(Error::new(Errno::ENOTDIR, "Not a directory"));
+            }
+
+            if !file.links.is_empty() {
+                return Err(Error::new(Errno::ENOTEMPTY, "Directory not empty"));
+            }
+        }
+
+        fs.get(self.inode_num)?.links.retain(|&link| link != dir);
+        fs.files[dir] = None;
+
+        Ok(())
+    }
+
+    fn symlink(
+        &self,
+        name: &str,
+        target: &str,
+        uid: u32,
+        gid: u32,
+    ) -> Result<usize, Error<'static>> {
+        let mut fs = self.fs.down();
+
+        fs.get_handle(self.inode_num, self.generation)?;
+        fs.check_space(target.len())?;
+        let link = fs.add_entry(self.inode_num, name, 0o777, false, uid, gid)?;
+
+        let file = fs.get(link)?;
+        file.symlink = true;
+        file.data.extend_from_slice(target.as_bytes());
+
+        Ok(link)
+    }
+
+    fn readlink(&self) -> Result<String, Error<'static>> {
+        let mut fs = self.fs.down();
+        let file = fs.get_handle(self.inode_num, self.generation)?;
+
+        if !file.symlink {
+            return Err(Error::new(Errno::EINVAL, "Not a symbolic link"));
+        }
+
+        Ok(String::from_utf8_lossy(&file.data).into_owned())
+    }
+
+    fn chmod(&self, user_perm: u8, group_perm: u8, all_perm: u8) -> Result<(), Error<'static>> {
+        let mut fs = self.fs.down();
+        let file = fs.get_handle(self.inode_num, self.generation)?;
+
+        file.user_perm = user_perm;
+        file.group_perm = group_perm;
+        file.all_perm = all_perm;
+
+        Ok(())
+    }
+
+    fn chown(&self, uid: u32, gid: u32) -> Result<(), Error<'static>> {
+        let mut fs = self.fs.down();
+        let file = fs.get_handle(self.inode_num, self.generation)?;
+
+        file.uid = uid;
+        file.gid = gid;
+
+        Ok(())
+    }
+
+    fn open(&self, _writable: bool) -> Result<Box<OpenFile>, Error<'static>> {
+        self.fs.down().get_handle(self.inode_num, self.generation)?;
+
+        Ok(Box::new(RamOpenFile {
+            inode_num: self.inode_num,
+            generation: self.generation,
+            fs: self.fs.clone(),
+        }))
+    }
+}
+
+impl OpenFile for RamOpenFile {
+    fn read(&self, offset: usize, buf: &mut BlockDataBuffer) -> Result<usize, Error<'static>> {
+        let mut fs = self.fs.down();
+        let data = &fs.get_handle(self.inode_num, self.generation)?.data;
+
+        let offset = min(offset, data.len());
+        let num_read = min(buf.size() - buf.offset(), data.len() - offset);
+
+        if num_read == 0 {
+            return Ok(0);
+        }
+
+        let buf_offset = buf.offset();
+        let dest =
+            unsafe { slice::from_raw_parts_mut(buf.get_ptr_mut::<u8>(buf_offset), num_read) };
+        dest.copy_from_slice(&data[offset..offset + num_read]);
+
+        buf.set_offset(buf_offset + num_read);
+
+        Ok(num_read)
+    }
+
+    fn write(
+        &self,
+        offset: usize,
+        bytes: usize,
+        buf: &mut BlockDataBuffer,
+    ) -> Result<usize, Error<'static>> {
+        let mut fs = self.fs.down();
+
+        let len = fs.get_handle(self.inode_num, self.generation)?.data.len();
+        let end = offset + bytes;
+
+        // only the bytes past the end of the file take up more space
+        if end > len {
+            fs.check_space(end - len)?;
+
+            let data = &mut fs.get_handle(self.inode_num, self.generation)?.data;
+            data.reserve_exact(end - len);
+            data.resize(end, 0);
+        }
+
+        let file = fs.get_handle(self.inode_num, self.generation)?;
+
+        let buf_offset = buf.offset();
+        let src = unsafe { slice::from_raw_parts(buf.get_ptr::<u8>(buf_offset), bytes) };
+        file.data[offset..end].copy_from_slice(src);
+        file.modified = now();
+
+        buf.set_offset(buf_offset + bytes);
+
+        Ok(bytes)
+    }
+
+    fn size(&self) -> Result<usize, Error<'static>> {
+        let mut fs = self.fs.down();
+        Ok(fs.get_handle(self.inode_num, self.generation)?.data.len())
+    }
+
+    fn name(&self) -> String {
+        match self.fs.down().get_handle(self.inode_num, self.generation) {
+            Ok(file) => file.name.clone(),
+            Err(_) => String::new(),
+        }
+    }
+}
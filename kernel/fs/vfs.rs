@@ -0,0 +1,269 @@
+//! The virtual file system (VFS) layer
+//!
+//! Every file system implements the traits in this module, so that the rest of the kernel does
+//! not need to know which file system a file lives on. A file in the namespace is named by a
+//! `VNode`: the file system it lives on (an index into the mount table) and its inode number on
+//! that file system.
+//!
+//! Files are linked to each other by directed edges, as in OFS. A directory is a file that only
+//! holds links: it is created by `mkdir` and removed by `rmdir`, and it is linked from exactly one
+//! parent, so the directories form a tree. Other links, which make the namespace a general graph,
+//! are an extension made with `link` and `unlink`. A symbolic link is a file holding a path,
+//! which may lead to a file on any file system, and which `fs::lookup` follows.
+//!
+//! Each file has an owner, a group, and `rwx` permissions for the owner, the group, and everyone
+//! else, which are checked against the effective `Credentials` of a process (see `Stat::permits`).
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::Vec;
+
+use super::error::Error;
+use io::block::BlockDataBuffer;
+
+/// The maximum length of a file name in bytes
+pub const NAME_MAX: usize = 255;
+
+/// The root of the root file system
+pub const ROOT: VNode = VNode { mount: 0, inode: 0 };
+
+/// Permission to read a file, or to list a directory
+pub const PERM_READ: u8 = 4;
+
+/// Permission to write a file, or to add and remove links in a directory
+pub const PERM_WRITE: u8 = 2;
+
+/// Permission to run a file, or to look up names in a directory
+pub const PERM_EXEC: u8 = 1;
+
+/// Split `mode` (e.g. `0o640`) into the permissions (`rwx` bits) of the owner, the group, and
+/// everyone else
+pub fn split_mode(mode: u32) -> (u8, u8, u8) {
+    (
+        ((mode >> 6) & 7) as u8,
+        ((mode >> 3) & 7) as u8,
+        (mode & 7) as u8,
+    )
+}
+
+/// The credentials of the superuser, who may do anything
+pub const SUPERUSER: Credentials = Credentials {
+    uid: 0,
+    gid: 0,
+    euid: 0,
+    egid: 0,
+};
+
+/// A file in the namespace
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct VNode {
+    /// The index of the file system in the mount table
+    pub mount: usize,
+
+    /// The inode number of the file on that file system
+    pub inode: usize,
+}
+
+impl VNode {
+    pub fn new(mount: usize, inode: usize) -> VNode {
+        VNode {
+            mount: mount,
+            inode: inode,
+        }
+    }
+}
+
+/// The users and groups a process acts as. The real user and group are the ones who started the
+/// process, and the effective ones are used to check permissions.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Credentials {
+    /// The real user
+    pub uid: u32,
+
+    /// The real group
+    pub gid: u32,
+
+    /// The effective user
+    pub euid: u32,
+
+    /// The effective group
+    pub egid: u32,
+}
+
+/// The metadata of a file, in a form every file system can report
+#[derive(Clone)]
+pub struct Stat {
+    /// The inode number of the file
+    pub inode: usize,
+
+    /// The size of the file in bytes
+    pub size: usize,
+
+    /// The user that owns the file
+    pub uid: u32,
+
+    /// The group that owns the file
+    pub gid: u32,
+
+    /// The permissions (`rwx` bits) of the owner, the group, and everyone else
+    pub user_perm: u8,
+    pub group_perm: u8,
+    pub all_perm: u8,
+
+    /// When the file was created, in seconds since the Unix epoch
+    pub created: u32,
+
+    /// When the file was last modified, in seconds since the Unix epoch
+    pub modified: u32,
+
+    /// Is the file a directory?
+    pub is_dir: bool,
+
+    /// Is the file a symbolic link?
+    pub is_symlink: bool,
+
+    /// The number of files linking to the file
+    pub nlink: usize,
+}
+
+impl Stat {
+    /// Returns true if a process with the given credentials may use the file in all the ways in
+    /// `want` (`PERM_*` bits). The owner's permissions apply to the owner, the group's to the
+    /// rest of the group, and the others to everyone else. The superuser may do anything, except
+    /// run a file that nobody may run.
+    pub fn permits(&self, creds: &Credentials, want: u8) -> bool {
+        if creds.euid == 0 {
+            let any_exec = (self.user_perm | self.group_perm | self.all_perm) & PERM_EXEC != 0;
+            return want & PERM_EXEC == 0 || self.is_dir || any_exec;
+        }
+
+        let perm = if creds.euid == self.uid {
+            self.user_perm
+        } else if creds.egid == self.gid {
+            self.group_perm
+        } else {
+            self.all_perm
+        };
+
+        perm & want == want
+    }
+}
+
+/// A file linked from a directory, as listed by `fs::readdir`
+#[derive(Clone)]
+pub struct DirEntry {
+    /// The inode number of the file
+    pub inode: usize,
+
+    /// The name of the file
+    pub name: String,
+
+    /// Is the file a directory?
+    pub is_dir: bool,
+
+    /// Is the file a symbolic link?
+    pub is_symlink: bool,
+}
+
+/// A file system that can be mounted. The root of every file system is inode 0.
+///
+/// File systems are shared by every process, so all methods take `&self`, and implementations
+/// must do their own locking.
+pub trait FileSystem {
+    /// The name of the type of file system (e.g. `"ofs"`)
+    fn name(&self) -> &'static str;
+
+    /// Get a handle on the file with the given inode number
+    fn inode(&self, inode: usize) -> Result<Box<Inode>, Error<'static>>;
+
+    /// Create a new empty file with the given name, permissions (`mode`, e.g. `0o640`), and owner,
+    /// and return its inode number. Nothing links to the file yet.
+    fn create(&self, name: &str, mode: u32, uid: u32, gid: u32) -> Result<usize, Error<'static>>;
+
+    /// Delete the file with the given inode number, and remove all links to it. A directory must
+    /// be empty.
+    fn delete(&self, inode: usize) -> Result<(), Error<'static>>;
+}
+
+/// A handle on a file on some file system. The handle does not cache anything, so a file that is
+/// deleted while a handle is held just makes the methods of the handle fail.
+pub trait Inode {
+    /// The inode number of the file
+    fn number(&self) -> usize;
+
+    /// Get the metadata of the file
+    fn stat(&self) -> Result<Stat, Error<'static>>;
+
+    /// Get the name of the file
+    fn name(&self) -> Result<String, Error<'static>>;
+
+    /// Get the inode numbers of the files linked from this file
+    fn links(&self) -> Result<Vec<usize>, Error<'static>>;
+
+    /// Find the file linked from this file with the given name, and return its inode number
+    fn lookup(&self, name: &str) -> Result<usize, Error<'static>>;
+
+    /// Create a link (directed edge) from this file to the file with the given inode number.
+    /// Directories cannot be linked this way.
+    fn link(&self, other: usize) -> Result<(), Error<'static>>;
+
+    /// Remove the link from this file to the file with the given inode number, and delete that
+    /// file if nothing links to it anymore, all at once. Directories cannot be unlinked this way.
+    fn unlink(&self, other: usize) -> Result<(), Error<'static>>;
+
+    /// Create an empty directory with the given name and owner linked from this directory, and
+    /// return its inode number
+    fn mkdir(&self, name: &str, uid: u32, gid: u32) -> Result<usize, Error<'static>>;
+
+    /// Remove the empty directory with the given name linked from this directory
+    fn rmdir(&self, name: &str) -> Result<(), Error<'static>>;
+
+    /// Create a symbolic link with the given name and owner to the path `target`, linked from
+    /// this directory, and return its inode number. The path does not have to lead anywhere.
+    fn symlink(
+        &self,
+        name: &str,
+        target: &str,
+        uid: u32,
+        gid: u32,
+    ) -> Result<usize, Error<'static>>;
+
+    /// Get the path this symbolic link points to
+    fn readlink(&self) -> Result<String, Error<'static>>;
+
+    /// Set the permissions (`rwx` bits) of the owner, the group, and everyone else
+    fn chmod(&self, user_perm: u8, group_perm: u8, all_perm: u8) -> Result<(), Error<'static>>;
+
+    /// Set the owner and group of the file
+    fn chown(&self, uid: u32, gid: u32) -> Result<(), Error<'static>>;
+
+    /// Open the file for reading, or for reading and writing
+    fn open(&self, writable: bool) -> Result<Box<OpenFile>, Error<'static>>;
+}
+
+/// An open file. The offset into the file is kept by the caller and passed to every read and
+/// write, so that one open file can be shared without holding a lock while it blocks (see
+/// `process::fd`). Like an `Inode`, an open file does not cache anything, so the methods fail if
+/// the file is deleted while it is open.
+pub trait OpenFile {
+    /// Fill the buffer starting at the buffer offset from the file starting at `offset`. This
+    /// reads as much as possible from the file without overflowing the buffer or reading past the
+    /// EOF. This updates the buffer offset, and returns the number of bytes read.
+    fn read(&self, offset: usize, buf: &mut BlockDataBuffer) -> Result<usize, Error<'static>>;
+
+    /// Write `bytes` bytes from the buffer at the buffer offset to the file at `offset`,
+    /// overwriting any existing content at the offset. This updates the buffer offset, and
+    /// returns the number of bytes written.
+    fn write(
+        &self,
+        offset: usize,
+        bytes: usize,
+        buf: &mut BlockDataBuffer,
+    ) -> Result<usize, Error<'static>>;
+
+    /// The size of the file in bytes
+    fn size(&self) -> Result<usize, Error<'static>>;
+
+    /// The name of the file
+    fn name(&self) -> String;
+}
@@ -0,0 +1,172 @@
+//! A module for handling CPU exceptions (vectors 0-31)
+//!
+//! Each exception dumps the registers to the debug console. Exceptions caused by user mode code
+//! kill the current process with a signal, and all others panic. Page faults are handled by the
+//! VMM instead (see `vmm_page_fault`), and double faults run on their own task (see `tss`).
+
+use machine::exception_table;
+use process::signal::{SIGBUS, SIGFPE, SIGILL, SIGSEGV, SIGTRAP};
+use process::{self, CURRENT_PROCESS};
+
+use super::idt::add_trap_handler;
+use super::tss::interrupted_task;
+
+/// The names of the exceptions, indexed by vector
+const EXCEPTION_NAMES: [&'static str; 32] = [
+    "divide error",
+    "debug",
+    "non-maskable interrupt",
+    "breakpoint",
+    "overflow",
+    "bound range exceeded",
+    "invalid opcode",
+    "device not available",
+    "double fault",
+    "coprocessor segment overrun",
+    "invalid TSS",
+    "segment not present",
+    "stack-segment fault",
+    "general protection fault",
+    "page fault",
+    "reserved",
+    "x87 floating-point exception",
+    "alignment check",
+    "machine check",
+    "SIMD floating-point exception",
+    "virtualization exception",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "security exception",
+    "reserved",
+];
+
+/// The state saved on the kernel stack by `exception_common`, starting from the lowest address
+#[repr(C)]
+pub struct ExceptionContext {
+    pub cr2: usize,
+    pub ds: usize,
+    pub edi: usize,
+    pub esi: usize,
+    pub ebp: usize,
+    pub ebx: usize,
+    pub edx: usize,
+    pub ecx: usize,
+    pub eax: usize,
+    pub vector: usize,
+    pub error: usize,
+    pub eip: usize,
+    pub cs: usize,
+    pub eflags: usize,
+
+    /// Only valid if the exception happened in user mode
+    pub esp: usize,
+
+    /// Only valid if the exception happened in user mode
+    pub ss: usize,
+}
+
+impl ExceptionContext {
+    /// Returns true if the exception happened in user mode
+    pub fn from_user(&self) -> bool {
+        self.cs & 3 == 3
+    }
+}
+
+/// Register the handlers for all exceptions
+pub fn init() {
+    for vector in 0..32 {
+        // page faults are registered by the VMM, and double faults use a task gate
+        if vector == 8 || vector == 14 {
+            continue;
+        }
+
+        // allow `int3` and `into` from user mode
+        let dpl = if vector == 3 || vector == 4 { 3 } else { 0 };
+
+        add_trap_handler(vector as u8, unsafe { exception_table[vector] }, dpl);
+    }
+}
+
+/// Returns the signal to kill a user process with for the given exception, or `None` if the
+/// exception is not the fault of the process
+fn signal(vector: usize) -> Option<usize> {
+    match vector {
+        0 | 7 | 9 | 16 | 19 => Some(SIGFPE),
+        1 | 3 => Some(SIGTRAP),
+        4 | 5 | 10 | 13 | 20 => Some(SIGSEGV),
+        6 => Some(SIGILL),
+        11 | 12 | 17 => Some(SIGBUS),
+        _ => None,
+    }
+}
+
+/// The Rust-side code of the exception handlers
+#[no_mangle]
+pub unsafe extern "C" fn exception_handler(context: *const ExceptionContext) {
+    let context = &*context;
+    let name = EXCEPTION_NAMES[context.vector];
+
+    bootlog!(
+        "\nException {} ({}), error code 0x{:X}\n",
+        context.vector, name, context.error
+    );
+    bootlog!(
+        "eax 0x{:08X} ebx 0x{:08X} ecx 0x{:08X} edx 0x{:08X}\n",
+        context.eax, context.ebx, context.ecx, context.edx
+    );
+    bootlog!(
+        "esi 0x{:08X} edi 0x{:08X} ebp 0x{:08X} cr2 0x{:08X}\n",
+        context.esi, context.edi, context.ebp, context.cr2
+    );
+    bootlog!(
+        "eip 0x{:08X} cs 0x{:X} ds 0x{:X} eflags 0x{:08X}\n",
+        context.eip, context.cs, context.ds, context.eflags
+    );
+
+    if context.from_user() {
+        bootlog!("user esp 0x{:08X} ss 0x{:X}\n", context.esp, context.ss);
+    }
+
+    match signal(context.vector) {
+        Some(signal) if context.from_user() && !CURRENT_PROCESS.is_null() => {
+            process::kill(signal);
+        }
+        _ => {
+            if CURRENT_PROCESS.is_null() {
+                panic!("{} @ 0x{:X} with no current process", name, context.eip);
+            } else {
+                panic!("{:?} [{} @ 0x{:X}]", *CURRENT_PROCESS, name, context.eip);
+            }
+        }
+    }
+}
+
+/// The Rust-side code of the double fault task. The state of the task that faulted was saved in
+/// its TSS by the CPU.
+#[no_mangle]
+pub unsafe extern "C" fn double_fault_handler(error: usize) {
+    let task = interrupted_task();
+
+    bootlog!("\nException 8 (double fault), error code 0x{:X}\n", error);
+    bootlog!(
+        "eax 0x{:08X} ebx 0x{:08X} ecx 0x{:08X} edx 0x{:08X}\n",
+        task.eax, task.ebx, task.ecx, task.edx
+    );
+    bootlog!(
+        "esi 0x{:08X} edi 0x{:08X} ebp 0x{:08X} esp 0x{:08X}\n",
+        task.esi, task.edi, task.ebp, task.esp
+    );
+    bootlog!(
+        "eip 0x{:08X} cs 0x{:X} ds 0x{:X} ss 0x{:X} eflags 0x{:08X} cr3 0x{:08X}\n",
+        task.eip, task.cs, task.ds, task.ss, task.eflags, task.cr3
+    );
+
+    panic!("Double fault @ 0x{:X}", task.eip);
+}
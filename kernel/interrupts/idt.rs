@@ -73,6 +73,17 @@ pub fn add_interrupt_handler(irq: u8, handler: unsafe extern "C" fn()) {
     }
 }
 
+/// Register a task gate for the given trap number, so that the trap switches to the task with the
+/// given TSS selector
+pub fn add_task_gate(index: u8, tss_selector: u16) {
+    let idx = index as usize;
+    unsafe {
+        idt[idx] = IDTDescr::new();
+        idt[idx].set_selector(tss_selector);
+        idt[idx].set_type_attr(true, 0, false, 0x5);
+    }
+}
+
 /// Register a trap handler for the given trap number with the given privelege level
 pub fn add_trap_handler(index: u8, handler: unsafe extern "C" fn(), dpl: u8) {
     let idx = index as usize;
@@ -1,13 +1,16 @@
 //! This module contains everything needed for interrupts
 
+pub use self::exception::init as exception_init;
+pub use self::exception::{double_fault_handler, exception_handler};
 pub use self::idt::add_trap_handler;
 pub use self::process::{no_interrupts, off, on};
-pub use self::tss::esp0;
 pub use self::tss::init as tss_init;
+pub use self::tss::{double_fault_cr3, esp0};
 
 pub mod pic;
 pub mod pit;
 
+mod exception;
 mod idt;
 mod process;
 mod tss;
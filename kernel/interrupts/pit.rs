@@ -1,5 +1,7 @@
 //! A module for the programmable interrupt timer
 
+use core::ptr;
+
 use super::super::machine::pit_do_init;
 
 /// Max frequency of the PIT
@@ -8,10 +10,16 @@ const FREQ: usize = 1193182;
 /// The frequency of the PIT
 static mut HZ: usize = 0;
 
-/// The number of jiffies passed since boot
-#[allow(dead_code)]
+/// The number of jiffies passed since boot. This wraps around after 2^32 jiffies, so it should
+/// only be used to measure short intervals; use `clock` for the time since boot.
 pub static mut JIFFIES: usize = 0;
 
+/// The number of whole seconds passed since boot
+static mut SECONDS: usize = 0;
+
+/// The number of jiffies passed since the last whole second
+static mut TICKS: usize = 0;
+
 /// Initialize the PIT to the given frequency
 pub fn init(pit_hz: usize) {
     let d = FREQ / pit_hz;
@@ -27,15 +35,38 @@ pub fn init(pit_hz: usize) {
     }
 }
 
-/// Handle a PIT interrupt. Increments `JIFFIES`
+/// Handle a PIT interrupt. Increments `JIFFIES` and the clock
 pub fn handler() {
     unsafe {
-        JIFFIES += 1;
+        JIFFIES = JIFFIES.wrapping_add(1);
+
+        TICKS += 1;
+        if TICKS == HZ {
+            TICKS = 0;
+            SECONDS += 1;
+        }
+    }
+}
+
+/// Get the frequency of the PIT, i.e. the number of jiffies per second
+pub fn hz() -> usize {
+    unsafe { HZ }
+}
+
+/// Get the time since boot, as whole seconds and the jiffies since the last whole second. Unlike
+/// `JIFFIES`, this does not wrap around for over a century.
+pub fn clock() -> (usize, usize) {
+    // the handler may run between the two reads, so read again until the seconds agree
+    loop {
+        let (secs, ticks) = unsafe { (ptr::read_volatile(&SECONDS), ptr::read_volatile(&TICKS)) };
+        if secs == unsafe { ptr::read_volatile(&SECONDS) } {
+            return (secs, ticks);
+        }
     }
 }
 
 /// Calculate the number of seconds since boot
 #[allow(dead_code)]
 pub fn seconds() -> usize {
-    unsafe { JIFFIES / HZ }
+    clock().0
 }
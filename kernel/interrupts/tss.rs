@@ -2,13 +2,27 @@
 
 use core::mem;
 
-use super::super::machine::ltr;
+use super::super::machine::{double_fault, ltr};
+use super::idt::add_task_gate;
 
 static mut TSS_: TSS = TSS::new();
 
+/// The TSS of the double fault task
+static mut DF_TSS: TSS = TSS::new();
+
+/// Size of the double fault task's stack (number of words)
+const DF_STACK_SIZE: usize = 1024;
+
+/// The stack of the double fault task. The double fault may have been caused by a bad kernel stack,
+/// so the handler needs its own.
+static mut DF_STACK: [usize; DF_STACK_SIZE] = [0; DF_STACK_SIZE];
+
 extern "C" {
     static mut tssDescriptor: TSSDescriptor;
+    static mut dfTssDescriptor: TSSDescriptor;
     static tssDS: usize;
+    static dfTssDS: u16;
+    static kernelCodeSeg: u16;
     static kernelDataSeg: u16;
 }
 
@@ -18,17 +32,39 @@ struct TSSDescriptor {
     f1: usize,
 }
 
+/// A 32-bit task state segment. When the CPU switches tasks (e.g. for a double fault), the state
+/// of the old task is saved in its TSS.
+///
+/// NOTE: the segment selector fields are 16 bits, with the upper 16 bits reserved.
 #[allow(dead_code)]
-#[repr(C, packed)]
-struct TSS {
-    prev: usize,
-    esp0: usize,
-    ss0: usize,
-    esp1: usize,
-    ss1: usize,
-    esp2: usize,
-    ss2: usize,
-    unused: [usize; 19],
+#[repr(C)]
+pub struct TSS {
+    pub prev: usize,
+    pub esp0: usize,
+    pub ss0: usize,
+    pub esp1: usize,
+    pub ss1: usize,
+    pub esp2: usize,
+    pub ss2: usize,
+    pub cr3: usize,
+    pub eip: usize,
+    pub eflags: usize,
+    pub eax: usize,
+    pub ecx: usize,
+    pub edx: usize,
+    pub ebx: usize,
+    pub esp: usize,
+    pub ebp: usize,
+    pub esi: usize,
+    pub edi: usize,
+    pub es: usize,
+    pub cs: usize,
+    pub ss: usize,
+    pub ds: usize,
+    pub fs: usize,
+    pub gs: usize,
+    pub ldt: usize,
+    pub iomap: usize,
 }
 
 impl TSSDescriptor {
@@ -78,7 +114,25 @@ impl TSS {
             ss1: 0,
             esp2: 0,
             ss2: 0,
-            unused: [0; 19],
+            cr3: 0,
+            eip: 0,
+            eflags: 0,
+            eax: 0,
+            ecx: 0,
+            edx: 0,
+            ebx: 0,
+            esp: 0,
+            ebp: 0,
+            esi: 0,
+            edi: 0,
+            es: 0,
+            cs: 0,
+            ss: 0,
+            ds: 0,
+            fs: 0,
+            gs: 0,
+            ldt: 0,
+            iomap: 0,
         }
     }
 
@@ -92,6 +146,24 @@ pub fn init() {
         TSS_.ss0 = kernelDataSeg as usize;
         tssDescriptor.set(&TSS_);
         ltr(tssDS);
+
+        // set up the double fault task
+        let stack_top = &DF_STACK as *const [usize; DF_STACK_SIZE] as usize + DF_STACK_SIZE * 4;
+
+        DF_TSS.esp0 = stack_top;
+        DF_TSS.ss0 = kernelDataSeg as usize;
+        DF_TSS.eip = double_fault as usize;
+        DF_TSS.eflags = 0x2; // interrupts off
+        DF_TSS.esp = stack_top;
+        DF_TSS.cs = kernelCodeSeg as usize;
+        DF_TSS.ss = kernelDataSeg as usize;
+        DF_TSS.ds = kernelDataSeg as usize;
+        DF_TSS.es = kernelDataSeg as usize;
+        DF_TSS.fs = kernelDataSeg as usize;
+        DF_TSS.gs = kernelDataSeg as usize;
+        dfTssDescriptor.set(&DF_TSS);
+
+        add_task_gate(8, dfTssDS);
     }
 }
 
@@ -100,3 +172,17 @@ pub fn esp0(v: usize) {
         TSS_.esp0(v);
     }
 }
+
+/// Set the page directory used by the double fault task. It should always be the current one, so
+/// that the page directory is not freed while the task might still use it.
+pub fn double_fault_cr3(page_dir: usize) {
+    unsafe {
+        DF_TSS.cr3 = page_dir;
+    }
+}
+
+/// Get the state of the task interrupted by a double fault, saved by the CPU when it switched to
+/// the double fault task
+pub fn interrupted_task() -> &'static TSS {
+    unsafe { &TSS_ }
+}
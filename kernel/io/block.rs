@@ -125,7 +125,11 @@ pub trait BlockDevice {
 
         // read into the buffer until it is full
         while remaining > 0 {
-            let read = self.read(block_num + so_far / blk_size, offset + so_far, buffer);
+            let read = self.read(
+                block_num + (offset + so_far) / blk_size,
+                (offset + so_far) % blk_size,
+                buffer,
+            );
             remaining -= read;
             so_far += read;
         }
@@ -146,7 +150,11 @@ pub trait BlockDevice {
 
         // read into the buffer until it is full
         while remaining > 0 {
-            let written = self.write(block_num + so_far / blk_size, offset + so_far, buffer);
+            let written = self.write(
+                block_num + (offset + so_far) / blk_size,
+                (offset + so_far) % blk_size,
+                buffer,
+            );
             remaining -= written;
             so_far += written;
         }
@@ -14,10 +14,20 @@ pub const SECTOR_SIZE: usize = 512;
 /// The base I/O port for each controller
 const PORTS: [u16; 2] = [0x1f0, 0x170];
 
-/// An abstraction of an IDE block device
+/// One lock per controller. The drives on a controller share its ports, so only one of them can
+/// be used at a time, even through different `IDE` handles.
+static mut LOCKS: [StaticSemaphore; 2] = [StaticSemaphore::new(1), StaticSemaphore::new(1)];
+
+/// The number of times the status is polled while identifying a drive before giving up on it. A
+/// present drive answers within a few milliseconds, and each poll takes at least 1us.
+const MAX_IDENTIFY_POLLS: usize = 100_000;
+
+/// The drives used by the kernel itself, indexed by drive number (see `claim`)
+static mut CLAIMED: [bool; 4] = [false; 4];
+
+/// An abstraction of an IDE block device. Any number of handles may be created for the same drive.
 pub struct IDE {
     drive: u16,
-    lock: StaticSemaphore,
 }
 
 /// IDE drive status
@@ -36,12 +46,87 @@ enum IDEStatus {
 
 impl IDE {
     pub fn new(drive: u16) -> IDE {
-        IDE {
-            drive: drive,
-            lock: StaticSemaphore::new(1),
+        IDE { drive: drive }
+    }
+
+    /// Mark the drive as used by the kernel itself, e.g. for the root file system or swap. The
+    /// kernel keeps state about what is on such a drive, so nothing else may write to it.
+    pub fn claim(&self) {
+        unsafe {
+            CLAIMED[self.drive as usize] = true;
         }
     }
 
+    /// Returns true if the drive is used by the kernel itself (see `claim`)
+    pub fn is_claimed(&self) -> bool {
+        unsafe { CLAIMED[self.drive as usize] }
+    }
+
+    /// Ask the drive how many sectors it has, using the IDENTIFY command. Returns `None` if there
+    /// is no ATA drive there, or if the drive does not answer in time.
+    pub fn sectors(&mut self) -> Option<usize> {
+        let base = self.port();
+        let ch = self.channel();
+
+        self.lock().down();
+
+        let sectors = unsafe {
+            outb(base + 6, 0xA0 | (ch << 4) as u8); // select the drive
+            outb(base + 2, 0);
+            outb(base + 3, 0);
+            outb(base + 4, 0);
+            outb(base + 5, 0);
+            outb(base + 7, 0xEC); // identify
+
+            // 0 => no drive, 0xFF => no controller
+            let status = self.get_status();
+            if status == 0 || status == 0xFF {
+                None
+            } else {
+                let mut polls = 0;
+                while self.is_busy() && polls < MAX_IDENTIFY_POLLS {
+                    polls += 1;
+                }
+
+                // ATAPI and SATA devices set these ports
+                if polls == MAX_IDENTIFY_POLLS || inb(base + 4) != 0 || inb(base + 5) != 0 {
+                    None
+                } else {
+                    let mut polls = 0;
+                    let ready = loop {
+                        let status = self.get_status();
+                        if status & (IDEStatus::ERR as u8) > 0 || polls == MAX_IDENTIFY_POLLS {
+                            break false;
+                        }
+                        if status & (IDEStatus::DRQ as u8) > 0 {
+                            break true;
+                        }
+                        polls += 1;
+                    };
+
+                    if ready {
+                        // read all 256 words of identification data. Words 60 and 61 are the
+                        // number of sectors addressable with LBA28.
+                        let mut sectors = 0;
+                        for i in 0..(SECTOR_SIZE / mem::size_of::<u32>()) {
+                            let dword = inl(base);
+                            if i == 30 {
+                                sectors = dword as usize;
+                            }
+                        }
+                        Some(sectors)
+                    } else {
+                        None
+                    }
+                }
+            }
+        };
+
+        self.lock().up();
+
+        sectors
+    }
+
     // The drive number encodes the controller in bit 1 and the channel in bit 0
 
     /// Get the controller for the drive
@@ -62,6 +147,12 @@ impl IDE {
         PORTS[self.controller() as usize]
     }
 
+    /// Get the lock of the controller of the drive
+    #[inline]
+    fn lock(&self) -> &'static StaticSemaphore {
+        unsafe { &LOCKS[self.controller() as usize] }
+    }
+
     /// Get the status of the drive. This
     /// is a bit mask of status flags.
     #[inline]
@@ -109,7 +200,7 @@ impl BlockDevice for IDE {
         let base = self.port();
         let ch = self.channel();
 
-        self.lock.down();
+        self.lock().down();
 
         // seek
         self.wait_for_drive();
@@ -136,14 +227,14 @@ impl BlockDevice for IDE {
             }
         }
 
-        self.lock.up();
+        self.lock().up();
     }
 
     fn write_block(&mut self, block_num: usize, buffer: &BlockDataBuffer) {
         let base = self.port();
         let ch = self.channel();
 
-        self.lock.down();
+        self.lock().down();
 
         // seek
         self.wait_for_drive();
@@ -170,6 +261,6 @@ impl BlockDevice for IDE {
             }
         }
 
-        self.lock.up();
+        self.lock().up();
     }
 }
@@ -5,6 +5,7 @@ pub use self::nbb::NonBlockingBuffer;
 pub mod block;
 pub mod ide;
 pub mod kbd;
+pub mod rtc;
 pub mod stream;
 
 mod nbb;
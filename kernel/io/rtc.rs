@@ -0,0 +1,202 @@
+//! A module for reading the date and time from the CMOS real-time clock (RTC)
+//!
+//! The RTC keeps the wall-clock time (taken to be UTC) with a resolution of one second. Depending
+//! on status register B, it counts in BCD or binary, and hours in 12- or 24-hour format. It does
+//! not reliably report the century, so two-digit years from 70 are taken to be in the 1900s and
+//! the others in the 2000s, like POSIX does.
+
+use super::super::machine::{inb, outb};
+
+/// Port to select a CMOS register
+const CMOS_ADDR: u16 = 0x70;
+
+/// Port to read the selected CMOS register
+const CMOS_DATA: u16 = 0x71;
+
+// The RTC registers
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+/// Status A: the RTC is updating its registers, which may be inconsistent
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+
+/// Status B: the hours are in 24-hour format
+const HOUR_24: u8 = 1 << 1;
+
+/// Status B: the registers are in binary instead of BCD
+const BINARY: u8 = 1 << 2;
+
+/// 12-hour format: the hour is after noon
+const HOUR_PM: u8 = 1 << 7;
+
+/// The number of times status A is polled before giving up on an update finishing. An update takes
+/// at most about 2ms, and each poll takes at least 1us.
+const MAX_UPDATE_POLLS: usize = 10_000;
+
+/// The number of times the registers are read before giving up on two reads agreeing
+const MAX_READS: usize = 5;
+
+/// The number of seconds in a day
+const SECS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// The Unix epoch, 1970-01-01 00:00 UTC
+pub const EPOCH: DateTime = DateTime {
+    year: 1970,
+    month: 1,
+    day: 1,
+    hour: 0,
+    minute: 0,
+    second: 0,
+};
+
+/// A date and time as read from the RTC
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    /// Returns true if this is a real date, no earlier than the epoch
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && self.month >= 1
+            && self.month <= 12
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// The number of seconds since the Unix epoch at this date, or 0 if the date is not valid
+    pub fn epoch_seconds(&self) -> u32 {
+        if !self.is_valid() {
+            return 0;
+        }
+
+        let mut days = 0;
+        for year in 1970..self.year {
+            days += if is_leap(year) { 366 } else { 365 };
+        }
+        for month in 1..self.month {
+            days += days_in_month(self.year, month);
+        }
+        days += self.day - 1;
+
+        days * SECS_PER_DAY + self.hour * 3600 + self.minute * 60 + self.second
+    }
+}
+
+/// Read the current date and time from the RTC, or `None` if the RTC holds an impossible date or
+/// never gives the same date twice in a row (e.g. there is no RTC)
+pub fn read() -> Option<DateTime> {
+    // The registers are read twice in a row until they agree, so that an update that starts
+    // while reading cannot give a mix of the old and new time.
+    let mut date = read_raw()?;
+    let mut reads = 1;
+    loop {
+        if reads == MAX_READS {
+            return None;
+        }
+
+        let again = read_raw()?;
+        reads += 1;
+        if again == date {
+            break;
+        }
+        date = again;
+    }
+
+    let status_b = read_reg(REG_STATUS_B);
+    let decode = |v: u8| {
+        if status_b & BINARY != 0 {
+            v as u32
+        } else {
+            from_bcd(v) as u32
+        }
+    };
+
+    let hour = if status_b & HOUR_24 != 0 {
+        decode(date[2])
+    } else {
+        let pm = date[2] & HOUR_PM != 0;
+        decode(date[2] & !HOUR_PM) % 12 + if pm { 12 } else { 0 }
+    };
+
+    let year = decode(date[5]);
+
+    let date = DateTime {
+        year: if year >= 70 { 1900 + year } else { 2000 + year },
+        month: decode(date[4]),
+        day: decode(date[3]),
+        hour: hour,
+        minute: decode(date[1]),
+        second: decode(date[0]),
+    };
+
+    if date.is_valid() {
+        Some(date)
+    } else {
+        None
+    }
+}
+
+/// Read the raw date registers once no update is in progress, or `None` if the update never
+/// finishes
+fn read_raw() -> Option<[u8; 6]> {
+    let mut polls = 0;
+    while read_reg(REG_STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        polls += 1;
+        if polls == MAX_UPDATE_POLLS {
+            return None;
+        }
+    }
+
+    Some([
+        read_reg(REG_SECONDS),
+        read_reg(REG_MINUTES),
+        read_reg(REG_HOURS),
+        read_reg(REG_DAY),
+        read_reg(REG_MONTH),
+        read_reg(REG_YEAR),
+    ])
+}
+
+/// Read the given CMOS register
+fn read_reg(reg: u8) -> u8 {
+    unsafe {
+        outb(CMOS_ADDR, reg);
+        inb(CMOS_DATA)
+    }
+}
+
+/// Convert a BCD byte to binary
+fn from_bcd(v: u8) -> u8 {
+    (v >> 4) * 10 + (v & 0xF)
+}
+
+/// Returns true if the given year is a leap year
+fn is_leap(year: u32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// The number of days in the given month (1 to 12) of the given year
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
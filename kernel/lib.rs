@@ -17,6 +17,7 @@
 
 // use libcore
 extern crate alloc;
+extern crate ofs_core;
 extern crate rlibc;
 extern crate smallheap;
 
@@ -28,6 +29,7 @@ mod bare_bones;
 
 mod static_linked_list;
 
+mod errno;
 mod fs;
 mod interrupts;
 mod io;
@@ -35,11 +37,13 @@ mod machine;
 mod memory;
 mod process;
 mod sync;
+mod time;
 mod vga;
 
 // exported functions -- to use in asm functions
 pub use self::bare_bones::*;
 pub use self::interrupts::pic::pic_irq;
+pub use self::interrupts::{double_fault_handler, exception_handler};
 pub use self::memory::vmm_page_fault;
 pub use self::process::context::store_kcontext;
 pub use self::process::{_proc_yield, syscall_handler};
@@ -63,8 +67,9 @@ pub fn kernel_main() {
     // Start initing stuff                             //
     /////////////////////////////////////////////////////
 
-    // init tss, heap, and vm
+    // init tss, exception handlers, heap, and vm
     interrupts::tss_init();
+    interrupts::exception_init();
 
     // make the kernel heap 3MiB starting at 1MiB.
     // make memory data structures take up the next 4MiB.
@@ -76,9 +81,15 @@ pub fn kernel_main() {
     // init interupts
     interrupts::init(1000 /* hz */);
 
+    // wall-clock time
+    time::init();
+
     // filesystem
     fs::init(self::io::ide::IDE::new(3 /* hdd */));
 
+    // swap
+    memory::swap_init(self::io::ide::IDE::new(1 /* hdb */));
+
     /////////////////////////////////////////////////////
     // Done initing stuff                              //
     /////////////////////////////////////////////////////
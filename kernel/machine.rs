@@ -105,12 +105,23 @@ extern "C" {
     /// Switch to usermode with the given PC, stack pointer, and %eax
     pub fn switch_to_user(pc: usize, esp: usize, eax: usize);
 
+    /// The return address of a newly forked process. It restores the `UContext` at the top of
+    /// the stack and returns to usermode. *Do not* call this function directly!
+    pub fn fork_return();
+
     /// The assembly handle for the page fault handler. This function calls
-    /// `vmm_page_fault`.
+    /// `vmm_page_fault` with the faulting address and the error code.
     pub fn page_fault_handler();
 
+    /// The assembly handles for the CPU exceptions (vectors 0-31). Most of them call
+    /// `exception_handler`, but vector 14 is the `page_fault_handler`.
+    pub static exception_table: [unsafe extern "C" fn(); 32];
+
+    /// The entry point of the double fault task. This function calls `double_fault_handler`.
+    pub fn double_fault();
+
     /// The assembly handle for the system call trap handler. This function calls
-    /// `syscall_handler`
+    /// `syscall_handler` and returns its result to the user in `%eax`
     pub fn syscall_trap();
 
 // pub fn sys_sigret(uint32_t);
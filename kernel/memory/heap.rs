@@ -2,7 +2,7 @@
 //! smallheap.
 
 use core::alloc::{GlobalAlloc, Layout, Opaque};
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 
 use smallheap::{self, Allocator};
 
@@ -11,30 +11,57 @@ use interrupts::no_interrupts;
 /// A wrapper around the heap allocator for use as the `global_allocator`.
 pub struct KernelAllocator {
     heap: RefCell<Option<Allocator>>,
+
+    /// The size of the heap in bytes
+    size: Cell<usize>,
+
+    /// The number of bytes currently allocated
+    used: Cell<usize>,
 }
 
 impl KernelAllocator {
     pub const fn new() -> Self {
         KernelAllocator {
             heap: RefCell::new(None),
+            size: Cell::new(0),
+            used: Cell::new(0),
         }
     }
 
     pub fn set_heap(&mut self, heap: Allocator) {
+        self.size.set(heap.size());
         *self.heap.borrow_mut() = Some(heap);
     }
+
+    /// The number of bytes currently allocated. This does not count the bookkeeping of the heap
+    /// itself, so it is a lower bound.
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    /// The number of bytes that are not allocated (see `used`)
+    pub fn free(&self) -> usize {
+        self.size.get() - self.used.get()
+    }
 }
 
 unsafe impl GlobalAlloc for KernelAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut Opaque {
         no_interrupts(|| {
-            self.heap
+            let ptr = self
+                .heap
                 .borrow_mut()
                 .as_mut()
                 .unwrap()
-                .malloc(layout.size(), layout.align())
-                .map(|p| p.as_ptr() as *mut Opaque)
-                .unwrap_or(0 as *mut Opaque)
+                .malloc(layout.size(), layout.align());
+
+            match ptr {
+                Some(p) => {
+                    self.used.set(self.used.get() + layout.size());
+                    p.as_ptr() as *mut Opaque
+                }
+                None => 0 as *mut Opaque,
+            }
         })
     }
 
@@ -44,7 +71,9 @@ unsafe impl GlobalAlloc for KernelAllocator {
                 .borrow_mut()
                 .as_mut()
                 .unwrap()
-                .free(ptr as *mut u8, layout.size())
+                .free(ptr as *mut u8, layout.size());
+
+            self.used.set(self.used.get() - layout.size());
         })
     }
 }
@@ -2,16 +2,22 @@
 //!
 //! 1. Kernel heap manager
 //! 2. Physical memory allocator
-//! 3. Virtual memory management
+//! 3. Virtual memory management, including swapping
 
 pub use self::heap::KernelAllocator;
-pub use self::vm::{vmm_page_fault, AddressSpace};
+pub use self::physmem::frame_usage;
+pub use self::vm::{is_user_range, swap_init, vmm_page_fault, AddressSpace};
 
 mod heap;
 mod physmem;
 mod regionmap;
 mod vm;
 
+/// Get the number of bytes of the kernel heap that are allocated and that are free
+pub fn heap_usage() -> (usize, usize) {
+    unsafe { (::ALLOCATOR.used(), ::ALLOCATOR.free()) }
+}
+
 /// Initialize all memory subsystems
 pub fn init(allocator: &mut KernelAllocator, kheap_start: usize, kheap_size: usize) {
     heap::init(allocator, kheap_start, kheap_size);
@@ -9,6 +9,7 @@ use process::CURRENT_PROCESS;
 use static_linked_list::StaticLinkedList;
 
 use super::regionmap::RegionMap;
+use super::vm::swap_out;
 
 /// Array of `FrameInfo`. This is a pointer to the region of memory used
 /// to hold physical memory allocation metadata.
@@ -18,6 +19,12 @@ static mut FRAME_INFO: *mut FrameInfoSection = 0 as *mut FrameInfoSection;
 /// 0 => empty list
 static mut FREE_FRAMES: usize = 0;
 
+/// The number of frames on the free list
+static mut NUM_FREE_FRAMES: usize = 0;
+
+/// The number of frames of physical memory
+static mut NUM_FRAMES: usize = 0;
+
 /// A physical memory frame
 #[repr(C, packed)]
 pub struct Frame([usize; 1024]);
@@ -46,33 +53,40 @@ pub struct FrameInfo(usize);
 
 /// A struct to keep track of information related to shared
 /// physical frames.
-#[allow(dead_code)]
 pub struct SharedFrameInfo {
-    /// needs to be at least 4B aligned
-    pad: u32,
+    /// The number of address spaces mapping this frame copy-on-write.
+    /// This also keeps the struct 4B aligned.
+    cow: usize,
 
     /// list of sharers: (pid, vaddr)
     list: StaticLinkedList<(usize, usize)>,
 }
 
 impl Frame {
-    /// Allocate a frame and return its physical address
+    /// Allocate a frame and return its physical address. If there are no free frames, user pages
+    /// are swapped out to make room.
     pub fn alloc() -> usize {
         let all_frames = unsafe { &mut *FRAME_INFO };
 
-        no_interrupts(|| {
-            // get a frame
-            let free = unsafe { FREE_FRAMES };
+        loop {
+            let frame = no_interrupts(|| {
+                // get a frame
+                let free = unsafe { FREE_FRAMES };
 
-            if free == 0 {
-                // TODO: page out
-                panic!("Out of physical memory");
-            }
+                if free == 0 {
+                    return None;
+                }
 
-            all_frames[free].alloc();
+                all_frames[free].alloc();
 
-            free << 12
-        })
+                Some(free << 12)
+            });
+
+            match frame {
+                Some(paddr) => return paddr,
+                None => swap_out(),
+            }
+        }
     }
 
     /// Free the frame with the given index
@@ -104,6 +118,66 @@ impl Frame {
 
         // printf!("Shared {:X} {:X}\n", vaddr, paddr);
     }
+
+    /// Returns true if the given frame is shared via `share`
+    pub fn is_shared(paddr: usize) -> bool {
+        let all_frames = unsafe { &mut *FRAME_INFO };
+
+        no_interrupts(|| {
+            all_frames[paddr >> 12]
+                .get_shared_info()
+                .map(|sfi| !sfi.list.is_empty())
+                .unwrap_or(false)
+        })
+    }
+
+    /// Add a copy-on-write mapping of the given frame. The first call also counts the mapping of
+    /// the original owner of the frame.
+    pub fn cow_share(paddr: usize) {
+        let all_frames = unsafe { &mut *FRAME_INFO };
+
+        no_interrupts(|| {
+            let frame = &mut all_frames[paddr >> 12];
+            let sfi = if frame.has_shared_info() {
+                frame.get_shared_info().expect("No shared frame info!")
+            } else {
+                let raw_sfi = Box::into_raw(box SharedFrameInfo::new());
+                frame.set_shared_info(raw_sfi);
+                unsafe { &mut *raw_sfi }
+            };
+
+            // count the original mapping too
+            if sfi.cow == 0 {
+                sfi.cow = 1;
+            }
+
+            sfi.cow += 1;
+        });
+    }
+
+    /// Returns the number of copy-on-write mappings of the given frame
+    pub fn cow_refs(paddr: usize) -> usize {
+        let all_frames = unsafe { &mut *FRAME_INFO };
+
+        no_interrupts(|| {
+            all_frames[paddr >> 12]
+                .get_shared_info()
+                .map(|sfi| sfi.cow)
+                .unwrap_or(0)
+        })
+    }
+
+    /// Stop tracking copy-on-write mappings of the given frame because only one mapping is left.
+    pub fn cow_claim(paddr: usize) {
+        let all_frames = unsafe { &mut *FRAME_INFO };
+
+        no_interrupts(|| {
+            let frame = &mut all_frames[paddr >> 12];
+            if frame.has_shared_info() {
+                frame.drop_shared_info();
+            }
+        });
+    }
 }
 
 /// Make the words of a frame indexable
@@ -147,39 +221,42 @@ impl FrameInfo {
         }
 
         // Remove from list
-        no_interrupts(|| unsafe { FREE_FRAMES = self.get_next_free() });
+        no_interrupts(|| unsafe {
+            FREE_FRAMES = self.get_next_free();
+            NUM_FREE_FRAMES -= 1;
+        });
 
         // mark not free
         self.clear_shared_info();
         self.set_free(false);
     }
 
-    /// Free the frame referred to by this FrameInfo if this is the last sharere; otherwise, just
-    /// remove this process's `SharedFrameInfo`.
+    /// Free the frame referred to by this FrameInfo if this is the last sharer; otherwise, just
+    /// remove this process's share or copy-on-write mapping from its `SharedFrameInfo`.
     pub fn free(&mut self) {
-        // remove shared page info for this process
-        let pid = unsafe { (*CURRENT_PROCESS).get_pid() };
         if self.has_shared_info() {
-            // remove this share
-            {
-                let sfi_list = &mut self.get_shared_info()
-                    .expect("No shared frame info to free")
-                    .list;
-                let i = sfi_list
-                    .iter()
-                    .position(|&(req_pid, _)| req_pid == pid)
-                    .expect("Attempt to free shared page which this process is not sharing!");
-                let _ = sfi_list.remove(i);
-            }
+            // remove shared page info for this process
+            let pid = unsafe { (*CURRENT_PROCESS).get_pid() };
+
+            let unused = {
+                let sfi = self.get_shared_info().expect("No shared frame info to free");
+
+                if let Some(i) = sfi.list.iter().position(|&(req_pid, _)| req_pid == pid) {
+                    // remove this share
+                    let _ = sfi.list.remove(i);
+                } else if sfi.cow > 0 {
+                    // remove this copy-on-write mapping
+                    sfi.cow -= 1;
+                } else {
+                    panic!("Attempt to free shared page which this process is not sharing!");
+                }
+
+                sfi.list.is_empty() && sfi.cow == 0
+            };
 
             // if no more sharers, drop the shared info
-            if self.get_shared_info().unwrap().list.is_empty() {
-                let addr = self.0 & !3;
-                let ptr = addr as *mut SharedFrameInfo;
-                no_interrupts(|| {
-                    unsafe { drop(Box::from_raw(ptr)) };
-                    self.clear_shared_info();
-                })
+            if unused {
+                no_interrupts(|| self.drop_shared_info());
             }
         }
 
@@ -192,6 +269,7 @@ impl FrameInfo {
             no_interrupts(|| unsafe {
                 self.set_next_free(FREE_FRAMES);
                 FREE_FRAMES = self.get_index();
+                NUM_FREE_FRAMES += 1;
             });
         }
     }
@@ -255,17 +333,30 @@ impl FrameInfo {
     fn clear_shared_info(&mut self) {
         self.0 &= 3;
     }
+
+    /// Deallocate the `SharedFrameInfo` of the frame and remove the ptr to it
+    fn drop_shared_info(&mut self) {
+        let addr = self.0 & !3;
+        let ptr = addr as *mut SharedFrameInfo;
+        unsafe { drop(Box::from_raw(ptr)) };
+        self.clear_shared_info();
+    }
 }
 
 impl SharedFrameInfo {
     fn new() -> SharedFrameInfo {
         SharedFrameInfo {
-            pad: 0,
+            cow: 0,
             list: StaticLinkedList::new(),
         }
     }
 }
 
+/// Get the number of frames of physical memory that are allocated and that are free
+pub fn frame_usage() -> (usize, usize) {
+    no_interrupts(|| unsafe { (NUM_FRAMES - NUM_FREE_FRAMES, NUM_FREE_FRAMES) })
+}
+
 /// Initialize physical memory frames using the rest of physical memory.
 /// This function detects all available physical memory.
 pub fn init(start: usize) {
@@ -296,6 +387,10 @@ pub fn init(start: usize) {
         num_frames += num;
     }
 
+    unsafe {
+        NUM_FRAMES = num_frames;
+    }
+
     bootlog!(
         "phys mem inited - metadata @ 0x{:X}, {} frames\n",
         start,
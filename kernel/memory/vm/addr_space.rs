@@ -1,13 +1,22 @@
 //! A module process address spaces
 
-use interrupts::no_interrupts;
+use alloc::Vec;
+
+use core::intrinsics::transmute;
+use core::ptr;
+
+use interrupts::{double_fault_cr3, no_interrupts};
+use io::block::BlockDataBuffer;
 use machine::{invlpg, vmm_on};
 use process::proc_table::PROCESS_TABLE;
-use process::{State, CURRENT_PROCESS};
+use process::signal::SIGSEGV;
+use process::{self, State, CURRENT_PROCESS};
 use sync::{Barrier, Event, StaticSemaphore};
 
 use super::super::physmem::Frame;
+use super::is_user_range;
 use super::structs::{PagingEntry, VMTable};
+use super::swap;
 use super::{KMAP_ADDRESS, NUM_SHARED, PD_ADDRESS, SHARED_PDES, USER_ADDRESS, VMM_ON};
 
 /// The address space of a single process
@@ -15,6 +24,9 @@ pub struct AddressSpace {
     /// paddr of this addr space's PD
     page_dir: usize,
 
+    /// paddr of the page table mapping kmap memory
+    kmap_table: usize,
+
     /// the index of the first un-kmapped page
     kmap_index: u8,
 
@@ -41,6 +53,10 @@ pub struct AddressSpace {
 impl AddressSpace {
     /// Create a new address space and set up the PD.
     pub fn new() -> AddressSpace {
+        // allocate the kmap page table up front, so that kmap never needs to allocate a frame
+        // (allocating a frame may swap out a page, which uses kmap)
+        let (_, kmap_table) = VMTable::new();
+
         // allocate a frame
         let (pd, pd_paddr) = VMTable::new();
 
@@ -63,8 +79,18 @@ impl AddressSpace {
             pd[NUM_SHARED].set_address(pd_paddr); // PD paddr
         }
 
+        // the next PDE maps kmap memory and the beginning of user memory
+        unsafe {
+            pd[NUM_SHARED + 1].set_present(true); // present
+            pd[NUM_SHARED + 1].set_read_write(true); // read/write
+            pd[NUM_SHARED + 1].set_privelege_level(true); // user pages are in this PDE too
+            pd[NUM_SHARED + 1].set_caching(false); // write-back
+            pd[NUM_SHARED + 1].set_address(kmap_table); // PT paddr
+        }
+
         let a = AddressSpace {
             page_dir: pd_paddr,
+            kmap_table: kmap_table,
             kmap_index: 0,
             lock: StaticSemaphore::new(1),
             req_pid: 0,
@@ -147,10 +173,10 @@ impl AddressSpace {
     }
 
     /// Map the given `paddr` for temporary use by the kernel and return a mut reference to the
-    /// frame.
+    /// frame. The method first tries to acquire the address space lock if `lock` is true.
     /// NOTE: should only be called on the current address space because it assumes that the PD is
     /// at PD_ADDRESS
-    pub fn kmap(&mut self, paddr: usize) -> &mut Frame {
+    pub fn kmap(&mut self, paddr: usize, lock: bool) -> &mut Frame {
         // get next unmapped address
         let next = self.kmap_index;
 
@@ -158,7 +184,7 @@ impl AddressSpace {
         if next == 255 {
             // unmap all if we have run out
             for i in 0..256 {
-                self.unmap(unsafe { KMAP_ADDRESS } + i * 0x1000, true);
+                self.unmap(unsafe { KMAP_ADDRESS } + i * 0x1000, lock);
             }
             self.kmap_index = 0;
         } else {
@@ -169,7 +195,7 @@ impl AddressSpace {
         let vaddr = unsafe { KMAP_ADDRESS } + (next as usize) * 0x1000;
 
         // map the frame
-        self.map(paddr, vaddr, true);
+        self.map(paddr, vaddr, lock);
 
         unsafe { &mut *(vaddr as *mut Frame) }
     }
@@ -210,7 +236,8 @@ impl AddressSpace {
 
             // if page table is now empty,
             // unmap and deallocate it
-            if (0..1024).all(|i| !pt[i].is_flag(0)) {
+            // NOTE: the kmap page table is kept until the address space is dropped
+            if pde_index != unsafe { KMAP_ADDRESS } >> 22 && (0..1024).all(|i| pt[i].is_unused()) {
                 pd[pde_index].free(virt >= unsafe { KMAP_ADDRESS });
             }
         }
@@ -260,11 +287,93 @@ impl AddressSpace {
         ret
     }
 
+    /// Returns the page table entry for `virt` in this address space, or None if there is no page
+    /// table for it. This address space does not have to be active. If it is not, its paging
+    /// structures are kmapped into the current address space, so the entry is only valid until the
+    /// next `kmap`.
+    ///
+    /// NOTE: this does not acquire the address space lock, so it should run with interrupts
+    /// disabled
+    pub unsafe fn get_pte(&self, virt: usize) -> Option<&'static mut PagingEntry> {
+        let pde_index = virt >> 22;
+        let pte_index = (virt & 0x003F_F000) >> 12;
+
+        let current = &mut (*CURRENT_PROCESS).addr_space;
+
+        if current.page_dir == self.page_dir {
+            let pd = &mut *PD_ADDRESS;
+
+            if !pd[pde_index].is_flag(0) {
+                return None;
+            }
+
+            let pt = &mut *(((NUM_SHARED << 22) | (pde_index << 12)) as *mut VMTable);
+            Some(&mut pt[pte_index])
+        } else {
+            let pd: &mut VMTable = transmute(current.kmap(self.page_dir, false));
+
+            if !pd[pde_index].is_flag(0) {
+                return None;
+            }
+
+            let pt: &mut VMTable = transmute(current.kmap(pd[pde_index].get_address(), false));
+            Some(&mut pt[pte_index])
+        }
+    }
+
+    /// Count the user pages of this address space. Returns the number of pages in memory and the
+    /// number of pages swapped out. This address space does not have to be active.
+    pub fn usage(&self) -> (usize, usize) {
+        let mut resident = 0;
+        let mut swapped = 0;
+
+        // NOTE: interrupts are off so that the kmapped tables stay mapped
+        no_interrupts(|| unsafe {
+            let current = &mut (*CURRENT_PROCESS).addr_space;
+
+            // the page tables of the user part of the address space
+            let mut tables = Vec::new();
+            {
+                let pd: &mut VMTable = transmute(current.kmap(self.page_dir, false));
+
+                for pde_index in (NUM_SHARED + 1)..1024 {
+                    if pd[pde_index].is_flag(0) {
+                        tables.push((pde_index, pd[pde_index].get_address()));
+                    }
+                }
+            }
+
+            for (pde_index, pt_paddr) in tables {
+                let pt: &mut VMTable = transmute(current.kmap(pt_paddr, false));
+
+                for pte_index in 0..1024 {
+                    let vaddr = (pde_index << 22) | (pte_index << 12);
+
+                    // kmapped pages are not counted
+                    if vaddr < USER_ADDRESS {
+                        continue;
+                    }
+
+                    if pt[pte_index].get_swap_slot().is_some() {
+                        swapped += 1;
+                    } else if pt[pte_index].is_flag(0) {
+                        resident += 1;
+                    }
+                }
+            }
+        });
+
+        (resident, swapped)
+    }
+
     /// Activate the current address space and turn on VM if needed
     pub fn activate(&mut self) {
         no_interrupts(|| unsafe {
             vmm_on(self.page_dir);
             VMM_ON = true;
+
+            // the double fault handler runs in the current address space
+            double_fault_cr3(self.page_dir);
         })
     }
 
@@ -283,7 +392,8 @@ impl AddressSpace {
 
         unsafe {
             self.req_pid = if let Some(p) = PROCESS_TABLE.get(pid) {
-                if (*p).get_state() == State::TERMINATED {
+                let state = (*p).get_state();
+                if state == State::ZOMBIE || state == State::TERMINATED {
                     return false;
                 } else {
                     pid
@@ -367,6 +477,192 @@ impl AddressSpace {
         true
     }
 
+    /// Create a copy of this address space for the child process with PID `child_pid`. Pages
+    /// shared with `request_share` stay shared with the child. All other pages become
+    /// copy-on-write: they are marked read-only in both address spaces, and the first write to
+    /// one of them makes a private copy (see `cow_fault`).
+    ///
+    /// NOTE: must run while this address space is active
+    pub fn fork(&mut self, child_pid: usize) -> AddressSpace {
+        let child = AddressSpace::new();
+
+        let pd = unsafe { &mut *PD_ADDRESS };
+
+        self.lock.down();
+
+        for pde_index in (unsafe { NUM_SHARED } + 1)..1024 {
+            if !pd[pde_index].is_flag(0) {
+                continue;
+            }
+
+            let pt = unsafe { &mut *(((NUM_SHARED << 22) | (pde_index << 12)) as *mut VMTable) };
+
+            // create the child's page table (the child already has one for kmap memory)
+            // NOTE: only the most recently kmapped frame is guaranteed to still be mapped
+            let child_pt_paddr = if pde_index == unsafe { KMAP_ADDRESS } >> 22 {
+                child.kmap_table
+            } else {
+                Frame::alloc()
+            };
+            {
+                let child_pt: &mut VMTable = unsafe { transmute(self.kmap(child_pt_paddr, false)) };
+
+                for pte_index in 0..1024 {
+                    let vaddr = (pde_index << 22) | (pte_index << 12);
+
+                    child_pt[pte_index] = PagingEntry::new();
+
+                    // kmapped pages are not copied
+                    if vaddr < unsafe { USER_ADDRESS } {
+                        continue;
+                    }
+
+                    // NOTE: interrupts are off so that the page is not swapped out under us
+                    no_interrupts(|| {
+                        let pte = &mut pt[pte_index];
+
+                        if let Some(slot) = pte.get_swap_slot() {
+                            // the child refers to the same swap slot
+                            swap::share_slot(slot);
+                        } else if pte.is_flag(0) {
+                            let paddr = pte.get_address();
+
+                            if Frame::is_shared(paddr) {
+                                Frame::share(child_pid, vaddr, paddr);
+                            } else {
+                                Frame::cow_share(paddr);
+                                pte.set_read_write(false);
+                                pte.set_cow(true);
+                                unsafe { invlpg(vaddr) };
+
+                                swap::track(child_pid, vaddr);
+                            }
+                        } else {
+                            return;
+                        }
+
+                        child_pt[pte_index] = pte.clone();
+                    });
+                }
+            }
+
+            // point the child's PD at the new page table
+            let child_pd: &mut VMTable = unsafe { transmute(self.kmap(child.page_dir, false)) };
+            child_pd[pde_index] = pd[pde_index].clone();
+            child_pd[pde_index].set_address(child_pt_paddr);
+        }
+
+        self.lock.up();
+
+        child
+    }
+
+    /// Handle a write to the page containing `virt` if it is copy-on-write. If other address
+    /// spaces still map the frame, this address space gets its own copy of the page; otherwise,
+    /// it just takes over the frame. Either way, the page becomes writable.
+    ///
+    /// Returns false if the page is not copy-on-write.
+    ///
+    /// NOTE: should only be called on the current address space because it assumes that the PD is
+    /// at PD_ADDRESS
+    pub fn cow_fault(&mut self, virt: usize) -> bool {
+        let pde_index = virt >> 22;
+        let pte_index = (virt & 0x003F_F000) >> 12;
+        let page = virt & 0xFFFF_F000;
+
+        let pd = unsafe { &mut *PD_ADDRESS };
+
+        self.lock.down();
+
+        if !pd[pde_index].is_flag(0) {
+            self.lock.up();
+            return false;
+        }
+
+        let pt = unsafe { &mut *(((NUM_SHARED << 22) | (pde_index << 12)) as *mut VMTable) };
+        let pte = &mut pt[pte_index];
+
+        if !pte.is_flag(0) || !pte.is_cow() {
+            self.lock.up();
+            return false;
+        }
+
+        let paddr = pte.get_address();
+
+        if Frame::cow_refs(paddr) > 1 {
+            // copy the page to a new frame
+            let new_paddr = Frame::alloc();
+            {
+                let old = unsafe { &*(page as *const Frame) };
+                let new = self.kmap(new_paddr, false);
+                for i in 0..1024 {
+                    new[i] = old[i];
+                }
+            }
+
+            // drop our reference to the old frame
+            no_interrupts(|| {
+                Frame::free(paddr >> 12);
+                pte.set_address(new_paddr);
+            });
+        } else {
+            // nobody else maps the frame anymore
+            Frame::cow_claim(paddr);
+        }
+
+        no_interrupts(|| {
+            pte.set_cow(false);
+            pte.set_read_write(true);
+            unsafe { invlpg(page) };
+        });
+
+        self.lock.up();
+
+        true
+    }
+
+    /// Bring the page containing `virt` back in from the swap area if it was swapped out.
+    ///
+    /// Returns false if the page is not swapped out.
+    ///
+    /// NOTE: should only be called on the current address space because it assumes that the PD is
+    /// at PD_ADDRESS
+    pub fn swap_in(&mut self, virt: usize) -> bool {
+        let page = virt & 0xFFFF_F000;
+
+        let slot =
+            no_interrupts(|| unsafe { self.get_pte(page).and_then(|pte| pte.get_swap_slot()) });
+        let slot = match slot {
+            Some(slot) => slot,
+            None => return false,
+        };
+
+        // read the page before allocating a frame, since allocating may swap out other pages
+        let mut buf = BlockDataBuffer::new(0x1000);
+        swap::read_slot(slot, &mut buf);
+
+        let paddr = Frame::alloc();
+
+        self.lock.down();
+
+        // replace the reference to the slot with a mapping of the new frame
+        no_interrupts(|| {
+            let pte = unsafe { self.get_pte(page) }.expect("No page table for swapped out page");
+            *pte = PagingEntry::new();
+        });
+        self.map(paddr, page, false);
+        swap::free_slot(slot);
+
+        unsafe {
+            ptr::copy(buf.get_ptr::<u8>(0), page as *mut u8, 0x1000);
+            swap::track((*CURRENT_PROCESS).get_pid(), page);
+        }
+
+        self.lock.up();
+
+        true
+    }
+
     /// Remove all non-kernel mappings in this address space.
     /// NOTE: must run while this address space is active
     pub fn clear(&mut self) {
@@ -374,6 +670,9 @@ impl AddressSpace {
 
         self.lock.down();
 
+        // none of the pages can be swapped out anymore
+        swap::untrack(unsafe { (*CURRENT_PROCESS).get_pid() });
+
         // for each present PDE, remove all
         // mappings associated with it
         for pde_index in (unsafe { NUM_SHARED } + 1)..1024 {
@@ -390,45 +689,94 @@ impl AddressSpace {
 }
 
 impl Drop for AddressSpace {
-    /// Deallocate the page directory.
+    /// Deallocate the page directory and the kmap page table.
     /// NOTE: cannot run while the address space is active
     fn drop(&mut self) {
-        // free the page directory
+        // free the kmap page table and the page directory
+        Frame::free(self.kmap_table >> 12);
         Frame::free(self.page_dir >> 12);
     }
 }
 
+/// Page fault error code bit: the page was present (i.e. this is a protection violation)
+const PF_PRESENT: usize = 1 << 0;
+
+/// Page fault error code bit: the fault was caused by a write
+const PF_WRITE: usize = 1 << 1;
+
+/// Page fault error code bit: the fault happened in user mode
+const PF_USER: usize = 1 << 2;
+
 /// The Rust-side code of the page fault handler.
 #[no_mangle]
-pub unsafe extern "C" fn vmm_page_fault(
-    // context: *mut KContext,
-    fault_addr: usize
-) {
+pub unsafe extern "C" fn vmm_page_fault(fault_addr: usize, error: usize) {
+    if CURRENT_PROCESS.is_null() {
+        panic!("Page fault @ 0x{:X} with no current process", fault_addr);
+    }
+
+    // A fault in user mode is the process's fault, and so is a fault at a user address in a
+    // system call, which only touches user memory that the process passed in. Any other fault is
+    // a kernel bug, and the kernel may be holding locks, so it cannot just kill the process.
+    let process_fault = error & PF_USER != 0 || is_user_range(fault_addr, 1);
+
     // segfault! should be very rare with rust
     // first 13MiB are reserved by kernel
     if fault_addr < 0xD00000 {
-        // TODO: only kill that process
-        panic!(
-            "{:?} [segmentation violation @ 0x{:X}]",
+        if !process_fault {
+            kernel_fault(fault_addr, error);
+        }
+
+        bootlog!(
+            "{:?} [segmentation violation @ 0x{:X}]\n",
             *CURRENT_PROCESS, fault_addr
         );
+        process::kill(SIGSEGV);
     }
 
     // printf!("page fault {:X}\n", fault_addr);
 
-    if !CURRENT_PROCESS.is_null() {
-        (*CURRENT_PROCESS)
-            .addr_space
-            .map(Frame::alloc(), fault_addr, true);
-    } else {
-        panic!("Page fault @ 0x{:X} with no current process", fault_addr);
+    // writes to present pages are either copy-on-write or illegal
+    if error & PF_PRESENT != 0 {
+        if error & PF_WRITE != 0 && (*CURRENT_PROCESS).addr_space.cow_fault(fault_addr) {
+            return;
+        }
+
+        if !process_fault {
+            kernel_fault(fault_addr, error);
+        }
+
+        bootlog!(
+            "{:?} [protection violation @ 0x{:X}]\n",
+            *CURRENT_PROCESS, fault_addr
+        );
+        process::kill(SIGSEGV);
+    }
+
+    // bring back pages that were swapped out
+    if (*CURRENT_PROCESS).addr_space.swap_in(fault_addr) {
+        return;
     }
 
+    (*CURRENT_PROCESS)
+        .addr_space
+        .map(Frame::alloc(), fault_addr, true);
+
     // memclr an alloced frame
     let page = &mut *((fault_addr & 0xFFFF_F000) as *mut Frame);
     for i in 0..1024 {
         page[i] = 0;
     }
 
+    // the new page may be swapped out later
+    swap::track((*CURRENT_PROCESS).get_pid(), fault_addr);
+
     // printf!("page fault done {:X}\n", fault_addr);
 }
+
+/// Panic because the kernel made a page fault it cannot handle
+unsafe fn kernel_fault(fault_addr: usize, error: usize) -> ! {
+    panic!(
+        "{:?} [kernel page fault @ 0x{:X}, error code 0x{:X}]",
+        *CURRENT_PROCESS, fault_addr, error
+    );
+}
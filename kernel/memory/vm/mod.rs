@@ -12,15 +12,18 @@
 //!
 //! When a page fault occurs for a legal address, the fault handler checks the following cases:
 //!
-//! 1. Is the page paged out to disk? If so, swap it back in
+//! 1. Is the page paged out to disk? If so, swap it back in (see the `swap` module)
 //! 2. Is the page marked present but read-only? If so, this page is COW, so clone it and mark the
 //!    new page read/write
 //! 3. Else, allocate a new frame a map the page to it
 
 pub use self::addr_space::{vmm_page_fault, AddressSpace};
+pub use self::swap::init as swap_init;
+pub use self::swap::swap_out;
 
 mod addr_space;
 mod structs;
+mod swap;
 
 use self::structs::{PagingEntry, VMTable};
 use super::super::interrupts::add_trap_handler;
@@ -46,6 +49,15 @@ static mut USER_ADDRESS: usize = 0;
 /// Is VM on?
 static mut VMM_ON: bool = false;
 
+/// Returns true if the `len` bytes starting at `addr` all lie in the part of the address space
+/// that belongs to the process (i.e. not the shared kernel memory).
+pub fn is_user_range(addr: usize, len: usize) -> bool {
+    match addr.checked_add(len) {
+        Some(_) => addr >= unsafe { USER_ADDRESS },
+        None => false,
+    }
+}
+
 /// Create `n` PDEs to direct map the memory start from the `i`th page.
 /// unsafe because it deals with raw pointers and static muts.
 unsafe fn init_shared_pdes(n: usize, mut i: usize) {
@@ -7,12 +7,17 @@ use interrupts::no_interrupts;
 use process::CURRENT_PROCESS;
 
 use super::super::physmem::Frame;
+use super::swap;
 use super::VMM_ON;
 
 /// A single entry in a page directory or table
 /// ```
 /// 31                 12  9       0
 /// [                    000 flags  ]
+///
+/// When swapped out
+/// 31                 12  10      0
+/// [ swap slot          010000000000]
 /// ```
 #[derive(Copy, Clone)]
 #[repr(C, packed)]
@@ -63,6 +68,27 @@ impl PagingEntry {
         self.set_flag(3, value);
     }
 
+    /// Set the accessed bit. The CPU sets it whenever the page is used.
+    /// true = accessed, false = not accessed
+    #[inline(always)]
+    pub fn set_accessed(&mut self, value: bool) {
+        self.set_flag(5, value);
+    }
+
+    /// Set the copy-on-write bit (one of the bits available to the OS).
+    /// true = copy-on-write, false = not copy-on-write
+    #[inline(always)]
+    pub fn set_cow(&mut self, value: bool) {
+        self.set_flag(9, value);
+    }
+
+    /// Mark the page swapped out to the given swap slot. This replaces the whole entry, so the
+    /// page is no longer present.
+    #[inline(always)]
+    pub fn set_swapped(&mut self, slot: usize) {
+        self.entry = (slot << 12) | (1 << 10);
+    }
+
     // general ops
 
     /// Set the `index`-th flag to `value`.
@@ -91,13 +117,41 @@ impl PagingEntry {
         ((self.entry >> index) & 1) == 1
     }
 
+    /// Return true if the entry is marked copy-on-write
+    #[inline(always)]
+    pub fn is_cow(&self) -> bool {
+        self.is_flag(9)
+    }
+
+    /// Return true if the page has been accessed since the bit was last cleared
+    #[inline(always)]
+    pub fn is_accessed(&self) -> bool {
+        self.is_flag(5)
+    }
+
+    /// Return the swap slot of the page if it is swapped out
+    #[inline(always)]
+    pub fn get_swap_slot(&self) -> Option<usize> {
+        if !self.is_flag(0) && self.is_flag(10) {
+            Some(self.entry >> 12)
+        } else {
+            None
+        }
+    }
+
+    /// Return true if the entry neither maps a frame nor refers to a swap slot
+    #[inline(always)]
+    pub fn is_unused(&self) -> bool {
+        self.entry == 0
+    }
+
     /// Return the upper 20-bits of the entry
     #[inline(always)]
     pub fn get_address(&self) -> usize {
         self.entry & 0xFFFF_F000
     }
 
-    /// Free the frame pointed to if `dealloc` and mark this entry not present.
+    /// Free the frame (or swap slot) pointed to if `dealloc` and mark this entry not present.
     pub fn free(&mut self, dealloc: bool) {
         no_interrupts(|| {
             if self.is_flag(0) {
@@ -117,6 +171,12 @@ impl PagingEntry {
                 self.set_flag(8, false);
                 self.set_flag(9, false);
                 self.set_address(0);
+            } else if let Some(slot) = self.get_swap_slot() {
+                if dealloc {
+                    swap::free_slot(slot);
+                }
+
+                self.entry = 0;
             }
         })
     }
@@ -134,7 +194,7 @@ impl VMTable {
             // otherwise use the paddr
             let frame = if VMM_ON {
                 if !CURRENT_PROCESS.is_null() {
-                    (*CURRENT_PROCESS).addr_space.kmap(paddr)
+                    (*CURRENT_PROCESS).addr_space.kmap(paddr, true)
                 } else {
                     panic!("VMM_ON with no CURRENT_PROCESS!");
                 }
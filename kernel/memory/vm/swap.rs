@@ -0,0 +1,228 @@
+//! A module for swapping user pages out to disk
+//!
+//! When physical memory runs out, `Frame::alloc` calls `swap_out` to evict a user page to the swap
+//! area. The victim is chosen with the clock algorithm: every user page that may be swapped out is
+//! kept in a circular list, and a page is only evicted if it has not been accessed since the clock
+//! hand last passed it. Shared and copy-on-write pages are never evicted, since other address
+//! spaces map them too.
+//!
+//! The PTE of a swapped out page is not present, and it holds the index of the swap slot in place
+//! of the frame address (see `PagingEntry::set_swapped`), so the page fault handler can bring the
+//! page back in. After a `fork`, several address spaces may refer to the same slot, so each slot
+//! is reference counted.
+
+use alloc::boxed::Box;
+
+use core::ptr;
+
+use interrupts::no_interrupts;
+use io::block::{BlockDataBuffer, BlockDevice};
+use io::ide::IDE;
+use machine::invlpg;
+use process::proc_table::PROCESS_TABLE;
+use process::{State, CURRENT_PROCESS};
+use static_linked_list::StaticLinkedList;
+use sync::StaticSemaphore;
+
+use super::super::physmem::Frame;
+
+/// The size of a page (and of a swap slot)
+const PAGE_SIZE: usize = 0x1000;
+
+/// The number of slots in the swap area. The swap disk must be at least 16MiB.
+const NUM_SLOTS: usize = 4096;
+
+/// The disk holding the swap area
+static mut SWAP_DEVICE: *mut IDE = 0 as *mut IDE;
+
+/// The number of PTEs referring to each swap slot. 0 => free slot
+static mut SLOT_REFS: [usize; NUM_SLOTS] = [0; NUM_SLOTS];
+
+/// The pages that may be swapped out: (pid, vaddr). The front of the list is the clock hand.
+static mut CLOCK: StaticLinkedList<(usize, usize)> = StaticLinkedList::new();
+
+/// A lock serializing swapping, so that a page is never read back in before it is written out
+static mut SWAP_LOCK: StaticSemaphore = StaticSemaphore::new(1);
+
+/// Initialize swapping using the given disk as the swap area
+pub fn init(device: IDE) {
+    device.claim();
+
+    unsafe {
+        let device = box device;
+        SWAP_DEVICE = Box::into_raw(device);
+    }
+
+    printf!("swap inited - {} slots\n", NUM_SLOTS);
+}
+
+/// Allow the page at `vaddr` in the address space of the process with PID `pid` to be swapped out
+pub fn track(pid: usize, vaddr: usize) {
+    no_interrupts(|| unsafe { CLOCK.push_back((pid, vaddr & 0xFFFF_F000)) });
+}
+
+/// Forget all pages of the process with PID `pid`, e.g. because its address space was cleared
+pub fn untrack(pid: usize) {
+    no_interrupts(|| unsafe {
+        let mut others = StaticLinkedList::new();
+
+        while let Some((owner, vaddr)) = CLOCK.pop_front() {
+            if owner != pid {
+                others.push_back((owner, vaddr));
+            }
+        }
+
+        CLOCK.append(&mut others);
+    });
+}
+
+/// Add a reference to the given swap slot (e.g. because `fork` copied a swapped out page)
+pub fn share_slot(slot: usize) {
+    no_interrupts(|| unsafe { SLOT_REFS[slot] += 1 });
+}
+
+/// Drop a reference to the given swap slot. The slot is free once nothing refers to it anymore.
+pub fn free_slot(slot: usize) {
+    no_interrupts(|| unsafe {
+        if SLOT_REFS[slot] == 0 {
+            panic!("Attempt to free unused swap slot {}", slot);
+        }
+
+        SLOT_REFS[slot] -= 1;
+    });
+}
+
+/// Read the page in the given swap slot into `buffer`, which must be a page long
+pub fn read_slot(slot: usize, buffer: &mut BlockDataBuffer) {
+    unsafe {
+        SWAP_LOCK.down();
+
+        let device = &mut *SWAP_DEVICE;
+        let sector_size = device.get_block_size();
+        let mut sector = BlockDataBuffer::new(sector_size);
+
+        for i in 0..(PAGE_SIZE / sector_size) {
+            device.read_block(slot * PAGE_SIZE / sector_size + i, &mut sector);
+            ptr::copy(
+                sector.get_ptr::<u8>(0),
+                buffer.get_ptr_mut::<u8>(i * sector_size),
+                sector_size,
+            );
+        }
+
+        SWAP_LOCK.up();
+    }
+}
+
+/// Swap out a user page chosen by the clock algorithm and free its frame.
+///
+/// # Panics
+///
+/// Panics if no page can be swapped out or the swap area is full.
+pub fn swap_out() {
+    unsafe {
+        if SWAP_DEVICE.is_null() || CURRENT_PROCESS.is_null() {
+            panic!("Out of physical memory");
+        }
+
+        SWAP_LOCK.down();
+
+        let (paddr, slot) = no_interrupts(|| choose_victim());
+
+        // copy the page out of the frame before doing any I/O
+        let mut page = BlockDataBuffer::new(PAGE_SIZE);
+        {
+            let frame = (*CURRENT_PROCESS).addr_space.kmap(paddr, false);
+            ptr::copy(
+                frame as *const Frame as *const u8,
+                page.get_ptr_mut::<u8>(0),
+                PAGE_SIZE,
+            );
+        }
+
+        let device = &mut *SWAP_DEVICE;
+        let sector_size = device.get_block_size();
+        let mut sector = BlockDataBuffer::new(sector_size);
+
+        for i in 0..(PAGE_SIZE / sector_size) {
+            ptr::copy(
+                page.get_ptr::<u8>(i * sector_size),
+                sector.get_ptr_mut::<u8>(0),
+                sector_size,
+            );
+            device.write_block(slot * PAGE_SIZE / sector_size + i, &sector);
+        }
+
+        // the page is safely on disk now
+        Frame::free(paddr >> 12);
+
+        SWAP_LOCK.up();
+    }
+}
+
+/// Move the clock hand to the next page to swap out, point its PTE at a newly allocated swap slot,
+/// and return the frame and the slot. The frame still has to be written to the slot and freed.
+///
+/// NOTE: should run with interrupts disabled
+unsafe fn choose_victim() -> (usize, usize) {
+    // every page is passed at most twice: once to clear its accessed bit, and once to evict it
+    let mut remaining = 2 * CLOCK.len();
+
+    while remaining > 0 {
+        remaining -= 1;
+
+        let (pid, vaddr) = CLOCK.pop_front().unwrap();
+
+        // forget pages of dead processes
+        let process = match PROCESS_TABLE.get(pid) {
+            Some(p) => {
+                let state = (*p).get_state();
+                if state == State::ZOMBIE || state == State::TERMINATED {
+                    continue;
+                }
+                p
+            }
+            None => continue,
+        };
+
+        // forget pages that are no longer mapped
+        let pte = match (*process).addr_space.get_pte(vaddr) {
+            Some(pte) if pte.is_flag(0) => pte,
+            _ => continue,
+        };
+
+        let paddr = pte.get_address();
+
+        // only swap out frames no other address space maps
+        if pte.is_cow() || Frame::is_shared(paddr) {
+            CLOCK.push_back((pid, vaddr));
+            continue;
+        }
+
+        // give recently used pages a second chance
+        if pte.is_accessed() {
+            pte.set_accessed(false);
+            if process == CURRENT_PROCESS {
+                invlpg(vaddr);
+            }
+
+            CLOCK.push_back((pid, vaddr));
+            continue;
+        }
+
+        let slot = match SLOT_REFS.iter().position(|&refs| refs == 0) {
+            Some(slot) => slot,
+            None => panic!("Out of swap space"),
+        };
+        SLOT_REFS[slot] = 1;
+
+        pte.set_swapped(slot);
+        if process == CURRENT_PROCESS {
+            invlpg(vaddr);
+        }
+
+        return (paddr, slot);
+    }
+
+    panic!("Out of physical memory");
+}
@@ -23,6 +23,26 @@ pub struct KContext {
     pub eax: usize,
 }
 
+/// A struct representing the user context saved on the kernel stack
+/// by `syscall_trap`, starting from the lowest address.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct UContext {
+    pub eax: usize,
+    pub ecx: usize,
+    pub edx: usize,
+    pub ebx: usize,
+    pub esi: usize,
+    pub edi: usize,
+    pub ebp: usize,
+    pub ds: usize,
+    pub eip: usize,
+    pub cs: usize,
+    pub eflags: usize,
+    pub esp: usize,
+    pub ss: usize,
+}
+
 impl KContext {
     /// Create a new empty context struct
     pub fn new() -> KContext {
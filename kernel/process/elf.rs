@@ -183,6 +183,20 @@ pub const PT_PHDR: usize = 6;
 pub const PT_LOPROC: usize = 0x7000_0000;
 pub const PT_HIPROC: usize = 0x7fff_ffff;
 
+// Types of auxiliary vector entries, passed to the program on its stack
+/// End of the auxiliary vector
+pub const AT_NULL: usize = 0;
+/// The address of the program header table in memory
+pub const AT_PHDR: usize = 3;
+/// The size of an entry in the program header table
+pub const AT_PHENT: usize = 4;
+/// The number of program headers
+pub const AT_PHNUM: usize = 5;
+/// The page size
+pub const AT_PAGESZ: usize = 6;
+/// The entry point of the program
+pub const AT_ENTRY: usize = 9;
+
 /// A safe wrapper around a pointer to a Phdr table
 pub struct PhdrTable {
     phdr_table: BlockDataBuffer,
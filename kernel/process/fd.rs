@@ -0,0 +1,95 @@
+//! A module for per-process file descriptor tables
+
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use alloc::Vec;
+
+use fs::vfs::{OpenFile, VNode};
+use sync::Semaphore;
+
+/// The maximum number of files a process may have open at a time
+const MAX_FDS: usize = 32;
+
+/// An open file with its offset, shared by all the descriptors that refer to it
+pub struct SharedFile {
+    /// The underlying file handle
+    pub handle: Box<OpenFile>,
+
+    /// The offset of the next read or write. It is only locked while it is read or updated, and
+    /// not during the I/O itself, which may block (e.g. reading the keyboard).
+    pub offset: Semaphore<usize>,
+
+    /// If the file is a directory, the lowest inode number that `getdents` may return next. The
+    /// entries are returned in order of inode number, so that the listing can resume after a file
+    /// is added or removed.
+    pub dirent: Semaphore<usize>,
+}
+
+impl SharedFile {
+    /// Share the given file handle, starting at offset 0
+    pub fn new(handle: Box<OpenFile>) -> SharedFile {
+        SharedFile {
+            handle: handle,
+            offset: Semaphore::new(0, 1),
+            dirent: Semaphore::new(0, 1),
+        }
+    }
+}
+
+/// An open file and the ways in which it may be used
+#[derive(Clone)]
+pub struct Descriptor {
+    /// The underlying open file. Copies of the descriptor (e.g. in a forked process) share it, and
+    /// so the file offset.
+    pub file: Arc<SharedFile>,
+
+    /// The file that was opened
+    pub node: VNode,
+
+    /// Was the file opened for reading?
+    pub readable: bool,
+
+    /// Was the file opened for writing?
+    pub writable: bool,
+}
+
+/// A table mapping file descriptors to open files. Each process has its own table, but a forked
+/// process starts with a copy of its parent's, whose descriptors share the open files.
+#[derive(Clone)]
+pub struct FDTable {
+    /// The open files, indexed by file descriptor. `None` marks a closed descriptor, which can be
+    /// reused by the next `insert`.
+    files: Vec<Option<Descriptor>>,
+}
+
+impl FDTable {
+    /// Create a new empty table
+    pub fn new() -> FDTable {
+        FDTable { files: Vec::new() }
+    }
+
+    /// Add the open file to the table and return its file descriptor. The lowest free descriptor
+    /// is always used. Returns `None` if the table is full.
+    pub fn insert(&mut self, desc: Descriptor) -> Option<usize> {
+        if let Some(fd) = self.files.iter().position(|d| d.is_none()) {
+            self.files[fd] = Some(desc);
+            Some(fd)
+        } else if self.files.len() < MAX_FDS {
+            self.files.push(Some(desc));
+            Some(self.files.len() - 1)
+        } else {
+            None
+        }
+    }
+
+    /// Get the open file with the given descriptor if there is one
+    pub fn get(&mut self, fd: usize) -> Option<&mut Descriptor> {
+        self.files.get_mut(fd).and_then(|d| d.as_mut())
+    }
+
+    /// Remove the given descriptor from the table and return its file if there is one. The file
+    /// is closed when the last `Descriptor` sharing it is dropped.
+    pub fn remove(&mut self, fd: usize) -> Option<Descriptor> {
+        self.files.get_mut(fd).and_then(|d| d.take())
+    }
+}
@@ -12,7 +12,7 @@ pub fn run(this: &Process) -> usize {
     // clear the screen
     Rectangle::clear_screen();
 
-    exec(1);
+    let errno = exec("/test", &["/test"], &[]);
 
-    panic!("Oh no!");
+    panic!("Oh no! Unable to exec /test: {:?}", errno);
 }
@@ -1,25 +1,89 @@
 //! The ELF loader
 
+use alloc::Vec;
+
 use core::mem::size_of;
 use core::ptr;
 
 use super::elf::*;
-use fs::ROOT_FS;
+use super::CURRENT_PROCESS;
+use errno::Errno;
+use fs;
+use fs::vfs::PERM_EXEC;
 use io::block::BlockDataBuffer;
 use machine::switch_to_user;
+use memory::{heap_usage, is_user_range};
+
+/// The initial top of the user stack. The page above it is never used.
+pub const USER_STACK_TOP: usize = 0xFFFF_F000;
+
+/// The maximum number of bytes taken up by the arguments and environment of a program, including
+/// the pointers to them
+pub const MAX_ARG_SIZE: usize = 32 * 1024;
 
-/// Exec the given file
-/// TODO: use filenames, rather than inode numbers
-/// TODO: pass arguments
+/// The number of bytes of the kernel heap that reading the segments of a program always leaves
+/// free
+const HEAP_RESERVE: usize = 256 << 10;
+
+/// Exec the file with the given path, passing it the given arguments and environment.
 /// Only returns if there is an error
-pub fn exec(inode: usize) -> usize {
-    // open the file
-    let mut f = unsafe { (*ROOT_FS).open_read(inode).ok().unwrap() };
+pub fn exec<S: AsRef<str>>(path: &str, argv: &[S], envp: &[S]) -> Errno {
+    let (entry, esp) = match load(path, argv, envp) {
+        Ok(start) => start,
+        Err(errno) => return errno,
+    };
+
+    // Yield control to the program and switch to user mode
+    unsafe {
+        switch_to_user(entry, esp, 0);
+    }
+
+    // should not get here!
+    unreachable!();
+}
+
+/// Replace the address space of the current process with the program in the file with the given
+/// path, and set up its stack with the given arguments and environment. Returns the entry point
+/// and initial stack pointer of the program.
+///
+/// The file is checked before the address space is cleared, so the process is left untouched if
+/// there is an error.
+pub fn load<S: AsRef<str>>(path: &str, argv: &[S], envp: &[S]) -> Result<(usize, usize), Errno> {
+    // make sure the arguments fit on the stack
+    let strings_size: usize = argv
+        .iter()
+        .chain(envp.iter())
+        .map(|s| s.as_ref().len() + 1)
+        .sum();
+    let pointers_size = (argv.len() + envp.len() + 3) * size_of::<usize>();
+
+    if strings_size + pointers_size > MAX_ARG_SIZE {
+        return Err(Errno::E2BIG);
+    }
+
+    // open the file, which has to be a file the process may run
+    let f = {
+        let (node, creds) = unsafe { ((*CURRENT_PROCESS).lookup(path)?, (*CURRENT_PROCESS).creds) };
+        let file = fs::inode(node)?;
+
+        if fs::access(&*file, &creds, PERM_EXEC)?.is_dir {
+            return Err(Errno::EACCES);
+        }
+
+        file.open(false)?
+    };
+
+    let size = f.size()?;
+    if size < size_of::<Elf32Ehdr>() {
+        return Err(Errno::ENOEXEC);
+    }
 
     // read the elf header
     let ehdr = unsafe {
         let mut buf = BlockDataBuffer::new(size_of::<Elf32Ehdr>());
-        f.read(&mut buf);
+        if f.read(0, &mut buf)? != size_of::<Elf32Ehdr>() {
+            return Err(Errno::ENOEXEC);
+        }
         (*buf.get_ptr::<Elf32Ehdr>(0)).clone()
     };
 
@@ -31,41 +95,157 @@ pub fn exec(inode: usize) -> usize {
         || ehdr.e_machine != EM_386 || ehdr.e_version != EV_CURRENT
         || ehdr.e_phentsize != size_of::<Elf32Phdr>() as u16
     {
-        return 1;
+        return Err(Errno::ENOEXEC);
     }
 
-    // load the program header table
-    f.seek(ehdr.e_phoff);
+    // load the program header table, which has to be in the file
+    let phdr_table_size = ehdr.e_phnum as usize * size_of::<Elf32Phdr>();
+    match ehdr.e_phoff.checked_add(phdr_table_size) {
+        Some(end) if end <= size => {}
+        _ => return Err(Errno::ENOEXEC),
+    }
 
     let phdr_table = PhdrTable::new(ehdr.e_phnum as usize, {
-        let mut buf = BlockDataBuffer::new(ehdr.e_phnum as usize * size_of::<Elf32Phdr>());
-        f.read(&mut buf);
+        let mut buf = BlockDataBuffer::new(phdr_table_size);
+        if f.read(ehdr.e_phoff, &mut buf)? != phdr_table_size {
+            return Err(Errno::ENOEXEC);
+        }
         buf
     });
+    let phdrs: Vec<Elf32Phdr> = phdr_table.collect();
 
-    // load loadable segments into memory
-    for phdr in phdr_table {
-        if phdr.p_type == PT_LOAD {
-            let mut buf = BlockDataBuffer::new(phdr.p_filesz);
-            f.seek(phdr.p_offset);
-            f.read(&mut buf);
-
-            unsafe {
-                ptr::copy(buf.get_ptr(0), phdr.p_vaddr as *mut u8, buf.size());
-            }
+    // check that all loadable segments are in the file and in user memory
+    let mut segments_size: usize = 0;
+    for phdr in phdrs.iter().filter(|phdr| phdr.p_type == PT_LOAD) {
+        if phdr.p_filesz > phdr.p_memsz || !is_user_range(phdr.p_vaddr, phdr.p_memsz) {
+            return Err(Errno::ENOEXEC);
         }
+
+        match phdr.p_offset.checked_add(phdr.p_filesz) {
+            Some(end) if end <= size => {}
+            _ => return Err(Errno::ENOEXEC),
+        }
+
+        segments_size = segments_size.saturating_add(phdr.p_filesz);
     }
 
-    // TODO: set up the stack
-    unsafe {
-        *(0xFFFF_FFF0 as *mut u8) = 0;
+    // the segments are all read into the kernel heap at once, so they must fit with room to spare
+    let (_, heap_free) = heap_usage();
+    if heap_free < HEAP_RESERVE.saturating_add(segments_size) {
+        return Err(Errno::ENOMEM);
     }
 
-    // Yield control to the program and switch to user mode
+    // read the loadable segments first, since nothing may fail after the point of no return
+    let mut segments = Vec::new();
+    for phdr in phdrs.iter().filter(|phdr| phdr.p_type == PT_LOAD) {
+        let mut buf = BlockDataBuffer::new(phdr.p_filesz);
+        if f.read(phdr.p_offset, &mut buf)? != phdr.p_filesz {
+            return Err(Errno::ENOEXEC);
+        }
+        segments.push((phdr, buf));
+    }
+
+    // point of no return: get rid of the old program
     unsafe {
-        switch_to_user(ehdr.e_entry, 0xFFFF_FFF0, 0);
+        (*CURRENT_PROCESS).addr_space.clear();
     }
 
-    // should not get here!
-    0xDEAD_BEEF // ???
+    // load loadable segments into memory
+    // NOTE: new pages are zeroed, so we get the bss for free
+    let mut phdr_addr = None;
+
+    for (phdr, buf) in segments {
+        unsafe {
+            ptr::copy(buf.get_ptr(0), phdr.p_vaddr as *mut u8, buf.size());
+        }
+
+        // is the program header table part of this segment?
+        let end = phdr.p_offset.checked_add(phdr.p_filesz).unwrap_or(0);
+        if phdr.p_offset <= ehdr.e_phoff && ehdr.e_phoff < end {
+            phdr_addr = Some(phdr.p_vaddr + ehdr.e_phoff - phdr.p_offset);
+        }
+    }
+
+    // set up the stack
+    let mut auxv = Vec::new();
+    if let Some(addr) = phdr_addr {
+        auxv.push((AT_PHDR, addr));
+    }
+    auxv.push((AT_PHENT, size_of::<Elf32Phdr>()));
+    auxv.push((AT_PHNUM, ehdr.e_phnum as usize));
+    auxv.push((AT_PAGESZ, 0x1000));
+    auxv.push((AT_ENTRY, ehdr.e_entry));
+    auxv.push((AT_NULL, 0));
+
+    let esp = unsafe { setup_stack(argv, envp, &auxv) };
+
+    Ok((ehdr.e_entry, esp))
+}
+
+/// Copy the arguments, environment, and auxiliary vector to the user stack in the layout the
+/// System V ABI expects, and return the initial stack pointer.
+///
+/// ```text
+/// USER_STACK_TOP -> argument and environment strings
+///                   padding
+///                   auxiliary vector, ending with AT_NULL
+///                   NULL
+///                   envp[envc - 1] ... envp[0]
+///                   NULL
+///                   argv[argc - 1] ... argv[0]
+///           esp  -> argc
+/// ```
+unsafe fn setup_stack<S: AsRef<str>>(argv: &[S], envp: &[S], auxv: &[(usize, usize)]) -> usize {
+    let mut strings = USER_STACK_TOP;
+
+    let argv_ptrs = copy_strings(argv, &mut strings);
+    let envp_ptrs = copy_strings(envp, &mut strings);
+
+    // keep esp 16B aligned
+    let num_words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2 * auxv.len();
+    let esp = (strings - num_words * size_of::<usize>()) & !0xF;
+
+    let mut sp = esp as *mut usize;
+    let mut push = |word: usize| {
+        *sp = word;
+        sp = sp.offset(1);
+    };
+
+    push(argv.len());
+
+    for &addr in argv_ptrs.iter() {
+        push(addr);
+    }
+    push(0);
+
+    for &addr in envp_ptrs.iter() {
+        push(addr);
+    }
+    push(0);
+
+    for &(key, val) in auxv {
+        push(key);
+        push(val);
+    }
+
+    esp
+}
+
+/// Copy the given strings below `top` as NUL-terminated C strings and return their addresses.
+/// `top` is moved down past the copied strings.
+unsafe fn copy_strings<S: AsRef<str>>(strs: &[S], top: &mut usize) -> Vec<usize> {
+    let mut ptrs = Vec::with_capacity(strs.len());
+
+    for s in strs {
+        let bytes = s.as_ref().as_bytes();
+
+        *top -= bytes.len() + 1;
+
+        ptr::copy(bytes.as_ptr(), *top as *mut u8, bytes.len());
+        *((*top + bytes.len()) as *mut u8) = 0;
+
+        ptrs.push(*top);
+    }
+
+    ptrs
 }
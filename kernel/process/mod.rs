@@ -3,26 +3,37 @@
 pub use self::syscall::syscall_handler;
 
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::Vec;
 
 use core::fmt::{Debug, Formatter, Result};
+use core::mem;
+use core::ptr;
+use core::result;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use fs::ROOT_FS;
+use errno::Errno;
+use fs;
+use fs::vfs::{Credentials, VNode, ROOT, SUPERUSER};
 use interrupts::{esp0, no_interrupts, off};
 use io::NonBlockingBuffer;
-use machine::{self, context_switch};
+use machine::{self, context_switch, fork_return};
 use memory::AddressSpace;
 use static_linked_list::StaticLinkedList;
+use sync::Event;
 
-use self::context::KContext;
+use self::context::{KContext, UContext};
+use self::fd::FDTable;
 use self::idle::IDLE_PROCESS;
 use self::proc_table::PROCESS_TABLE;
 
 pub mod context;
+pub mod fd;
 pub mod focus;
 pub mod load;
 pub mod proc_table;
 pub mod ready_queue;
+pub mod signal;
 
 mod elf;
 mod idle;
@@ -54,7 +65,7 @@ pub enum CF {
 
 /// An enum representing the possible states of a process
 #[repr(C)]
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum State {
     /// Process is created, but not ready
     INIT,
@@ -64,6 +75,8 @@ pub enum State {
     RUNNING,
     /// Process is blocked on a q
     BLOCKED,
+    /// Process has exited, but its parent has not waited for it yet
+    ZOMBIE,
     /// Process has died
     TERMINATED,
 }
@@ -99,11 +112,30 @@ pub struct Process {
     /// A keyboard input buffer
     pub buffer: Option<NonBlockingBuffer>,
 
-    /// Current working file (inode number)
-    pub cwf: usize,
+    /// Current working file
+    pub cwf: VNode,
+
+    /// Path taken to the cwf (a stack of files), not including the cwf
+    pub path: StaticLinkedList<VNode>,
+
+    /// The files opened by this process
+    pub fds: FDTable,
+
+    /// The users and groups the process acts as
+    pub creds: Credentials,
+
+    /// The PID of the parent process, if it has one. Only forked processes have parents, and
+    /// processes are orphaned when their parent exits.
+    ppid: Option<usize>,
+
+    /// The PIDs of the children of this process that have not been waited for
+    children: StaticLinkedList<usize>,
+
+    /// The exit status of the process, valid once it has exited
+    exit_status: usize,
 
-    /// Path taken to the cwf (a stack of inode #s), not including the cwf
-    pub path: StaticLinkedList<usize>,
+    /// An event notified whenever a child of this process exits
+    child_event: Event,
 }
 
 impl Process {
@@ -122,8 +154,14 @@ impl Process {
             addr_space: AddressSpace::new(),
             disable_cnt: 0,
             buffer: None,
-            cwf: 0,
+            cwf: ROOT,
             path: StaticLinkedList::new(),
+            fds: FDTable::new(),
+            creds: SUPERUSER,
+            ppid: None,
+            children: StaticLinkedList::new(),
+            exit_status: 0,
+            child_event: Event::new(),
         };
 
         p.get_stack();
@@ -137,6 +175,37 @@ impl Process {
         process
     }
 
+    /// Create a child of this process, as in `fork`. The child gets a copy-on-write copy of this
+    /// process's address space and copies of its working file, open files, and credentials. This
+    /// process becomes the parent of the child and can `wait` for it. Rather than running
+    /// the routine of this process, the child starts by returning to usermode with the given
+    /// context, except that `fork` returns 0 to the child. As with `new`, a raw pointer to the
+    /// child is returned, and it is not made ready.
+    ///
+    /// NOTE: must run while this process's address space is active
+    pub fn fork(&mut self, context: &UContext) -> *mut Process {
+        let child = Process::new(self.name, self.run);
+
+        unsafe {
+            (*child).addr_space = self.addr_space.fork((*child).pid);
+            (*child).cwf = self.cwf;
+            for &node in self.path.iter() {
+                (*child).path.push_back(node);
+            }
+            (*child).fds = self.fds.clone();
+            (*child).creds = self.creds;
+
+            (*child).ppid = Some(self.pid);
+            self.children.push_back((*child).pid);
+
+            let mut child_context = *context;
+            child_context.eax = 0;
+            (*child).set_ucontext(child_context);
+        }
+
+        child
+    }
+
     /// A helper to get a kernel stack for this process
     fn get_stack(&mut self) {
         // TODO: fudge
@@ -159,6 +228,25 @@ impl Process {
         // printf!("stack for {:?} is at 0x{:x}\n", self, self.stack);
     }
 
+    /// A helper to smash the kernel stack of a new process so that it returns to usermode with the
+    /// given context instead of starting its routine
+    fn set_ucontext(&mut self, context: UContext) {
+        let stack_ptr = self.stack as *mut usize;
+        let words = mem::size_of::<UContext>() / mem::size_of::<usize>();
+
+        unsafe {
+            // put the context at the top of the stack
+            let context_ptr = stack_ptr.offset((STACK_SIZE - words) as isize) as *mut UContext;
+            ptr::write(context_ptr, context);
+
+            // put RA on stack to return to fork_return
+            let ra_ptr = stack_ptr.offset((STACK_SIZE - words - 1) as isize);
+            *ra_ptr = fork_return as usize;
+
+            self.kcontext.esp = ra_ptr as usize;
+        }
+    }
+
     /// Set the state of the process to `s`
     fn set_state(&mut self, s: State) {
         self.state = s;
@@ -174,6 +262,11 @@ impl Process {
         self.pid
     }
 
+    /// Get the name of this process
+    pub fn get_name(&self) -> &'static str {
+        self.name
+    }
+
     /// Start accepting keyboard input when this process
     /// gains focussed. The buffer will have the capacity
     /// given.
@@ -183,12 +276,86 @@ impl Process {
         }
     }
 
-    /// Return the inode number of the current working file
-    pub fn cwf(&self) -> usize {
+    /// Return the current working file
+    pub fn cwf(&self) -> VNode {
         self.cwf
     }
 
-    /// Change files to the given inode number if that file exists and is reachable from the cwf
+    /// The stack of files taken to reach the cwf, for looking up relative paths
+    fn parents(&self) -> Vec<VNode> {
+        self.path.iter().cloned().collect()
+    }
+
+    // NOTE: the paths passed to the methods below are either absolute or relative to the cwf, and
+    // are looked up with the credentials of the process.
+
+    /// Return the file with the given path
+    pub fn lookup(&self, path: &str) -> result::Result<VNode, Errno> {
+        let node = fs::lookup(path, self.cwf, &self.parents(), &self.creds)?;
+        Ok(node)
+    }
+
+    /// Create an empty regular file with the given path and permissions
+    pub fn create(&self, path: &str, mode: u32) -> result::Result<VNode, Errno> {
+        let node = fs::create(path, mode, self.cwf, &self.parents(), &self.creds)?;
+        Ok(node)
+    }
+
+    /// Create an empty directory with the given path
+    pub fn mkdir(&self, path: &str) -> result::Result<VNode, Errno> {
+        let node = fs::mkdir(path, self.cwf, &self.parents(), &self.creds)?;
+        Ok(node)
+    }
+
+    /// Remove the empty directory with the given path
+    pub fn rmdir(&self, path: &str) -> result::Result<(), Errno> {
+        fs::rmdir(path, self.cwf, &self.parents(), &self.creds)?;
+        Ok(())
+    }
+
+    /// Create a symbolic link to `target` with the given path
+    pub fn symlink(&self, target: &str, path: &str) -> result::Result<VNode, Errno> {
+        let node = fs::symlink(target, path, self.cwf, &self.parents(), &self.creds)?;
+        Ok(node)
+    }
+
+    /// Get the path the symbolic link with the given path points to
+    pub fn readlink(&self, path: &str) -> result::Result<String, Errno> {
+        let target = fs::readlink(path, self.cwf, &self.parents(), &self.creds)?;
+        Ok(target)
+    }
+
+    /// Link the file with the given path from the directory with the path `dir`
+    pub fn link(&self, path: &str, dir: &str) -> result::Result<(), Errno> {
+        fs::link(path, dir, self.cwf, &self.parents(), &self.creds)?;
+        Ok(())
+    }
+
+    /// Remove the link to the file with the given path, deleting the file if it was the last one
+    pub fn unlink(&self, path: &str) -> result::Result<(), Errno> {
+        fs::unlink(path, self.cwf, &self.parents(), &self.creds)?;
+        Ok(())
+    }
+
+    /// Set the permissions of the file with the given path
+    pub fn chmod(&self, path: &str, mode: u32) -> result::Result<(), Errno> {
+        fs::chmod(path, mode, self.cwf, &self.parents(), &self.creds)?;
+        Ok(())
+    }
+
+    /// Set the owner and group of the file with the given path. `None` keeps the current one.
+    pub fn chown(
+        &self,
+        path: &str,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> result::Result<(), Errno> {
+        fs::chown(path, uid, gid, self.cwf, &self.parents(), &self.creds)?;
+        Ok(())
+    }
+
+    /// Change files to the given inode number if that file exists and is linked from the cwf. The
+    /// inode number is on the file system of the cwf.
     pub fn cf(&mut self, new_cwf: CF) {
         match new_cwf {
             CF::Back => {
@@ -197,27 +364,17 @@ impl Process {
                 }
             }
             CF::Next(new) => {
-                // check if new exists
-                match unsafe { (*ROOT_FS).stat(new) } {
-                    Some(inode) => {
-                        // check if they are linked
-                        let cwf_inode = unsafe { (*ROOT_FS).stat(self.cwf()).unwrap() };
-                        let mut linked = false;
-
-                        for i in unsafe { inode.links.iter() } {
-                            if *i == self.cwf() {
-                                linked = true;
-                                break;
-                            }
-                        }
-
-                        // if linked actually change paths
-                        if linked {
-                            self.path.push_back(new);
-                            self.cwf = new;
-                        }
-                    }
-                    None => {}
+                // check if they are linked
+                let linked = match fs::inode(self.cwf).and_then(|cwf| cwf.links()) {
+                    Ok(links) => links.contains(&new),
+                    Err(_) => false,
+                };
+
+                // if linked actually change paths
+                if linked {
+                    let cwf = self.cwf;
+                    self.path.push_back(cwf);
+                    self.cwf = fs::cross(VNode::new(cwf.mount, new));
                 }
             }
         }
@@ -362,25 +519,127 @@ pub fn exit(code: usize) {
             panic!("Exiting with no current process!\n");
         }
 
-        (*CURRENT_PROCESS).set_state(State::TERMINATED);
+        bootlog!("{:?} [Exit 0x{:X}]\n", *CURRENT_PROCESS, code);
+
+        terminate((code & 0xFF) << 8);
+    }
+}
 
-        // clean up address space
-        (*CURRENT_PROCESS).addr_space.clear();
+/// Called to kill the current process with the given signal number (e.g. after a fault it caused)
+pub fn kill(signal: usize) {
+    unsafe {
+        if CURRENT_PROCESS.is_null() {
+            panic!("Killing with no current process!\n");
+        }
 
-        // Disable interrupts
-        off();
+        bootlog!("{:?} [Killed by signal {}]\n", *CURRENT_PROCESS, signal);
 
-        // NOTE: need to print *before* adding to reaper q
-        bootlog!("{:?} [Exit 0x{:X}]\n", *CURRENT_PROCESS, code);
+        terminate(signal & 0x7F);
+    }
+}
 
-        self::reaper::reaper_add(CURRENT_PROCESS);
+/// Terminate the current process with the given status (see `wait`). It becomes a zombie until its
+/// parent waits for it, or it is given to the reaper right away if it has no parent.
+unsafe fn terminate(status: usize) {
+    // clean up address space
+    (*CURRENT_PROCESS).addr_space.clear();
 
-        // set current to None, so we will never run this again
-        CURRENT_PROCESS = 0 as *mut Process;
+    // close all files
+    (*CURRENT_PROCESS).fds = FDTable::new();
+
+    // Disable interrupts
+    off();
+
+    // the status returned by `wait`
+    (*CURRENT_PROCESS).exit_status = status;
+
+    // nobody will wait for our children anymore
+    while let Some(child_pid) = (*CURRENT_PROCESS).children.pop_front() {
+        let child = PROCESS_TABLE.get(child_pid).expect("Child has already been reaped!");
+
+        if (*child).get_state() == State::ZOMBIE {
+            (*child).set_state(State::TERMINATED);
+            self::reaper::reaper_add(child);
+        } else {
+            (*child).ppid = None;
+        }
+    }
 
-        // switch to next ready process
-        _proc_yield(None);
+    // become a zombie until our parent waits for us
+    match (*CURRENT_PROCESS).ppid.and_then(|ppid| PROCESS_TABLE.get(ppid)) {
+        Some(parent) => {
+            (*CURRENT_PROCESS).set_state(State::ZOMBIE);
+            (*parent).child_event.notify();
+        }
+        None => {
+            (*CURRENT_PROCESS).set_state(State::TERMINATED);
+            self::reaper::reaper_add(CURRENT_PROCESS);
+        }
     }
 
+    // set current to None, so we will never run this again
+    CURRENT_PROCESS = 0 as *mut Process;
+
+    // switch to next ready process
+    _proc_yield(None);
+
     panic!("The impossible has happened!");
 }
+
+/// Called by the current process to wait for one of its children to exit. If `pid` is `Some`,
+/// only that child is waited for. Returns the PID and exit status of the child, and lets the
+/// reaper free it. If `block` is false, `None` is returned immediately if no child has exited
+/// yet. Returns `ECHILD` if there is no such child.
+pub fn wait(pid: Option<usize>, block: bool) -> result::Result<Option<(usize, usize)>, Errno> {
+    unsafe {
+        if CURRENT_PROCESS.is_null() {
+            panic!("Waiting with no current process!\n");
+        }
+
+        let me = &mut *CURRENT_PROCESS;
+
+        loop {
+            // look for a child that has exited
+            let zombie = no_interrupts(|| {
+                let mut found_child = false;
+
+                for (i, &child_pid) in me.children.iter().enumerate() {
+                    if pid.map_or(true, |pid| pid == child_pid) {
+                        found_child = true;
+
+                        let child = PROCESS_TABLE.get(child_pid).expect("Child has been reaped!");
+                        if (*child).get_state() == State::ZOMBIE {
+                            return Ok(Some(i));
+                        }
+                    }
+                }
+
+                if found_child {
+                    Ok(None)
+                } else {
+                    Err(Errno::ECHILD)
+                }
+            })?;
+
+            if let Some(i) = zombie {
+                // reap the child
+                let child_pid = me.children.remove(i);
+                let child = PROCESS_TABLE.get(child_pid).expect("Child has been reaped!");
+                let status = (*child).exit_status;
+
+                (*child).set_state(State::TERMINATED);
+                self::reaper::reaper_add(child);
+
+                return Ok(Some((child_pid, status)));
+            }
+
+            if !block {
+                return Ok(None);
+            }
+
+            // wait for some child to exit
+            me.child_event.wait();
+            me.child_event.reset();
+        }
+    }
+}
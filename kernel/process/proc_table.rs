@@ -1,6 +1,7 @@
 //! A module for the process table, a table mapping each PID to a process struct
 
 use alloc::boxed::Box;
+use alloc::Vec;
 
 use core::ops::{Index, IndexMut};
 
@@ -124,6 +125,37 @@ impl ProcessTable {
         }
     }
 
+    /// Get the PIDs of all processes in the table, in increasing order
+    pub fn pids(&self) -> Vec<usize> {
+        let mut pids = Vec::new();
+
+        // lock
+        unsafe {
+            TABLE_LOCK.down();
+        }
+
+        let mut node = self.pt_head;
+        let mut first = self.first_pid;
+
+        while !node.is_null() {
+            for i in 0..NODE_SIZE {
+                if first + i < self.size && unsafe { !(*node)[i].is_null() } {
+                    pids.push(first + i);
+                }
+            }
+
+            node = unsafe { (*node).next };
+            first += NODE_SIZE;
+        }
+
+        // unlock
+        unsafe {
+            TABLE_LOCK.up();
+        }
+
+        pids
+    }
+
     /// Free as many `ProcessTableNode`s as possible
     fn free(&mut self) {
         // lock
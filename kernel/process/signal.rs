@@ -0,0 +1,17 @@
+//! Signal numbers. For now, a signal is only used as the exit status of a process killed by the
+//! kernel (see `process::kill`).
+
+/// Illegal instruction
+pub const SIGILL: usize = 4;
+
+/// Trace or breakpoint trap
+pub const SIGTRAP: usize = 5;
+
+/// Bus error (bad memory access)
+pub const SIGBUS: usize = 7;
+
+/// Arithmetic error
+pub const SIGFPE: usize = 8;
+
+/// Invalid memory reference
+pub const SIGSEGV: usize = 11;
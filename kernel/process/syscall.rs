@@ -1,8 +1,92 @@
 //! A module for handling system calls
+//!
+//! The user passes the system call number in `%eax` and up to three arguments in `%ecx`, `%edx`,
+//! and `%ebx`. The value returned by the system call is placed in the user's `%eax`. If the
+//! system call fails, the negated `Errno` is returned instead.
 
+use alloc::{arc::Arc, string::String, Vec};
+
+use core::cmp::min;
+use core::ptr;
+
+use errno::Errno;
+use fs;
+use fs::vfs::{PERM_READ, PERM_WRITE};
 use interrupts::add_trap_handler;
-use machine::syscall_trap;
-use process;
+use io::block::BlockDataBuffer;
+use machine::{switch_to_user, syscall_trap};
+use memory::is_user_range;
+use process::{self, CURRENT_PROCESS};
+use time;
+
+use super::context::UContext;
+use super::fd::{Descriptor, SharedFile};
+use super::load::{self, MAX_ARG_SIZE};
+
+/// The maximum number of bytes moved by a single `read` or `write`
+const MAX_IO_SIZE: usize = 4096;
+
+/// The maximum length of a path in bytes
+const MAX_PATH: usize = 256;
+
+// Flags for `open`
+const O_RDONLY: usize = 0;
+const O_WRONLY: usize = 1;
+const O_RDWR: usize = 2;
+const O_ACCMODE: usize = 3;
+const O_CREAT: usize = 0o100;
+
+// Values of `whence` for `seek`
+const SEEK_SET: usize = 0;
+const SEEK_CUR: usize = 1;
+const SEEK_END: usize = 2;
+
+// Options for `waitpid`
+const WNOHANG: usize = 1;
+
+// Values of `d_type` in the entries returned by `getdents`
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+const DT_LNK: u8 = 10;
+
+/// The size of the fixed part of an entry returned by `getdents`: `d_ino` (4B), `d_reclen` (2B),
+/// and `d_type` (1B). The NUL-terminated name follows.
+const DIRENT_HEADER: usize = 7;
+
+/// A system call implementation. It takes the saved user context and the arguments passed in
+/// `%ecx`, `%edx`, and `%ebx`, and returns the value to pass back in `%eax` or an error.
+type Syscall = unsafe fn(&mut UContext, usize, usize, usize) -> Result<usize, Errno>;
+
+/// The system call table, indexed by system call number
+static SYSCALL_TABLE: [Syscall; 27] = [
+    sys_exit,         // 0
+    sys_start_tests,  // 1
+    sys_open,         // 2
+    sys_close,        // 3
+    sys_read,         // 4
+    sys_write,        // 5
+    sys_seek,         // 6
+    sys_fork,         // 7
+    sys_execve,       // 8
+    sys_waitpid,      // 9
+    sys_getdents,     // 10
+    sys_mkdir,        // 11
+    sys_rmdir,        // 12
+    sys_symlink,      // 13
+    sys_readlink,     // 14
+    sys_link,         // 15
+    sys_unlink,       // 16
+    sys_chmod,        // 17
+    sys_chown,        // 18
+    sys_getuid,       // 19
+    sys_geteuid,      // 20
+    sys_getgid,       // 21
+    sys_getegid,      // 22
+    sys_setuid,       // 23
+    sys_setgid,       // 24
+    sys_time,         // 25
+    sys_gettimeofday, // 26
+];
 
 /// Initialize the system call subsystem.
 /// Use IRQ 100 as the system call trap.
@@ -13,19 +97,591 @@ pub fn init() {
 /// The system call handler
 #[no_mangle]
 #[inline(never)]
-pub unsafe fn syscall_handler(_context: *mut usize, syscall_num: usize, a0: usize, _a1: usize) {
-    match syscall_num {
-        0 => {
-            // exit
-            process::exit(a0);
+pub unsafe fn syscall_handler(
+    context: *mut UContext,
+    syscall_num: usize,
+    a0: usize,
+    a1: usize,
+) -> usize {
+    let context = &mut *context;
+    let a2 = context.ebx;
+
+    let ret = match SYSCALL_TABLE.get(syscall_num) {
+        Some(&syscall) => syscall(context, a0, a1, a2),
+        None => {
+            printf!(
+                "{:?} [Unknown system call #{}]\n",
+                *CURRENT_PROCESS, syscall_num
+            );
+            Err(Errno::ENOSYS)
         }
-        1 => {
-            // run some tests
-            // TODO: get rid of this syscall
-            process::ready_queue::make_ready(process::Process::new("p0", process::user::run));
+    };
+
+    match ret {
+        Ok(val) => val,
+        Err(errno) => errno.to_syscall_ret(),
+    }
+}
+
+/// `exit(code)`: terminate the current process with the given exit code
+unsafe fn sys_exit(_: &mut UContext, code: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    process::exit(code);
+    unreachable!();
+}
+
+/// `start_tests()`: run some tests
+/// TODO: get rid of this syscall
+unsafe fn sys_start_tests(_: &mut UContext, _: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    process::ready_queue::make_ready(process::Process::new("p0", process::user::run));
+    Ok(0)
+}
+
+/// `open(path, flags, mode)`: open the file with the given path and return a file descriptor. With
+/// `O_CREAT`, an empty regular file with the permissions in `mode` (e.g. `0640`) is created if
+/// there is no file with the path yet. Relative paths start at the cwf of the process.
+unsafe fn sys_open(
+    _: &mut UContext,
+    path: usize,
+    flags: usize,
+    mode: usize,
+) -> Result<usize, Errno> {
+    if flags & !(O_ACCMODE | O_CREAT) != 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    let (readable, writable) = match flags & O_ACCMODE {
+        O_RDONLY => (true, false),
+        O_WRONLY => (false, true),
+        O_RDWR => (true, true),
+        _ => return Err(Errno::EINVAL),
+    };
+
+    let (node, created) = {
+        let path = copy_user_str(path, MAX_PATH, Errno::ENAMETOOLONG)?;
+
+        match (*CURRENT_PROCESS).lookup(&path) {
+            Err(Errno::ENOENT) if flags & O_CREAT != 0 => {
+                if mode & !0o777 != 0 {
+                    return Err(Errno::EINVAL);
+                }
+
+                ((*CURRENT_PROCESS).create(&path, mode as u32)?, true)
+            }
+            node => (node?, false),
         }
-        _ => {
-            panic!("system call #{}\n", syscall_num);
+    };
+
+    let inode = fs::inode(node)?;
+
+    let mut want = 0;
+    if readable {
+        want |= PERM_READ;
+    }
+    if writable {
+        want |= PERM_WRITE;
+    }
+
+    // the creator of a file may open it whatever its permissions
+    if !created && fs::access(&*inode, &(*CURRENT_PROCESS).creds, want)?.is_dir && writable {
+        return Err(Errno::EISDIR);
+    }
+
+    let file = inode.open(writable)?;
+
+    (*CURRENT_PROCESS)
+        .fds
+        .insert(Descriptor {
+            file: Arc::new(SharedFile::new(file)),
+            node: node,
+            readable: readable,
+            writable: writable,
+        })
+        .ok_or(Errno::EMFILE)
+}
+
+/// `close(fd)`: close the given file descriptor
+unsafe fn sys_close(_: &mut UContext, fd: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    // the file is closed when the last descriptor sharing it is dropped
+    match (*CURRENT_PROCESS).fds.remove(fd) {
+        Some(_) => Ok(0),
+        None => Err(Errno::EBADF),
+    }
+}
+
+/// `read(fd, buf, len)`: read up to `len` bytes from the file into the user buffer `buf`, and
+/// return the number of bytes read. 0 means end of file.
+unsafe fn sys_read(_: &mut UContext, fd: usize, buf: usize, len: usize) -> Result<usize, Errno> {
+    if !is_user_range(buf, len) {
+        return Err(Errno::EFAULT);
+    }
+
+    let desc = match (*CURRENT_PROCESS).fds.get(fd) {
+        Some(desc) if desc.readable => desc,
+        _ => return Err(Errno::EBADF),
+    };
+
+    let len = min(len, MAX_IO_SIZE);
+    if len == 0 {
+        return Ok(0);
+    }
+
+    // the offset is not locked during the read, which may block
+    let offset = *desc.file.offset.down();
+    let mut kbuf = BlockDataBuffer::new(len);
+    let num_read = desc.file.handle.read(offset, &mut kbuf)?;
+    *desc.file.offset.down() = offset + num_read;
+
+    if num_read > 0 {
+        ptr::copy(kbuf.get_ptr::<u8>(0), buf as *mut u8, num_read);
+    }
+
+    Ok(num_read)
+}
+
+/// `write(fd, buf, len)`: write up to `len` bytes from the user buffer `buf` to the file, and
+/// return the number of bytes written.
+unsafe fn sys_write(_: &mut UContext, fd: usize, buf: usize, len: usize) -> Result<usize, Errno> {
+    if !is_user_range(buf, len) {
+        return Err(Errno::EFAULT);
+    }
+
+    let desc = match (*CURRENT_PROCESS).fds.get(fd) {
+        Some(desc) if desc.writable => desc,
+        _ => return Err(Errno::EBADF),
+    };
+
+    let len = min(len, MAX_IO_SIZE);
+    if len == 0 {
+        return Ok(0);
+    }
+
+    let mut kbuf = BlockDataBuffer::new(len);
+    ptr::copy(buf as *const u8, kbuf.get_ptr_mut::<u8>(0), len);
+
+    let offset = *desc.file.offset.down();
+    let num_written = desc.file.handle.write(offset, len, &mut kbuf)?;
+    *desc.file.offset.down() = offset + num_written;
+
+    Ok(num_written)
+}
+
+/// `seek(fd, offset, whence)`: move the file offset of the given file descriptor and return the
+/// new offset. `offset` is relative to the start of the file, the current offset, or the end of
+/// the file, depending on `whence`.
+unsafe fn sys_seek(
+    _: &mut UContext,
+    fd: usize,
+    offset: usize,
+    whence: usize,
+) -> Result<usize, Errno> {
+    let desc = match (*CURRENT_PROCESS).fds.get(fd) {
+        Some(desc) => desc,
+        None => return Err(Errno::EBADF),
+    };
+
+    // the offset may be shared with other processes
+    let mut file_offset = desc.file.offset.down();
+    let size = desc.file.handle.size()?;
+
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => *file_offset,
+        SEEK_END => size,
+        _ => return Err(Errno::EINVAL),
+    };
+
+    // `offset` may be negative for SEEK_CUR and SEEK_END
+    let new_offset = match (base as isize).checked_add(offset as isize) {
+        Some(new_offset) if new_offset >= 0 => new_offset,
+        _ => return Err(Errno::EINVAL),
+    };
+
+    // seeking past the EOF seeks to the EOF
+    *file_offset = min(new_offset as usize, size);
+    Ok(*file_offset)
+}
+
+/// `fork()`: create a copy of the current process. Returns the PID of the child to the parent,
+/// and 0 to the child.
+unsafe fn sys_fork(context: &mut UContext, _: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    let child = (*CURRENT_PROCESS).fork(context);
+    let pid = (*child).get_pid();
+
+    process::ready_queue::make_ready(child);
+
+    Ok(pid)
+}
+
+/// `execve(path, argv, envp)`: replace the current program with the program in the file with the
+/// given path. `argv` and `envp` are NULL-terminated arrays of strings to pass to the new program.
+/// Only returns if there is an error.
+unsafe fn sys_execve(
+    _: &mut UContext,
+    path: usize,
+    argv: usize,
+    envp: usize,
+) -> Result<usize, Errno> {
+    let (entry, esp) = {
+        // copy everything out of the old address space before it is cleared
+        let path = copy_user_str(path, MAX_PATH, Errno::ENAMETOOLONG)?;
+        let argv = copy_user_strs(argv)?;
+        let envp = copy_user_strs(envp)?;
+
+        load::load(&path, &argv, &envp)?
+    }; // the copies are freed here, since we never return
+
+    switch_to_user(entry, esp, 0);
+
+    unreachable!();
+}
+
+/// `waitpid(pid, status, options)`: wait for the child with the given PID to exit, or for any
+/// child if `pid` is -1. If `status` is not NULL, the exit status of the child is stored there.
+/// Returns the PID of the child. If `options` contains `WNOHANG`, 0 is returned instead of
+/// blocking if no child has exited yet.
+unsafe fn sys_waitpid(
+    _: &mut UContext,
+    pid: usize,
+    status: usize,
+    options: usize,
+) -> Result<usize, Errno> {
+    let pid = match pid as isize {
+        -1 => None,
+        pid if pid > 0 => Some(pid as usize),
+        _ => return Err(Errno::EINVAL),
+    };
+
+    if options & !WNOHANG != 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    if status != 0 && !is_user_range(status, 4) {
+        return Err(Errno::EFAULT);
+    }
+
+    match process::wait(pid, options & WNOHANG == 0)? {
+        Some((child_pid, child_status)) => {
+            if status != 0 {
+                *(status as *mut u32) = child_status as u32;
+            }
+            Ok(child_pid)
         }
+        None => Ok(0),
+    }
+}
+
+/// `getdents(fd, buf, len)`: fill the user buffer `buf` of `len` bytes with entries of the
+/// directory open as `fd`, starting after the last entry returned, and return the number of bytes
+/// used. 0 means all entries have been returned. Each entry is a `struct dirent` (see `sys.h`)
+/// padded to a multiple of 4 bytes. `.` and `..` are not listed, since they depend on the path
+/// taken to the directory.
+unsafe fn sys_getdents(
+    _: &mut UContext,
+    fd: usize,
+    buf: usize,
+    len: usize,
+) -> Result<usize, Errno> {
+    if !is_user_range(buf, len) {
+        return Err(Errno::EFAULT);
+    }
+
+    let desc = match (*CURRENT_PROCESS).fds.get(fd) {
+        Some(desc) if desc.readable => desc,
+        _ => return Err(Errno::EBADF),
+    };
+
+    // the directory is read again on every call, so resume by inode number rather than index
+    let mut entries = fs::readdir(desc.node)?;
+    entries.sort_by_key(|entry| entry.inode);
+
+    let mut next = desc.file.dirent.down();
+    let start = *next;
+    let mut used = 0;
+
+    for entry in entries.iter().filter(|entry| entry.inode >= start) {
+        let reclen = (DIRENT_HEADER + entry.name.len() + 1 + 3) & !3;
+
+        if used + reclen > len {
+            // the buffer must hold at least one entry
+            if used == 0 {
+                return Err(Errno::EINVAL);
+            }
+            break;
+        }
+
+        let rec = (buf + used) as *mut u8;
+        *(rec as *mut u32) = entry.inode as u32;
+        *(rec.offset(4) as *mut u16) = reclen as u16;
+        *rec.offset(6) = if entry.is_dir {
+            DT_DIR
+        } else if entry.is_symlink {
+            DT_LNK
+        } else {
+            DT_REG
+        };
+
+        let name = rec.offset(DIRENT_HEADER as isize);
+        ptr::copy(entry.name.as_ptr(), name, entry.name.len());
+        ptr::write_bytes(
+            name.offset(entry.name.len() as isize),
+            0,
+            reclen - DIRENT_HEADER - entry.name.len(),
+        );
+
+        used += reclen;
+        *next = entry.inode + 1;
+    }
+
+    Ok(used)
+}
+
+/// `mkdir(path)`: create an empty directory with the given path. Relative paths start at the cwf
+/// of the process.
+unsafe fn sys_mkdir(_: &mut UContext, path: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    let path = copy_user_str(path, MAX_PATH, Errno::ENAMETOOLONG)?;
+    (*CURRENT_PROCESS).mkdir(&path)?;
+    Ok(0)
+}
+
+/// `rmdir(path)`: remove the empty directory with the given path. Relative paths start at the cwf
+/// of the process.
+unsafe fn sys_rmdir(_: &mut UContext, path: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    let path = copy_user_str(path, MAX_PATH, Errno::ENAMETOOLONG)?;
+    (*CURRENT_PROCESS).rmdir(&path)?;
+    Ok(0)
+}
+
+/// `symlink(target, path)`: create a symbolic link with the given path to the path `target`.
+/// Relative paths start at the cwf of the process, but `target` is only looked up when the link is
+/// followed.
+unsafe fn sys_symlink(
+    _: &mut UContext,
+    target: usize,
+    path: usize,
+    _: usize,
+) -> Result<usize, Errno> {
+    let target = copy_user_str(target, MAX_PATH, Errno::ENAMETOOLONG)?;
+    let path = copy_user_str(path, MAX_PATH, Errno::ENAMETOOLONG)?;
+    (*CURRENT_PROCESS).symlink(&target, &path)?;
+    Ok(0)
+}
+
+/// `readlink(path, buf, len)`: copy the path the symbolic link with the given path points to into
+/// the user buffer `buf`, and return its length. The path is not NUL-terminated, and it is
+/// truncated to `len` bytes.
+unsafe fn sys_readlink(
+    _: &mut UContext,
+    path: usize,
+    buf: usize,
+    len: usize,
+) -> Result<usize, Errno> {
+    if !is_user_range(buf, len) {
+        return Err(Errno::EFAULT);
+    }
+
+    let target = {
+        let path = copy_user_str(path, MAX_PATH, Errno::ENAMETOOLONG)?;
+        (*CURRENT_PROCESS).readlink(&path)?
+    };
+
+    let len = min(len, target.len());
+    ptr::copy(target.as_ptr(), buf as *mut u8, len);
+
+    Ok(len)
+}
+
+/// `link(path, dir)`: link the file with the given path from the directory with the path `dir`,
+/// under the same name. A symbolic link is linked itself.
+unsafe fn sys_link(_: &mut UContext, path: usize, dir: usize, _: usize) -> Result<usize, Errno> {
+    let path = copy_user_str(path, MAX_PATH, Errno::ENAMETOOLONG)?;
+    let dir = copy_user_str(dir, MAX_PATH, Errno::ENAMETOOLONG)?;
+    (*CURRENT_PROCESS).link(&path, &dir)?;
+    Ok(0)
+}
+
+/// `unlink(path)`: remove the link to the file with the given path, and delete the file if
+/// nothing links to it anymore
+unsafe fn sys_unlink(_: &mut UContext, path: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    let path = copy_user_str(path, MAX_PATH, Errno::ENAMETOOLONG)?;
+    (*CURRENT_PROCESS).unlink(&path)?;
+    Ok(0)
+}
+
+/// `chmod(path, mode)`: set the permissions of the file with the given path to the `rwx` bits in
+/// `mode` (e.g. `0750`). Only the owner of the file and the superuser may do this.
+unsafe fn sys_chmod(_: &mut UContext, path: usize, mode: usize, _: usize) -> Result<usize, Errno> {
+    if mode & !0o777 != 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    let path = copy_user_str(path, MAX_PATH, Errno::ENAMETOOLONG)?;
+    (*CURRENT_PROCESS).chmod(&path, mode as u32)?;
+    Ok(0)
+}
+
+/// `chown(path, uid, gid)`: set the owner and group of the file with the given path. -1 keeps the
+/// current owner or group. Only the superuser may change the owner.
+unsafe fn sys_chown(_: &mut UContext, path: usize, uid: usize, gid: usize) -> Result<usize, Errno> {
+    let path = copy_user_str(path, MAX_PATH, Errno::ENAMETOOLONG)?;
+    (*CURRENT_PROCESS).chown(&path, id_arg(uid), id_arg(gid))?;
+    Ok(0)
+}
+
+/// `getuid()`: return the real user of the current process
+unsafe fn sys_getuid(_: &mut UContext, _: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    Ok((*CURRENT_PROCESS).creds.uid as usize)
+}
+
+/// `geteuid()`: return the effective user of the current process
+unsafe fn sys_geteuid(_: &mut UContext, _: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    Ok((*CURRENT_PROCESS).creds.euid as usize)
+}
+
+/// `getgid()`: return the real group of the current process
+unsafe fn sys_getgid(_: &mut UContext, _: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    Ok((*CURRENT_PROCESS).creds.gid as usize)
+}
+
+/// `getegid()`: return the effective group of the current process
+unsafe fn sys_getegid(_: &mut UContext, _: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    Ok((*CURRENT_PROCESS).creds.egid as usize)
+}
+
+/// `setuid(uid)`: set the user of the current process. The superuser sets both the real and
+/// effective user, and can never get its privileges back. Other users may only set the effective
+/// user back to the real one.
+unsafe fn sys_setuid(_: &mut UContext, uid: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    let uid = id_arg(uid).ok_or(Errno::EINVAL)?;
+    let creds = &mut (*CURRENT_PROCESS).creds;
+
+    if creds.euid == 0 {
+        creds.uid = uid;
+        creds.euid = uid;
+    } else if uid == creds.uid {
+        creds.euid = uid;
+    } else {
+        return Err(Errno::EPERM);
+    }
+
+    Ok(0)
+}
+
+/// `setgid(gid)`: set the group of the current process, like `setuid` sets the user
+unsafe fn sys_setgid(_: &mut UContext, gid: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    let gid = id_arg(gid).ok_or(Errno::EINVAL)?;
+    let creds = &mut (*CURRENT_PROCESS).creds;
+
+    if creds.euid == 0 {
+        creds.gid = gid;
+        creds.egid = gid;
+    } else if gid == creds.gid {
+        creds.egid = gid;
+    } else {
+        return Err(Errno::EPERM);
+    }
+
+    Ok(0)
+}
+
+/// `time(t)`: return the current time in seconds since the Unix epoch, and also store it in `*t`
+/// unless `t` is NULL
+unsafe fn sys_time(_: &mut UContext, t: usize, _: usize, _: usize) -> Result<usize, Errno> {
+    if t != 0 && !is_user_range(t, 4) {
+        return Err(Errno::EFAULT);
+    }
+
+    let now = time::now();
+    if t != 0 {
+        *(t as *mut u32) = now;
+    }
+
+    Ok(now as usize)
+}
+
+/// `gettimeofday(tv, tz)`: store the current time in `*tv` as a `struct timeval` (seconds since
+/// the Unix epoch, then microseconds). Time zones are not supported, so `tz` must be NULL.
+unsafe fn sys_gettimeofday(
+    _: &mut UContext,
+    tv: usize,
+    tz: usize,
+    _: usize,
+) -> Result<usize, Errno> {
+    if tz != 0 {
+        return Err(Errno::EINVAL);
+    }
+    if !is_user_range(tv, 8) {
+        return Err(Errno::EFAULT);
+    }
+
+    let (secs, micros) = time::now_precise();
+    *(tv as *mut u32) = secs;
+    *((tv + 4) as *mut u32) = micros;
+
+    Ok(0)
+}
+
+/// Decode a user or group ID argument. -1 means none.
+fn id_arg(id: usize) -> Option<u32> {
+    if id as isize == -1 {
+        None
+    } else {
+        Some(id as u32)
+    }
+}
+
+/// Copy the NUL-terminated string at `addr` in user memory to the kernel. If the string is longer
+/// than `max` bytes, `too_long` is returned.
+unsafe fn copy_user_str(addr: usize, max: usize, too_long: Errno) -> Result<String, Errno> {
+    let mut bytes = Vec::new();
+
+    loop {
+        if !is_user_range(addr, bytes.len() + 1) {
+            return Err(Errno::EFAULT);
+        }
+
+        let byte = *((addr + bytes.len()) as *const u8);
+
+        if byte == 0 {
+            break;
+        } else if bytes.len() == max {
+            return Err(too_long);
+        }
+
+        bytes.push(byte);
+    }
+
+    String::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+}
+
+/// Copy the NULL-terminated array of strings at `addr` in user memory to the kernel. A NULL
+/// `addr` is treated as an empty array.
+unsafe fn copy_user_strs(addr: usize) -> Result<Vec<String>, Errno> {
+    let mut strs = Vec::new();
+    let mut size = 0;
+
+    if addr == 0 {
+        return Ok(strs);
+    }
+
+    loop {
+        if !is_user_range(addr, (strs.len() + 1) * 4) {
+            return Err(Errno::EFAULT);
+        }
+
+        let ptr = *((addr + strs.len() * 4) as *const usize);
+
+        if ptr == 0 {
+            return Ok(strs);
+        }
+
+        let s = copy_user_str(ptr, MAX_ARG_SIZE - size, Errno::E2BIG)?;
+
+        // count the string and its pointer
+        size += s.len() + 1 + 4;
+        if size > MAX_ARG_SIZE {
+            return Err(Errno::E2BIG);
+        }
+
+        strs.push(s);
     }
 }
@@ -8,7 +8,8 @@ use super::focus::focus;
 use super::proc_table::PROCESS_TABLE;
 use super::ready_queue;
 use super::Process;
-use fs::ROOT_FS;
+use fs;
+use fs::vfs::{ROOT, SUPERUSER};
 use io::block::BlockDataBuffer;
 use io::stream::InputStream;
 use sync::{Semaphore, StaticSemaphore};
@@ -249,19 +250,18 @@ fn run4(_: &Process) -> usize {
 fn run5(_: &Process) -> usize {
     // test the fs
 
-    let mut f = unsafe { (*ROOT_FS).open_read(0).ok().unwrap() };
+    let f = {
+        let node = fs::lookup("/root", ROOT, &[], &SUPERUSER).ok().unwrap();
+        fs::inode(node).ok().unwrap().open(false).ok().unwrap()
+    };
 
     let mut buf = BlockDataBuffer::new(512);
 
-    f.seek(512);
-
-    f.read(&mut buf);
+    f.read(512, &mut buf).ok().unwrap();
     let val3 = unsafe { *buf.get_ref::<usize>(0) };
 
-    f.seek(0);
-
     buf.set_offset(0);
-    f.read(&mut buf);
+    f.read(0, &mut buf).ok().unwrap();
     let val1 = unsafe { *buf.get_ref::<usize>(0) };
     let val2 = unsafe {
         (*buf.get_ref::<usize>(126) & 0xFFFF_0000) | (*buf.get_ref::<usize>(127) & 0x0000_FFFF)
@@ -275,7 +275,7 @@ fn run5(_: &Process) -> usize {
     let _ = write!(
         &mut b,
         "Read values from file {}: {:8X}, {:8X}, {:8X} ... ",
-        f.get_filename(),
+        f.name(),
         val1,
         val2,
         val3
@@ -286,8 +286,6 @@ fn run5(_: &Process) -> usize {
         let _ = write!(&mut b, "Failure :[");
     }
 
-    f.seek(516);
-
     let mut buf2 = BlockDataBuffer::new(32);
     // unsafe {
     //    *buf2.get_ref_mut::<usize>(0) = 0xCAFEBABE;
@@ -300,11 +298,10 @@ fn run5(_: &Process) -> usize {
     //    *buf2.get_ref_mut::<usize>(7) = 0xBAADFACE;
     // }
 
-    // f.write(32, &mut buf2);
+    // f.write(516, 32, &mut buf2);
 
-    f.seek(32);
     buf2.set_offset(0);
-    f.read(&mut buf2);
+    f.read(32, &mut buf2).ok().unwrap();
     // buf2.set_offset(0);
 
     // let btct = unsafe { *buf2.get_ref::<usize>(0) };
@@ -315,8 +312,7 @@ fn run5(_: &Process) -> usize {
 
     // unsafe { *buf2.get_ref_mut::<usize>(0) = btct+1 };
     //
-    // f.seek(32);
-    // f.write(4, &mut buf2);
+    // f.write(32, 4, &mut buf2);
 
     // let _ = write!(&mut b, "\nBoot #{}\n", btct);
 
@@ -0,0 +1,77 @@
+//! A module for the wall-clock time
+//!
+//! The date is read from the RTC once at boot, and the time since then is counted by the PIT
+//! clock, so the clock never goes backwards. It is not kept in sync with the RTC afterwards, so it drifts
+//! as much as the PIT does.
+
+use interrupts::pit;
+use io::rtc;
+
+/// The time at boot, in seconds since the Unix epoch
+static mut BOOT_TIME: u32 = 0;
+
+/// The value of the PIT clock when the RTC was read
+static mut BOOT_CLOCK: (usize, usize) = (0, 0);
+
+/// Read the date from the RTC. The PIT must already be running. If the RTC cannot be read or does
+/// not hold a real date, the clock starts at the epoch.
+pub fn init() {
+    let date = match rtc::read() {
+        Some(date) => date,
+        None => {
+            bootlog!("no valid rtc date - starting the clock at the epoch\n");
+            rtc::EPOCH
+        }
+    };
+
+    unsafe {
+        BOOT_TIME = date.epoch_seconds();
+        BOOT_CLOCK = pit::clock();
+    }
+
+    bootlog!(
+        "time inited - {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC\n",
+        date.year,
+        date.month,
+        date.day,
+        date.hour,
+        date.minute,
+        date.second
+    );
+}
+
+/// Get the current time in seconds since the Unix epoch
+pub fn now() -> u32 {
+    now_precise().0
+}
+
+/// Get the current time as seconds since the Unix epoch and microseconds since that second
+pub fn now_precise() -> (u32, u32) {
+    let (secs, micros) = since(unsafe { BOOT_CLOCK });
+    (unsafe { BOOT_TIME } + secs, micros)
+}
+
+/// Get the time since the PIT started, as seconds and microseconds
+pub fn uptime() -> (u32, u32) {
+    since((0, 0))
+}
+
+/// Get the time since the PIT clock had the value `start`, as seconds and microseconds
+fn since(start: (usize, usize)) -> (u32, u32) {
+    let hz = pit::hz();
+    if hz == 0 {
+        return (0, 0);
+    }
+
+    let (secs, ticks) = pit::clock();
+
+    // borrow a second if the clock is earlier in its second than `start`
+    let (secs, ticks) = if ticks >= start.1 {
+        (secs - start.0, ticks - start.1)
+    } else {
+        (secs - start.0 - 1, ticks + hz - start.1)
+    };
+    let micros = ticks * (1_000_000 / hz);
+
+    (secs as u32, micros as u32)
+}
@@ -1,6 +1,6 @@
 //! A module for primitive rectangle drawing
 
-pub use super::vga::Color;
+pub use super::vga::{Color, COLS, ROWS};
 
 use core::fmt::{Error, Write};
 
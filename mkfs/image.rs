@@ -1,326 +1,429 @@
 //! A simple module for creating an OFS disk image
 
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata as HostMetadata};
 use std::io::prelude::*;
-use std::io::BufWriter;
-use std::fs::File;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 use std::process::exit;
-use std::cmp::min;
-use std::mem;
+
+use ofs_core::{
+    Error, Inode, MemDevice, Metadata, OFSDate, Volume, DNODE_SIZE, FLAG_DIR, FLAG_SYMLINK,
+    MAX_FILE_BLOCKS,
+};
 
 /// The size of a sector in bytes
-pub const SECTOR_SIZE: u32 = 512; // bytes
+pub const SECTOR_SIZE: usize = ofs_core::BLOCK_SIZE;
 
-const INODE_SIZE: usize = 128; // bytes
-const DNODE_SIZE: usize = 512; // bytes
+/// The default number of inodes
+pub const DEFAULT_INODES: usize = SECTOR_SIZE * 8;
 
-/// Represents an OFS disk image, which can be written to a file
-pub struct OFSImage {
-    /// The number of inodes in the fs
-    num_inodes: u32,
+/// The default number of dnodes
+pub const DEFAULT_DNODES: usize = SECTOR_SIZE * 8;
 
-    /// The number of dnodes in the fs
-    num_dnodes: u32,
+/// The default size of the journal in sectors
+pub const JOURNAL_SECTORS: usize = 64;
 
-    /// A list of inodes
-    inodes: Vec<Option<Inode>>,
+/// The smallest journal in sectors, other than none at all. It holds the header and the 7 blocks
+/// of the kernel's largest transactions whose size does not depend on the number of links (e.g.
+/// creating a symbolic link with a long name: both bitmaps, the name, index, and data dnodes, and
+/// the blocks of the new inode and of its parent).
+pub const MIN_JOURNAL_SECTORS: usize = 8;
 
-    /// A list of dnodes
-    dnodes: Vec<Option<Dnode>>,
-}
+/// Represents an OFS disk image in memory, which can be written to a file
+pub struct OFSImage {
+    volume: Volume<MemDevice>,
 
-#[derive(Clone, Copy)]
-#[repr(C, packed)]
-pub struct Inode {
-    name: [u8; 12], // file name (up to 12B)
-    uid: u32, // owner UID
-    gid: u32, // group GID
-    user_perm: u8, // user permissions
-    group_perm: u8, // group permissions
-    all_perm: u8, // everyone permissions
-    flags: u8, // various flags
-    size: u32, // file size in bytes NOTE: for now this must a multiple of 4
-    data: u32, // index of Dnode with contents
-    created: u32, // date created
-    modified: u32, // date last modified
-    links: [u32; 22],
+    /// The inode numbers of the host files added so far, by (device, inode) on the host, so that
+    /// hard links on the host become links to the same file in the image
+    added: HashMap<(u64, u64), usize>,
 }
 
-impl Inode {
-    fn new() -> Inode {
-        Inode {
-            name: [0; 12],
-            uid: 0,
-            gid: 0,
-            user_perm: 0,
-            group_perm: 0,
-            all_perm: 0,
-            flags: 0,
-            size: 0,
-            data: 0,
-            created: 0,
-            modified: 0,
-            links: [0; 22],
+impl OFSImage {
+    /// Create a new OFSImage with the given numbers of inodes and dnodes, and the given journal
+    /// size in sectors. Without a journal, the kernel mounts the image read-only.
+    pub fn new(num_inodes: usize, num_dnodes: usize, num_journal: usize) -> OFSImage {
+        let meta = Metadata::new(num_inodes, num_dnodes, num_journal);
+        let device = MemDevice::new(Volume::<MemDevice>::size_in_blocks(&meta));
+
+        OFSImage {
+            volume: Volume::format(device, meta),
+            added: HashMap::new(),
         }
     }
-}
-
-#[derive(Copy)]
-#[repr(C, packed)]
-pub struct Dnode {
-    bytes: [u8; DNODE_SIZE], // contents
-}
 
-impl Clone for Dnode {
-    fn clone(&self) -> Dnode {
-        let mut cloned = [0; DNODE_SIZE];
-        for d in 0..cloned.len() {
-            cloned[d] = self.bytes[d];
-        }
-        Dnode { bytes: cloned }
+    /// Link the `a`th inode to the `b`th inode, so that `b` can be reached from `a` by name.
+    /// Returns false if `a` has no free link slots left.
+    pub fn link(&mut self, a: usize, b: usize) -> bool {
+        self.volume.add_link(a, b)
     }
-}
 
-impl OFSImage {
-    /// Create a new OFSImage with the given numbers of inodes and dnodes.
-    pub fn new(num_inodes: u32, num_dnodes: u32) -> OFSImage {
-        OFSImage {
-            num_inodes: num_inodes,
-            num_dnodes: num_dnodes,
-            inodes: vec![None; num_inodes as usize],
-            dnodes: vec![None; num_dnodes as usize],
+    /// Copy the directory tree (on the host machine) to the OFS image. The directory becomes the
+    /// root (inode 0), and every file or directory in a directory is linked from it. Directories
+    /// stay directories, and symbolic links stay symbolic links to the same path.
+    ///
+    /// Hard links on the host become links to the same file. A directory can only have one
+    /// parent, so reaching a directory twice (e.g. through a bind mount) is an error.
+    pub fn add_tree(&mut self, dir: &Path) {
+        if !dir.is_dir() {
+            println!("Error! No such directory '{}'.", dir.display());
+            exit(1);
         }
+
+        self.add(dir);
     }
 
-    /// Allocate an inode in the file system and return mutable reference to it.
-    pub fn alloc_inode(&mut self) -> &mut Inode {
-        let first_free = (0..self.num_inodes)
-            .find(|i| self.inodes.get_mut(*i as usize).unwrap().is_none())
-            .unwrap_or_else(|| panic!("No inodes left!")) as usize;
+    /// Copy the file, directory, or symbolic link (on the host machine) to the OFS image and
+    /// return its inode number, or None if it cannot be added. A directory becomes an empty
+    /// directory linked to its contents.
+    fn add(&mut self, path: &Path) -> Option<usize> {
+        let meta = fs::symlink_metadata(path).unwrap_or_else(|e| {
+            println!("Error! Cannot stat '{}': {}.", path.display(), e);
+            exit(1);
+        });
+
+        // hard links
+        if let Some(&inode) = self.added.get(&(meta.dev(), meta.ino())) {
+            if meta.is_dir() {
+                println!(
+                    "Error! Directory '{}' is reached twice, but a directory can only have one \
+                     parent.",
+                    path.display()
+                );
+                exit(1);
+            }
+
+            return Some(inode);
+        }
 
-        *self.inodes.get_mut(first_free).unwrap() = Some(Inode::new());
+        let contents = if meta.is_dir() {
+            Vec::new()
+        } else if meta.is_file() {
+            read_file(path)
+        } else if meta.file_type().is_symlink() {
+            read_symlink(path)
+        } else {
+            println!(
+                "Warning! Skipping '{}', which is not a file, directory, or symbolic link.",
+                path.display()
+            );
+            return None;
+        };
+
+        let index = self.add_inode(path, &meta, &contents);
+        self.added.insert((meta.dev(), meta.ino()), index);
+
+        if meta.is_dir() {
+            self.add_dir_contents(index, path);
+        }
 
-        self.inodes
-            .get_mut(first_free)
-            .unwrap()
-            .as_mut()
-            .unwrap()
+        Some(index)
     }
 
-    /// Allocate a dnode in the file system and return its dnode index.
-    pub fn alloc_dnode(&mut self) -> usize {
-        let first_free = (0..self.num_dnodes)
-            .find(|i| self.dnodes.get_mut(*i as usize).unwrap().is_none())
-            .unwrap_or_else(|| panic!("No dnodes left!")) as usize;
-
-        *self.dnodes.get_mut(first_free).unwrap() = Some(Dnode { bytes: [0; DNODE_SIZE] });
+    /// Add and link everything in the directory `dir` to the `index`th inode. Entries are added in
+    /// order of their names, so that the image does not depend on the order on the host.
+    fn add_dir_contents(&mut self, index: usize, dir: &Path) {
+        let mut entries: Vec<_> = fs::read_dir(dir)
+            .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+            .unwrap_or_else(|e| {
+                println!("Error! Cannot read directory '{}': {}.", dir.display(), e);
+                exit(1);
+            })
+            .into_iter()
+            .map(|entry| entry.path())
+            .collect();
+
+        entries.sort();
+
+        for path in entries {
+            let child = match self.add(&path) {
+                Some(child) => child,
+                None => continue,
+            };
 
-        first_free
-    }
+            // two hard links to the same file in one directory become a single link
+            if self.volume.get_inode(index).is_linked(child) {
+                continue;
+            }
 
-    /// Get the `i`th dnode.
-    ///
-    /// # Panics
-    /// if the `i`th dnode is not allocated
-    pub fn get_dnode(&mut self, i: u32) -> &mut Dnode {
-        self.dnodes
-            .get_mut(i as usize)
-            .unwrap()
-            .as_mut()
-            .unwrap_or_else(|| panic!("dnode {} is not allocated", i))
+            if !self.link(index, child) {
+                println!(
+                    "Warning! No links left in '{}' for '{}'.",
+                    dir.display(),
+                    path.display()
+                );
+            }
+        }
     }
 
-    /// Copy the file (on the host machine) to the OFS image. No guarantees are made about
-    /// the order of inodes or dnodes chosen.
-    pub fn add_file(&mut self, file: &str) {
-        // open the file and read it
-        if !Path::new(file).is_file() {
-            println!("Error! No such file or directory '{}'.", file);
+    /// Create an inode with the given contents, and the name, owner, permissions, and dates of
+    /// the given host file, and return its inode number.
+    fn add_inode(&mut self, path: &Path, meta: &HostMetadata, contents: &[u8]) -> usize {
+        if contents.len() > MAX_FILE_BLOCKS * DNODE_SIZE {
+            println!("Error! File too large '{}'.", path.display());
             exit(1);
         }
-        let mut f = File::open(file).ok().unwrap();
-
-        // get the file size (bytes)
-        let size = f.metadata().ok().unwrap().len();
-
-        // create the dnodes
-        let mut remaining = size;
-        let mut first_dnode = 0;
-
-        let mut next_dnode = first_dnode;
-        while remaining > DNODE_SIZE as u64 {
-            // allocate a dnode
-            let prev_dnode = next_dnode;
-            next_dnode = self.alloc_dnode();
-            if remaining == size {
-                // don't forget where the file begins ;)
-                first_dnode = next_dnode;
-            } else {
-                // chain the dnodes
-                let dnode = self.get_dnode(prev_dnode as u32);
-                dnode.bytes[DNODE_SIZE - 1] = ((next_dnode >> 24) & 0xFF) as u8;
-                dnode.bytes[DNODE_SIZE - 2] = ((next_dnode >> 16) & 0xFF) as u8;
-                dnode.bytes[DNODE_SIZE - 3] = ((next_dnode >> 8) & 0xFF) as u8;
-                dnode.bytes[DNODE_SIZE - 4] = ((next_dnode) & 0xFF) as u8;
-            }
 
-            // read DNODE_SIZE - 4 bytes
-            let mut buf = [0; DNODE_SIZE - 4]; // last 32-bit word is for the next-ptr
-            let _ = f.read_exact(&mut buf).ok().unwrap();
+        // `/` has no name, and a symbolic link is not followed to get its name
+        let name = if meta.file_type().is_symlink() {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        } else {
+            path.canonicalize()
+                .expect("Cannot canonicalize file path!")
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+
+        // Empty files have no dnodes at all. Hosts do not always record when a file was created,
+        // so the modification date is used for both dates.
+        let index = self.volume.alloc_inode().unwrap_or_else(|e| fail(e, path));
+
+        let mut inode = Inode::new();
+        inode.uid = meta.uid();
+        inode.gid = meta.gid();
+        inode.user_perm = ((meta.mode() >> 6) & 7) as u8;
+        inode.group_perm = ((meta.mode() >> 3) & 7) as u8;
+        inode.all_perm = (meta.mode() & 7) as u8;
+        inode.created = date(meta.mtime());
+        inode.modified = date(meta.mtime());
+
+        if meta.is_dir() {
+            inode.flags |= FLAG_DIR;
+        } else if meta.file_type().is_symlink() {
+            inode.flags |= FLAG_SYMLINK;
+        }
 
-            // copy to the dnode
-            let mut dnode_bytes = [0; DNODE_SIZE];
-            for i in 0..(DNODE_SIZE - 4) {
-                dnode_bytes[i] = buf[i];
-            }
+        self.volume
+            .set_name(&mut inode, name.as_bytes())
+            .unwrap_or_else(|e| fail(e, path));
+        self.volume
+            .write_file(&mut inode, 0, contents)
+            .unwrap_or_else(|e| fail(e, path));
+        self.volume.write_inode(index, &inode);
 
-            let dnode = self.get_dnode(next_dnode as u32);
-            dnode.bytes = dnode_bytes;
+        println!("Added file '{}' to image.", path.display());
 
-            // update counter
-            remaining -= (DNODE_SIZE - 4) as u64;
-        }
+        index
+    }
 
-        // create the last dnode
-        let last_dnode_idx = self.alloc_dnode() as u32;
-        {
-            let last_dnode = self.get_dnode(last_dnode_idx);
-            let _ = f.read_exact(&mut last_dnode.bytes);
-        }
+    /// Write the bytes of this OFSImage to the given file.
+    pub fn burn(&self, file: &str) {
+        let mut outfile =
+            File::create(file).unwrap_or_else(|_| panic!("Cannot write to file '{}'", file));
 
-        if size > DNODE_SIZE as u64 {
-            let second_last = self.get_dnode(next_dnode as u32);
-            second_last.bytes[DNODE_SIZE - 1] = ((last_dnode_idx >> 24) & 0xFF) as u8;
-            second_last.bytes[DNODE_SIZE - 2] = ((last_dnode_idx >> 16) & 0xFF) as u8;
-            second_last.bytes[DNODE_SIZE - 3] = ((last_dnode_idx >> 8) & 0xFF) as u8;
-            second_last.bytes[DNODE_SIZE - 4] = ((last_dnode_idx) & 0xFF) as u8;
-        }
+        let _ = outfile.write_all(self.volume.device.as_bytes());
 
-        // create the inode
-        let inode = self.alloc_inode();
+        println!("Burned image to file '{}'.", file);
+    }
+}
 
-        let basename_str = Path::new(file)
-            .canonicalize()
-            .expect("Cannot canonicalize file path!")
-            .file_name()
-            .unwrap()
-            .to_os_string();
+/// Read the contents of the given host file
+fn read_file(path: &Path) -> Vec<u8> {
+    let mut contents = Vec::new();
 
-        let basename = basename_str.to_str()
-            .unwrap();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_end(&mut contents)) {
+        println!("Error! Cannot read '{}': {}.", path.display(), e);
+        exit(1);
+    }
 
-        inode.size = size as u32;
-        for i in 0..min(12, basename.len()) {
-            inode.name[i] = basename.as_bytes()[i];
+    contents
+}
+
+/// Read the path the given host symbolic link points to
+fn read_symlink(path: &Path) -> Vec<u8> {
+    match fs::read_link(path) {
+        Ok(target) => target.as_os_str().as_bytes().to_vec(),
+        Err(e) => {
+            println!("Error! Cannot read '{}': {}.", path.display(), e);
+            exit(1);
         }
-        inode.data = first_dnode as u32;
+    }
+}
 
-        println!("Added file '{}' to image.", basename);
+/// Convert a host timestamp to an OFS date. Dates that do not fit are clamped.
+fn date(secs: i64) -> OFSDate {
+    OFSDate(secs.max(0).min(u32::MAX as i64) as u32)
+}
+
+/// Report an error adding the given file to the image and exit
+fn fail(err: Error, path: &Path) -> ! {
+    match err {
+        Error::OutOfInodes => println!("Error! No inodes left for '{}'.", path.display()),
+        Error::OutOfDnodes => println!("Error! No dnodes left for '{}'.", path.display()),
+        Error::FileTooLarge => println!("Error! File too large '{}'.", path.display()),
+        Error::NameTooLong => println!("Error! File name too long '{}'.", path.display()),
+        Error::NotOFS | Error::UnsupportedVersion(_) => unreachable!(),
     }
 
-    /// Write the bytes of this OFSImage to the given file.
-    pub fn burn(&self, file: &str) {
-        let outfile = File::create(file)
-            .expect(format!("Cannot write to file '{}'", file).as_str());
-        let mut wbuf = BufWriter::new(outfile); // buffer writes
-
-        // metadata sector
-        let mut buf = [0; SECTOR_SIZE as usize];
-
-        // magic bytes
-        buf[0] = 'O' as u8;
-        buf[1] = 'F' as u8;
-        buf[2] = 'S' as u8;
-
-        // inode count
-        buf[7] = ((self.num_inodes >> 24) & 0xFF) as u8;
-        buf[6] = ((self.num_inodes >> 16) & 0xFF) as u8;
-        buf[5] = ((self.num_inodes >> 8) & 0xFF) as u8;
-        buf[4] = ((self.num_inodes) & 0xFF) as u8;
-
-        // dnode count
-        buf[11] = ((self.num_dnodes >> 24) & 0xFF) as u8;
-        buf[10] = ((self.num_dnodes >> 16) & 0xFF) as u8;
-        buf[9] = ((self.num_dnodes >> 8) & 0xFF) as u8;
-        buf[8] = ((self.num_dnodes) & 0xFF) as u8;
-
-        let _ = wbuf.write_all(&buf);
-
-        // inode bitmap
-        let inode_bitmap_sectors =
-            (self.num_inodes as f64 / SECTOR_SIZE as f64 / 8.0).ceil() as usize;
-        for i in 0..inode_bitmap_sectors {
-            buf = [0; SECTOR_SIZE as usize];
-
-            // create this section of the bit map
-            for j in 0..(SECTOR_SIZE * 8) as usize {
-                if self.inodes.get(i * SECTOR_SIZE as usize * 8 + j).unwrap().is_some() {
-                    buf[j / 8] |= (1 << (j % 8)) as u8;
-                }
-            }
+    exit(1);
+}
 
-            let _ = wbuf.write_all(&buf);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// A directory on the host to build an image from, removed again when dropped
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let path = env::temp_dir().join(format!("mkfs-{}-{}", process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir(&path).unwrap();
+            Scratch(path)
         }
 
-        // dnode bitmap
-        let dnode_bitmap_sectors =
-            (self.num_dnodes as f64 / SECTOR_SIZE as f64 / 8.0).ceil() as usize;
-        for i in 0..dnode_bitmap_sectors {
-            buf = [0; SECTOR_SIZE as usize];
-
-            // create this section of the bit map
-            for j in 0..(SECTOR_SIZE * 8) as usize {
-                if self.dnodes.get(i * SECTOR_SIZE as usize * 8 + j).unwrap().is_some() {
-                    buf[j / 8] |= (1 << (j % 8)) as u8;
-                }
-            }
+        /// Create a file with the given contents, and return its path
+        fn file(&self, name: &str, contents: &[u8]) -> PathBuf {
+            let path = self.0.join(name);
+            File::create(&path).unwrap().write_all(contents).unwrap();
+            path
+        }
 
-            let _ = wbuf.write_all(&buf);
+        /// Build an image of the directory
+        fn image(&self) -> OFSImage {
+            let mut image = OFSImage::new(64, 64, JOURNAL_SECTORS);
+            image.add_tree(&self.0);
+            image
         }
+    }
 
-        // inodes
-        let inodes_per_sector = SECTOR_SIZE as usize / INODE_SIZE;
-        let mut inode_ct = 0;
-        let mut inode_buf = [Inode::new(); SECTOR_SIZE as usize / INODE_SIZE];
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
 
-        for inode_opt in self.inodes.iter() {
-            inode_buf[inode_ct % inodes_per_sector] = match *inode_opt {
-                Some(ref inode) => (*inode).clone(),
-                None => Inode::new(),
-            };
+    /// Follow the given names from the root, and return the inode number reached
+    fn lookup(image: &mut OFSImage, names: &[&str]) -> usize {
+        names.iter().fold(0, |inode, name| {
+            image
+                .volume
+                .find_link(inode, name.as_bytes())
+                .unwrap_or_else(|| panic!("No '{}' in the image", name))
+        })
+    }
 
-            inode_ct += 1;
+    /// Read the contents of the given file in the image
+    fn contents(image: &mut OFSImage, inode: usize) -> Vec<u8> {
+        let inode = image.volume.get_inode(inode);
+        let mut buf = vec![0; inode.size];
+        image.volume.read_file(&inode, 0, &mut buf);
+        buf
+    }
 
-            if inode_ct % (SECTOR_SIZE as usize / INODE_SIZE) == 0 {
-                let _ = wbuf.write_all(unsafe {
-                    &mem::transmute::<_, [u8; SECTOR_SIZE as usize]>(inode_buf)
-                });
-            }
-        }
+    #[test]
+    fn copies_the_hierarchy() {
+        let dir = Scratch::new("hierarchy");
+        fs::create_dir_all(dir.0.join("a/b")).unwrap();
+        dir.file("a/b/deep", b"deep");
+        dir.file("a/a rather long name", b"long");
+        dir.file("top", &[7; 3 * DNODE_SIZE]);
 
-        // dnodes
-        let dnodes_per_sector = SECTOR_SIZE as usize / DNODE_SIZE;
-        let mut dnode_ct = 0;
-        let mut dnode_buf = [Dnode { bytes: [0; DNODE_SIZE] }; SECTOR_SIZE as usize / DNODE_SIZE];
+        let mut image = dir.image();
 
-        for dnode_opt in self.dnodes.iter() {
-            dnode_buf[dnode_ct % dnodes_per_sector] = match *dnode_opt {
-                Some(ref dnode) => (*dnode).clone(),
-                None => Dnode { bytes: [0; DNODE_SIZE] },
-            };
+        let root = image.volume.get_inode(0);
+        assert!(root.is_dir());
+        assert_eq!(root.links.iter().filter(|&&link| link != 0).count(), 2);
+        assert_eq!(root.nlink, 0);
 
-            dnode_ct += 1;
+        let a = lookup(&mut image, &["a"]);
+        let b = lookup(&mut image, &["a", "b"]);
+        assert!(image.volume.get_inode(a).is_dir());
+        assert!(image.volume.get_inode(b).is_dir());
+        assert_eq!(image.volume.get_inode(b).nlink, 1);
 
-            if dnode_ct % dnodes_per_sector == 0 {
-                let _ = wbuf.write_all(unsafe {
-                    &mem::transmute::<_, [u8; SECTOR_SIZE as usize]>(dnode_buf)
-                });
-            }
-        }
+        let deep = lookup(&mut image, &["a", "b", "deep"]);
+        assert!(!image.volume.get_inode(deep).is_dir());
+        assert_eq!(contents(&mut image, deep), b"deep");
 
-        // flush the buffer
-        let _ = wbuf.flush();
+        let long = lookup(&mut image, &["a", "a rather long name"]);
+        assert_eq!(contents(&mut image, long), b"long");
 
-        println!("Burned image to file '{}'.", file);
+        let top = lookup(&mut image, &["top"]);
+        assert_eq!(contents(&mut image, top), vec![7; 3 * DNODE_SIZE]);
+    }
+
+    #[test]
+    fn copies_owner_permissions_and_dates() {
+        let dir = Scratch::new("metadata");
+        let path = dir.file("file", b"");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000_000))
+            .unwrap();
+        let host = fs::metadata(&path).unwrap();
+
+        let mut image = dir.image();
+        let file = lookup(&mut image, &["file"]);
+        let inode = image.volume.get_inode(file);
+
+        assert_eq!((inode.uid, inode.gid), (host.uid(), host.gid()));
+        assert_eq!(
+            (inode.user_perm, inode.group_perm, inode.all_perm),
+            (6, 4, 0)
+        );
+        assert_eq!(inode.created, OFSDate(1_000_000_000));
+        assert_eq!(inode.modified, OFSDate(1_000_000_000));
+
+        // empty files have no dnodes
+        assert_eq!(inode.size, 0);
+        assert_eq!(inode.data, 0);
+    }
+
+    #[test]
+    fn hard_links_become_one_file() {
+        let dir = Scratch::new("hard-links");
+        fs::create_dir(dir.0.join("sub")).unwrap();
+        let original = dir.file("original", b"shared");
+        fs::hard_link(&original, dir.0.join("sub/copy")).unwrap();
+        fs::hard_link(&original, dir.0.join("twin")).unwrap();
+
+        let mut image = dir.image();
+        let file = lookup(&mut image, &["original"]);
+        assert_eq!(contents(&mut image, file), b"shared");
+
+        // the file keeps the name it was added with first
+        let sub = lookup(&mut image, &["sub"]);
+        assert!(image.volume.get_inode(sub).is_linked(file));
+        assert_eq!(lookup(&mut image, &["sub", "original"]), file);
+
+        // the two hard links in the root become a single link
+        let root = image.volume.get_inode(0);
+        assert_eq!(root.links.iter().filter(|&&link| link == file).count(), 1);
+        assert_eq!(image.volume.get_inode(file).nlink, 2);
+    }
+
+    #[test]
+    fn symbolic_links_are_not_followed() {
+        let dir = Scratch::new("symlinks");
+        fs::create_dir(dir.0.join("target")).unwrap();
+        dir.file("target/file", b"file");
+        symlink("target", dir.0.join("to target")).unwrap();
+        symlink("/nowhere/at/all", dir.0.join("dangling")).unwrap();
+
+        let mut image = dir.image();
+
+        let link = lookup(&mut image, &["to target"]);
+        let inode = image.volume.get_inode(link);
+        assert!(inode.is_symlink());
+        assert!(!inode.is_dir());
+        assert!(inode.links.iter().all(|&link| link == 0));
+        assert_eq!(contents(&mut image, link), b"target");
+
+        let dangling = lookup(&mut image, &["dangling"]);
+        assert_eq!(contents(&mut image, dangling), b"/nowhere/at/all");
     }
 }
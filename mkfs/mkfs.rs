@@ -1,23 +1,84 @@
 //! A simple tool for building OFS images
+//!
+//! ```text
+//! mkfs [-i <inodes>] [-d <dnodes>] [-j <journal sectors>] [-o <output>] <directory>
+//! ```
+//!
+//! The directory becomes the root of the image, and its contents are linked from it, recursively.
+//! An image with a journal of 0 sectors is mounted read-only by the kernel.
+
+extern crate ofs_core;
 
 mod image;
 
 use std::env::args;
+use std::path::Path;
+use std::process::exit;
 
 use image::OFSImage;
 
+fn usage() -> ! {
+    println!(
+        "Usage: mkfs [-i <inodes>] [-d <dnodes>] [-j <journal sectors>] [-o <output>] <directory>"
+    );
+    println!(
+        "Defaults: {} inodes, {} dnodes, {} journal sectors, output 'hdd.img'",
+        image::DEFAULT_INODES,
+        image::DEFAULT_DNODES,
+        image::JOURNAL_SECTORS
+    );
+    exit(1);
+}
+
+/// Parse the value of the given option as a number
+fn number(option: &str, value: Option<String>) -> usize {
+    match value.map(|value| value.parse()) {
+        Some(Ok(n)) if n <= u32::MAX as usize => n,
+        _ => {
+            println!("Error! '{}' needs a number.", option);
+            usage();
+        }
+    }
+}
+
 pub fn main() {
     // read command line args
-    let mut args = args();
-    args.next(); // drop the first arg (./mkfs)
+    let mut num_inodes = image::DEFAULT_INODES;
+    let mut num_dnodes = image::DEFAULT_DNODES;
+    let mut num_journal = image::JOURNAL_SECTORS;
+    let mut output = "hdd.img".to_string();
+    let mut dir = None;
+
+    let mut args = args().skip(1); // drop the first arg (./mkfs)
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-i" | "--inodes" => num_inodes = number(&arg, args.next()),
+            "-d" | "--dnodes" => num_dnodes = number(&arg, args.next()),
+            "-j" | "--journal" => num_journal = number(&arg, args.next()),
+            "-o" | "--output" => output = args.next().unwrap_or_else(|| usage()),
+            _ if dir.is_none() && !arg.starts_with('-') => dir = Some(arg),
+            _ => usage(),
+        }
+    }
 
-    // create a new image
-    let mut image = OFSImage::new(image::SECTOR_SIZE * 8, image::SECTOR_SIZE * 8);
+    let dir = dir.unwrap_or_else(|| usage());
+
+    // the root and the reserved dnode 0
+    if num_inodes < 1 || num_dnodes < 2 {
+        println!("Error! The image needs at least 1 inode and 2 dnodes.");
+        exit(1);
+    }
 
-    // add the files to the image
-    for arg in args {
-        image.add_file(arg.as_str());
+    if num_journal != 0 && num_journal < image::MIN_JOURNAL_SECTORS {
+        println!(
+            "Error! The journal needs at least {} sectors, or 0 for a read-only image.",
+            image::MIN_JOURNAL_SECTORS
+        );
+        exit(1);
     }
 
-    image.burn("hdd.img");
+    // create a new image and copy the tree to it
+    let mut image = OFSImage::new(num_inodes, num_dnodes, num_journal);
+    image.add_tree(Path::new(&dir));
+    image.burn(&output);
 }
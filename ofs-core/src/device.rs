@@ -0,0 +1,68 @@
+//! The devices OFS volumes are stored on
+
+#[cfg(any(test, feature = "std"))]
+use std::vec::Vec;
+
+#[cfg(any(test, feature = "std"))]
+use layout::BLOCK_SIZE;
+#[cfg(any(test, feature = "std"))]
+use volume::div_round_up;
+
+/// A device holding an OFS volume, which is read and written in blocks of `BLOCK_SIZE` bytes
+pub trait BlockDevice {
+    /// Read the given block into `buf`, which is `BLOCK_SIZE` long
+    fn read_block(&mut self, block: usize, buf: &mut [u8]);
+
+    /// Write the given block from `buf`, which is `BLOCK_SIZE` long
+    fn write_block(&mut self, block: usize, buf: &[u8]);
+}
+
+/// A device in memory, for building and inspecting images on the host
+#[cfg(any(test, feature = "std"))]
+pub struct MemDevice {
+    bytes: Vec<u8>,
+}
+
+#[cfg(any(test, feature = "std"))]
+impl MemDevice {
+    /// Create a zeroed device with the given number of blocks
+    pub fn new(num_blocks: usize) -> MemDevice {
+        MemDevice {
+            bytes: vec![0; num_blocks * BLOCK_SIZE],
+        }
+    }
+
+    /// Create a device holding the given image. A partial block at the end is padded with zeros.
+    pub fn from_bytes(mut bytes: Vec<u8>) -> MemDevice {
+        let padded = div_round_up(bytes.len(), BLOCK_SIZE) * BLOCK_SIZE;
+        bytes.resize(padded, 0);
+        MemDevice { bytes }
+    }
+
+    /// The number of blocks on the device
+    pub fn num_blocks(&self) -> usize {
+        self.bytes.len() / BLOCK_SIZE
+    }
+
+    /// The contents of the device
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+impl BlockDevice for MemDevice {
+    /// # Panics
+    ///
+    /// Panics if the block is past the end of the device
+    fn read_block(&mut self, block: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.bytes[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]);
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the block is past the end of the device
+    fn write_block(&mut self, block: usize, buf: &[u8]) {
+        self.bytes[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE].copy_from_slice(buf);
+    }
+}
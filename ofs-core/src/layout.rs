@@ -0,0 +1,414 @@
+//! The on-disk structures of OFS
+//!
+//! All integers are stored as 32-bit little-endian words, whatever the word size of the machine
+//! reading them. The structures are decoded into native types when they are read, so the kernel
+//! (32-bit) and the host tools (usually 64-bit) always agree on the layout.
+
+/// The size of a block in bytes
+pub const BLOCK_SIZE: usize = 512;
+
+/// The size of an inode in bytes
+pub const INODE_SIZE: usize = 128;
+
+/// The size of a dnode in bytes
+pub const DNODE_SIZE: usize = 512;
+
+/// The first bytes of the metadata block
+pub const MAGIC: [u8; 4] = [b'O', b'F', b'S', 0];
+
+/// The on-disk format version:
+///
+/// - 0: file names are at most 12B and are stored in the inode
+/// - 1: file names of up to `NAME_MAX` bytes; names longer than 12B are stored in a name dnode
+/// - 2: file contents are mapped through an index dnode rather than a chain of dnodes
+/// - 3: directories (`FLAG_DIR`), and a count of the links to each file (`Inode.nlink`)
+/// - 4: symbolic links (`FLAG_SYMLINK`)
+///
+/// Older volumes have to be rebuilt with mkfs.
+pub const OFS_VERSION: usize = 4;
+
+/// The maximum length of a file name in bytes
+pub const NAME_MAX: usize = 255;
+
+/// The maximum length of a file name that is stored in the inode itself
+pub const SHORT_NAME_MAX: usize = 12;
+
+/// The number of links in an inode
+pub const NUM_LINKS: usize = 21;
+
+/// The number of dnode numbers that fit in a dnode
+pub const PTRS_PER_DNODE: usize = DNODE_SIZE / 4;
+
+/// The number of direct pointers at the start of an index dnode
+pub const NUM_DIRECT: usize = PTRS_PER_DNODE - 2;
+
+/// The position of the indirect pointer in an index dnode
+pub const INDIRECT: usize = PTRS_PER_DNODE - 2;
+
+/// The position of the double indirect pointer in an index dnode
+pub const DOUBLE_INDIRECT: usize = PTRS_PER_DNODE - 1;
+
+/// The maximum number of dnodes holding the contents of a file
+pub const MAX_FILE_BLOCKS: usize = NUM_DIRECT + PTRS_PER_DNODE + PTRS_PER_DNODE * PTRS_PER_DNODE;
+
+/// `Inode.flags`: the name is stored in a name dnode rather than in the inode
+pub const FLAG_LONG_NAME: u8 = 1 << 0;
+
+/// `Inode.flags`: the file is a directory
+pub const FLAG_DIR: u8 = 1 << 1;
+
+/// `Inode.flags`: the file is a symbolic link, and its contents are the path it points to
+pub const FLAG_SYMLINK: u8 = 1 << 2;
+
+/// The first word of the journal header ("JRNL"). The second word is the number of blocks of a
+/// committed transaction that has not been completed yet, or 0.
+pub const JOURNAL_MAGIC: usize = 0x4C4E_524A;
+
+/// A 4B representation of the date, in seconds since the Unix epoch (1970-01-01 00:00 UTC)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OFSDate(pub u32);
+
+/// The metadata block of the volume (block 0)
+///
+/// ```text
+/// [ magic | num_inode | num_dnode | num_journal | version ]
+/// ```
+///
+/// NOTE: inode 0 is always the root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    /// The number of inodes
+    pub num_inode: usize,
+
+    /// The number of dnodes
+    pub num_dnode: usize,
+
+    /// The number of journal blocks; 0 => read-only volume
+    pub num_journal: usize,
+
+    /// The on-disk format version (see `OFS_VERSION`)
+    pub version: usize,
+}
+
+/// A single OFS inode (128B)
+///
+/// ```text
+/// [ name (12B) | uid | gid | user_perm, group_perm, all_perm, flags (1B each) | size | data |
+///   created | modified | links (21 words) | nlink ]
+/// ```
+///
+/// If the name is longer than 12B, the name field holds the number of the name dnode instead,
+/// and `FLAG_LONG_NAME` is set. The name dnode holds the name followed by a NUL.
+///
+/// The links of a directory (`FLAG_DIR`) are its entries, and each directory is linked from
+/// exactly one other directory, its parent (except for the root, which nothing links to). Other
+/// files may also link to each other, as an OFS extension. `nlink` is the number of files linking
+/// to this file.
+///
+/// The contents of a symbolic link (`FLAG_SYMLINK`) are the path it points to, without a NUL.
+/// The path may lead to a file on any file system mounted in the kernel, so it is not checked.
+///
+/// A file's contents are stored in whole dnodes, which are found through the file's index dnode
+/// (`data`). The index dnode holds the numbers of the file's first `NUM_DIRECT` dnodes, followed
+/// by the number of an indirect dnode and of a double indirect dnode:
+///
+/// ```text
+/// index dnode     [ direct 0 | ... | direct 125 | indirect | double indirect ]
+/// indirect        [ the next 128 dnodes of the file ]
+/// double indirect [ 128 indirect dnodes, each with the next 128 dnodes of the file ]
+/// ```
+///
+/// A pointer of 0 means that part of the file has no dnode yet and reads as zeros. Dnode 0 is
+/// reserved so that it is never allocated. An empty file has no index dnode at all. The file size
+/// is exact, so the last dnode may be only partly used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Inode {
+    pub name: [u8; SHORT_NAME_MAX], // file name (up to 12B) or name dnode #
+    pub uid: u32,                   // owner UID
+    pub gid: u32,                   // group GID
+    pub user_perm: u8,              // user permissions
+    pub group_perm: u8,             // group permissions
+    pub all_perm: u8,               // everyone permissions
+    pub flags: u8,                  // various flags
+    pub size: usize,                // file size in bytes
+    pub data: usize,                // index dnode #; 0 => no dnodes yet
+    pub created: OFSDate,           // date created
+    pub modified: OFSDate,          // date last modified
+    pub links: [usize; NUM_LINKS],  // linked inode #s; 0 => unused
+    pub nlink: usize,               // number of links to this file
+}
+
+/// Read the little-endian word at `offset` in `bytes`
+pub fn get_word(bytes: &[u8], offset: usize) -> usize {
+    (bytes[offset] as usize)
+        | (bytes[offset + 1] as usize) << 8
+        | (bytes[offset + 2] as usize) << 16
+        | (bytes[offset + 3] as usize) << 24
+}
+
+/// Write `word` as a little-endian word at `offset` in `bytes`
+///
+/// # Panics
+///
+/// Panics if `word` does not fit in 32 bits
+pub fn put_word(bytes: &mut [u8], offset: usize, word: usize) {
+    assert!(
+        word as u64 <= 0xFFFF_FFFF,
+        "{} does not fit in a word",
+        word
+    );
+
+    bytes[offset] = (word & 0xFF) as u8;
+    bytes[offset + 1] = ((word >> 8) & 0xFF) as u8;
+    bytes[offset + 2] = ((word >> 16) & 0xFF) as u8;
+    bytes[offset + 3] = ((word >> 24) & 0xFF) as u8;
+}
+
+impl Metadata {
+    /// The metadata of a new volume with the current format version
+    pub fn new(num_inode: usize, num_dnode: usize, num_journal: usize) -> Metadata {
+        Metadata {
+            num_inode,
+            num_dnode,
+            num_journal,
+            version: OFS_VERSION,
+        }
+    }
+
+    /// Decode the metadata block. Returns None if it does not start with the magic.
+    pub fn decode(bytes: &[u8]) -> Option<Metadata> {
+        if bytes[..4] != MAGIC {
+            return None;
+        }
+
+        Some(Metadata {
+            num_inode: get_word(bytes, 4),
+            num_dnode: get_word(bytes, 8),
+            num_journal: get_word(bytes, 12),
+            version: get_word(bytes, 16),
+        })
+    }
+
+    /// Encode the metadata into the start of `bytes`
+    pub fn encode(&self, bytes: &mut [u8]) {
+        bytes[..4].copy_from_slice(&MAGIC);
+        put_word(bytes, 4, self.num_inode);
+        put_word(bytes, 8, self.num_dnode);
+        put_word(bytes, 12, self.num_journal);
+        put_word(bytes, 16, self.version);
+    }
+}
+
+impl Inode {
+    /// An unnamed, empty file
+    pub fn new() -> Inode {
+        Inode {
+            name: [0; SHORT_NAME_MAX],
+            uid: 0,
+            gid: 0,
+            user_perm: 0,
+            group_perm: 0,
+            all_perm: 0,
+            flags: 0,
+            size: 0,
+            data: 0,
+            created: OFSDate(0),
+            modified: OFSDate(0),
+            links: [0; NUM_LINKS],
+            nlink: 0,
+        }
+    }
+
+    /// Decode the inode in the first `INODE_SIZE` bytes of `bytes`
+    pub fn decode(bytes: &[u8]) -> Inode {
+        let mut inode = Inode::new();
+
+        inode.name.copy_from_slice(&bytes[..SHORT_NAME_MAX]);
+        inode.uid = get_word(bytes, 12) as u32;
+        inode.gid = get_word(bytes, 16) as u32;
+        inode.user_perm = bytes[20];
+        inode.group_perm = bytes[21];
+        inode.all_perm = bytes[22];
+        inode.flags = bytes[23];
+        inode.size = get_word(bytes, 24);
+        inode.data = get_word(bytes, 28);
+        inode.created = OFSDate(get_word(bytes, 32) as u32);
+        inode.modified = OFSDate(get_word(bytes, 36) as u32);
+
+        for (i, link) in inode.links.iter_mut().enumerate() {
+            *link = get_word(bytes, 40 + 4 * i);
+        }
+        inode.nlink = get_word(bytes, 40 + 4 * NUM_LINKS);
+
+        inode
+    }
+
+    /// Encode the inode into the first `INODE_SIZE` bytes of `bytes`
+    pub fn encode(&self, bytes: &mut [u8]) {
+        bytes[..SHORT_NAME_MAX].copy_from_slice(&self.name);
+        put_word(bytes, 12, self.uid as usize);
+        put_word(bytes, 16, self.gid as usize);
+        bytes[20] = self.user_perm;
+        bytes[21] = self.group_perm;
+        bytes[22] = self.all_perm;
+        bytes[23] = self.flags;
+        put_word(bytes, 24, self.size);
+        put_word(bytes, 28, self.data);
+        put_word(bytes, 32, self.created.0 as usize);
+        put_word(bytes, 36, self.modified.0 as usize);
+
+        for (i, &link) in self.links.iter().enumerate() {
+            put_word(bytes, 40 + 4 * i, link);
+        }
+        put_word(bytes, 40 + 4 * NUM_LINKS, self.nlink);
+    }
+
+    /// Get the file name stored in the inode, without the NUL padding. If the file has a long
+    /// name, use `Volume::read_name` instead.
+    pub fn short_name(&self) -> &[u8] {
+        let len = self
+            .name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(SHORT_NAME_MAX);
+        &self.name[..len]
+    }
+
+    /// Returns true if the name of the file is stored in a name dnode
+    pub fn has_long_name(&self) -> bool {
+        self.flags & FLAG_LONG_NAME != 0
+    }
+
+    /// Returns true if the file is a directory
+    pub fn is_dir(&self) -> bool {
+        self.flags & FLAG_DIR != 0
+    }
+
+    /// Returns true if the file is a symbolic link
+    pub fn is_symlink(&self) -> bool {
+        self.flags & FLAG_SYMLINK != 0
+    }
+
+    /// Get the number of the name dnode. Only valid if the file has a long name.
+    pub fn name_dnode(&self) -> usize {
+        get_word(&self.name, 0)
+    }
+
+    /// Returns true if this file links to the `b`th inode
+    pub fn is_linked(&self, b: usize) -> bool {
+        b != 0 && self.links.contains(&b)
+    }
+
+    /// Add a link to the `b`th inode in the first unused slot. Returns false if all slots are used.
+    pub fn add_link(&mut self, b: usize) -> bool {
+        match self.links.iter().position(|&l| l == 0) {
+            Some(i) => {
+                self.links[i] = b;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the link to the `b`th inode, keeping the used links at the front. Returns false if
+    /// there is no such link (0 is never linked).
+    pub fn remove_link(&mut self, b: usize) -> bool {
+        let i = match self.links.iter().position(|&l| l == b) {
+            Some(i) if b != 0 => i,
+            _ => return false,
+        };
+
+        for j in i..(NUM_LINKS - 1) {
+            self.links[j] = self.links[j + 1];
+        }
+        self.links[NUM_LINKS - 1] = 0;
+
+        true
+    }
+}
+
+impl Default for Inode {
+    fn default() -> Inode {
+        Inode::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_round_trip() {
+        let meta = Metadata::new(4096, 2048, 64);
+        let mut bytes = [0; BLOCK_SIZE];
+        meta.encode(&mut bytes);
+
+        assert_eq!(&bytes[..8], &[b'O', b'F', b'S', 0, 0x00, 0x10, 0, 0]);
+        assert_eq!(Metadata::decode(&bytes), Some(meta));
+    }
+
+    #[test]
+    fn metadata_needs_magic() {
+        let bytes = [0; BLOCK_SIZE];
+        assert_eq!(Metadata::decode(&bytes), None);
+    }
+
+    #[test]
+    fn inode_round_trip() {
+        let mut inode = Inode::new();
+        inode.name[..4].copy_from_slice(b"test");
+        inode.uid = 1000;
+        inode.gid = 100;
+        inode.user_perm = 7;
+        inode.group_perm = 5;
+        inode.all_perm = 4;
+        inode.flags = 0x80;
+        inode.size = 0x1234_5678;
+        inode.data = 42;
+        inode.created = OFSDate(1);
+        inode.modified = OFSDate(2);
+        inode.links[0] = 3;
+        inode.links[NUM_LINKS - 1] = 0xFFFF_FFFF;
+        inode.nlink = 2;
+
+        let mut bytes = [0; INODE_SIZE];
+        inode.encode(&mut bytes);
+
+        assert_eq!(&bytes[24..28], &[0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(&bytes[120..124], &[0xFF; 4]);
+        assert_eq!(&bytes[124..128], &[2, 0, 0, 0]);
+        assert_eq!(Inode::decode(&bytes), inode);
+    }
+
+    #[test]
+    fn short_names_are_unpadded() {
+        let mut inode = Inode::new();
+        assert_eq!(inode.short_name(), b"");
+
+        inode.name[..4].copy_from_slice(b"root");
+        assert_eq!(inode.short_name(), b"root");
+
+        inode.name.copy_from_slice(b"twelve_bytes");
+        assert_eq!(inode.short_name(), b"twelve_bytes");
+    }
+
+    #[test]
+    fn links_stay_compact() {
+        let mut inode = Inode::new();
+        assert!(inode.add_link(1));
+        assert!(inode.add_link(2));
+        assert!(inode.add_link(3));
+
+        assert!(inode.remove_link(2));
+        assert!(!inode.remove_link(2));
+        assert!(!inode.remove_link(0));
+        assert_eq!(&inode.links[..3], &[1, 3, 0]);
+        assert!(inode.is_linked(3));
+        assert!(!inode.is_linked(0));
+
+        for i in 0..(NUM_LINKS - 2) {
+            assert!(inode.add_link(10 + i));
+        }
+        assert!(!inode.add_link(100));
+    }
+}
@@ -0,0 +1,29 @@
+//! The os1 file system (OFS), shared by the kernel and the host tools (mkfs)
+//!
+//! This crate defines the on-disk format of OFS (see `layout`) and the operations on a volume
+//! (see `Volume`). It does not allocate, so it can be used by the kernel, and it only needs a
+//! `BlockDevice` to read and write blocks, so it can be tested on the host against a `MemDevice`.
+//!
+//! A volume is a sequence of 512B blocks:
+//!
+//! ```text
+//! [ metadata | inode bitmap | dnode bitmap | inodes (128B each) | dnodes (512B each) | journal ]
+//! ```
+//!
+//! The journal is only used by the kernel (see `kernel/fs/ofs/journal.rs`).
+
+#![no_std]
+
+#[cfg(any(test, feature = "std"))]
+#[macro_use]
+extern crate std;
+
+mod device;
+mod layout;
+mod volume;
+
+pub use device::BlockDevice;
+#[cfg(any(test, feature = "std"))]
+pub use device::MemDevice;
+pub use layout::*;
+pub use volume::{div_round_up, Error, Volume};
@@ -0,0 +1,876 @@
+//! Operations on an OFS volume
+
+use core::cmp::min;
+
+use device::BlockDevice;
+use layout::*;
+
+/// The errors that operations on a volume can fail with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The device does not hold an OFS volume
+    NotOFS,
+
+    /// The volume has a format version other than `OFS_VERSION`
+    UnsupportedVersion(usize),
+
+    /// There are no free inodes
+    OutOfInodes,
+
+    /// There are no free dnodes
+    OutOfDnodes,
+
+    /// The file would be larger than `MAX_FILE_BLOCKS` dnodes
+    FileTooLarge,
+
+    /// The name is longer than `NAME_MAX`
+    NameTooLong,
+}
+
+/// An OFS volume on a block device
+///
+/// NOTE: all changes are written to the device immediately. It is up to the device to make
+/// sequences of changes atomic (e.g. the kernel's journal).
+pub struct Volume<D: BlockDevice> {
+    /// The device holding the volume
+    pub device: D,
+
+    /// The metadata of the volume
+    pub meta: Metadata,
+}
+
+/// The number of blocks needed to hold `bytes` bytes
+fn blocks_for(bytes: usize) -> usize {
+    div_round_up(bytes, BLOCK_SIZE)
+}
+
+/// The number of blocks needed for a bitmap of `bits` bits
+fn bitmap_blocks(bits: usize) -> usize {
+    blocks_for(div_round_up(bits, 8))
+}
+
+/// Divide `a` by `b`, rounding up. `usize::div_ceil` is too new for the kernel's compiler.
+pub fn div_round_up(a: usize, b: usize) -> usize {
+    match a % b {
+        0 => a / b,
+        _ => a / b + 1,
+    }
+}
+
+impl<D: BlockDevice> Volume<D> {
+    /// Make a new, empty volume on the given device, which must have at least
+    /// `Volume::size_in_blocks(&meta)` blocks.
+    pub fn format(device: D, meta: Metadata) -> Volume<D> {
+        let mut volume = Volume { device, meta };
+
+        let mut block = [0; BLOCK_SIZE];
+        volume.meta.encode(&mut block);
+        volume.device.write_block(0, &block);
+
+        // clear the bitmaps and the journal header
+        let block = [0; BLOCK_SIZE];
+        for i in 1..volume.first_inode_block() {
+            volume.device.write_block(i, &block);
+        }
+
+        if volume.meta.num_journal > 0 {
+            let journal_block = volume.journal_block();
+            volume.device.write_block(journal_block, &block);
+        }
+
+        // dnode 0 is reserved, since 0 means "no dnode" in an index
+        let dnode_bitmap_block = volume.dnode_bitmap_block();
+        volume.set_bit(dnode_bitmap_block, 0, true);
+
+        volume
+    }
+
+    /// Open the volume on the given device
+    pub fn open(mut device: D) -> Result<Volume<D>, Error> {
+        let mut block = [0; BLOCK_SIZE];
+        device.read_block(0, &mut block);
+
+        let meta = match Metadata::decode(&block) {
+            Some(meta) => meta,
+            None => return Err(Error::NotOFS),
+        };
+
+        if meta.version != OFS_VERSION {
+            return Err(Error::UnsupportedVersion(meta.version));
+        }
+
+        Ok(Volume { device, meta })
+    }
+
+    /// The number of blocks of a volume with the given metadata, including the journal
+    pub fn size_in_blocks(meta: &Metadata) -> usize {
+        1 + bitmap_blocks(meta.num_inode)
+            + bitmap_blocks(meta.num_dnode)
+            + blocks_for(meta.num_inode * INODE_SIZE)
+            + blocks_for(meta.num_dnode * DNODE_SIZE)
+            + meta.num_journal
+    }
+
+    /// Get the block number of the inode bitmap
+    pub fn inode_bitmap_block(&self) -> usize {
+        1
+    }
+
+    /// Get the block number of the dnode bitmap
+    pub fn dnode_bitmap_block(&self) -> usize {
+        self.inode_bitmap_block() + bitmap_blocks(self.meta.num_inode)
+    }
+
+    /// Get the block number of the first inode
+    pub fn first_inode_block(&self) -> usize {
+        self.dnode_bitmap_block() + bitmap_blocks(self.meta.num_dnode)
+    }
+
+    /// Get the block number of the first dnode
+    pub fn first_dnode_block(&self) -> usize {
+        self.first_inode_block() + blocks_for(self.meta.num_inode * INODE_SIZE)
+    }
+
+    /// Get the block number of the journal header. The journal comes after the last dnode.
+    pub fn journal_block(&self) -> usize {
+        self.first_dnode_block() + blocks_for(self.meta.num_dnode * DNODE_SIZE)
+    }
+
+    /// Get the block number of the `dnode`th dnode
+    pub fn dnode_block(&self, dnode: usize) -> usize {
+        self.first_dnode_block() + dnode * DNODE_SIZE / BLOCK_SIZE
+    }
+
+    /// Read `buf.len()` bytes at `offset` in the given block
+    fn read_bytes(&mut self, block: usize, offset: usize, buf: &mut [u8]) {
+        let mut contents = [0; BLOCK_SIZE];
+        self.device.read_block(block, &mut contents);
+        buf.copy_from_slice(&contents[offset..offset + buf.len()]);
+    }
+
+    /// Write `buf` at `offset` in the given block, keeping the rest of the block
+    fn write_bytes(&mut self, block: usize, offset: usize, buf: &[u8]) {
+        let mut contents = [0; BLOCK_SIZE];
+        if buf.len() < BLOCK_SIZE {
+            self.device.read_block(block, &mut contents);
+        }
+        contents[offset..offset + buf.len()].copy_from_slice(buf);
+        self.device.write_block(block, &contents);
+    }
+
+    /// Get the `index`th bit of the bitmap starting at `bitmap_block`
+    fn get_bit(&mut self, bitmap_block: usize, index: usize) -> bool {
+        let mut byte = [0; 1];
+        let block = bitmap_block + index / 8 / BLOCK_SIZE;
+        self.read_bytes(block, index / 8 % BLOCK_SIZE, &mut byte);
+        byte[0] & (1 << (index % 8)) != 0
+    }
+
+    /// Set the `index`th bit of the bitmap starting at `bitmap_block` to `value`
+    fn set_bit(&mut self, bitmap_block: usize, index: usize, value: bool) {
+        let mut byte = [0; 1];
+        let block = bitmap_block + index / 8 / BLOCK_SIZE;
+        let offset = index / 8 % BLOCK_SIZE;
+
+        self.read_bytes(block, offset, &mut byte);
+        if value {
+            byte[0] |= 1 << (index % 8);
+        } else {
+            byte[0] &= !(1 << (index % 8));
+        }
+        self.write_bytes(block, offset, &byte);
+    }
+
+    /// Find the first clear bit in the bitmap of `num_bits` bits starting at `bitmap_block`, set
+    /// it, and return its index. Returns None if all bits are set.
+    fn alloc_bit(&mut self, bitmap_block: usize, num_bits: usize) -> Option<usize> {
+        let mut contents = [0; BLOCK_SIZE];
+
+        for block in 0..bitmap_blocks(num_bits) {
+            self.device.read_block(bitmap_block + block, &mut contents);
+
+            for (i, byte) in contents.iter_mut().enumerate() {
+                if *byte == 0xFF {
+                    continue;
+                }
+
+                let bit = (0..8).find(|b| *byte & (1 << b) == 0).unwrap();
+                let index = (block * BLOCK_SIZE + i) * 8 + bit;
+                if index >= num_bits {
+                    return None;
+                }
+
+                *byte |= 1 << bit;
+                self.device.write_block(bitmap_block + block, &contents);
+
+                return Some(index);
+            }
+        }
+
+        None
+    }
+
+    /// Returns true if the `inode`th inode is not in use (or does not exist)
+    pub fn is_free_inode(&mut self, inode: usize) -> bool {
+        if inode >= self.meta.num_inode {
+            return true;
+        }
+
+        let bitmap_block = self.inode_bitmap_block();
+        !self.get_bit(bitmap_block, inode)
+    }
+
+    /// Returns true if the `dnode`th dnode is not in use (or does not exist)
+    pub fn is_free_dnode(&mut self, dnode: usize) -> bool {
+        if dnode >= self.meta.num_dnode {
+            return true;
+        }
+
+        let bitmap_block = self.dnode_bitmap_block();
+        !self.get_bit(bitmap_block, dnode)
+    }
+
+    /// Allocate a new inode and return its number. The inode is not initialized.
+    pub fn alloc_inode(&mut self) -> Result<usize, Error> {
+        let bitmap_block = self.inode_bitmap_block();
+        let num_inode = self.meta.num_inode;
+
+        self.alloc_bit(bitmap_block, num_inode)
+            .ok_or(Error::OutOfInodes)
+    }
+
+    /// Allocate a new dnode, fill it with zeros, and return its number
+    pub fn alloc_dnode(&mut self) -> Result<usize, Error> {
+        let bitmap_block = self.dnode_bitmap_block();
+        let num_dnode = self.meta.num_dnode;
+
+        let dnode = match self.alloc_bit(bitmap_block, num_dnode) {
+            Some(dnode) => dnode,
+            None => return Err(Error::OutOfDnodes),
+        };
+
+        let block = self.dnode_block(dnode);
+        self.device.write_block(block, &[0; BLOCK_SIZE]);
+
+        Ok(dnode)
+    }
+
+    /// Free the given inode
+    pub fn free_inode(&mut self, inode: usize) {
+        let bitmap_block = self.inode_bitmap_block();
+        self.set_bit(bitmap_block, inode, false);
+    }
+
+    /// Free the given dnode
+    pub fn free_dnode(&mut self, dnode: usize) {
+        let bitmap_block = self.dnode_bitmap_block();
+        self.set_bit(bitmap_block, dnode, false);
+    }
+
+    /// Mark the given dnode as in use without changing its contents (e.g. to repair the bitmap)
+    pub fn use_dnode(&mut self, dnode: usize) {
+        let bitmap_block = self.dnode_bitmap_block();
+        self.set_bit(bitmap_block, dnode, true);
+    }
+
+    /// Get the `inode`th inode
+    pub fn get_inode(&mut self, inode: usize) -> Inode {
+        let offset = inode * INODE_SIZE;
+        let block = self.first_inode_block() + offset / BLOCK_SIZE;
+
+        let mut bytes = [0; INODE_SIZE];
+        self.read_bytes(block, offset % BLOCK_SIZE, &mut bytes);
+
+        Inode::decode(&bytes)
+    }
+
+    /// Write the `inode`th inode
+    pub fn write_inode(&mut self, inode_num: usize, inode: &Inode) {
+        let offset = inode_num * INODE_SIZE;
+        let block = self.first_inode_block() + offset / BLOCK_SIZE;
+
+        let mut bytes = [0; INODE_SIZE];
+        inode.encode(&mut bytes);
+
+        self.write_bytes(block, offset % BLOCK_SIZE, &bytes);
+    }
+
+    /// Get the `i`th dnode number stored in the `dnode`th dnode
+    pub fn read_ptr(&mut self, dnode: usize, i: usize) -> usize {
+        let block = self.dnode_block(dnode);
+
+        let mut word = [0; 4];
+        self.read_bytes(block, 4 * i, &mut word);
+
+        get_word(&word, 0)
+    }
+
+    /// Set the `i`th dnode number stored in the `dnode`th dnode
+    pub fn write_ptr(&mut self, dnode: usize, i: usize, ptr: usize) {
+        let block = self.dnode_block(dnode);
+
+        let mut word = [0; 4];
+        put_word(&mut word, 0, ptr);
+
+        self.write_bytes(block, 4 * i, &word);
+    }
+
+    /// Get the `i`th dnode number stored in the `dnode`th dnode. If there is none yet, allocate a
+    /// zeroed dnode and store its number there.
+    fn read_or_alloc_ptr(&mut self, dnode: usize, i: usize) -> Result<usize, Error> {
+        match self.read_ptr(dnode, i) {
+            0 => {
+                let new = self.alloc_dnode()?;
+                self.write_ptr(dnode, i, new);
+                Ok(new)
+            }
+            ptr => Ok(ptr),
+        }
+    }
+
+    /// Return the position of the `block`th dnode of a file as a path through its index: the
+    /// position in the index dnode, followed by the positions in up to two indirect dnodes.
+    fn index_path(block: usize) -> Result<(usize, Option<usize>, Option<usize>), Error> {
+        if block < NUM_DIRECT {
+            return Ok((block, None, None));
+        }
+
+        let block = block - NUM_DIRECT;
+        if block < PTRS_PER_DNODE {
+            return Ok((INDIRECT, Some(block), None));
+        }
+
+        let block = block - PTRS_PER_DNODE;
+        if block < PTRS_PER_DNODE * PTRS_PER_DNODE {
+            return Ok((
+                DOUBLE_INDIRECT,
+                Some(block / PTRS_PER_DNODE),
+                Some(block % PTRS_PER_DNODE),
+            ));
+        }
+
+        Err(Error::FileTooLarge)
+    }
+
+    /// Return the number of the dnode holding the `block`th block of the given file, or None if
+    /// that block has no dnode (i.e. it reads as zeros).
+    pub fn map_block(&mut self, inode: &Inode, block: usize) -> Option<usize> {
+        let (first, second, third) = match Self::index_path(block) {
+            Ok(path) => path,
+            Err(_) => return None,
+        };
+
+        if inode.data == 0 {
+            return None;
+        }
+
+        let mut dnode = self.read_ptr(inode.data, first);
+        for &i in second.iter().chain(third.iter()) {
+            if dnode == 0 {
+                break;
+            }
+            dnode = self.read_ptr(dnode, i);
+        }
+
+        if dnode == 0 {
+            None
+        } else {
+            Some(dnode)
+        }
+    }
+
+    /// Like `map_block`, but allocate the dnode (and any indirect dnodes on the way) if the block
+    /// has none yet. If the file is empty, this also allocates its index dnode, so the inode has to
+    /// be written back.
+    pub fn map_block_alloc(&mut self, inode: &mut Inode, block: usize) -> Result<usize, Error> {
+        let (first, second, third) = Self::index_path(block)?;
+
+        if inode.data == 0 {
+            inode.data = self.alloc_dnode()?;
+        }
+
+        let mut dnode = self.read_or_alloc_ptr(inode.data, first)?;
+        for &i in second.iter().chain(third.iter()) {
+            dnode = self.read_or_alloc_ptr(dnode, i)?;
+        }
+
+        Ok(dnode)
+    }
+
+    /// Free all dnodes of the given file, including its index and name dnodes
+    pub fn free_dnodes(&mut self, inode: &Inode) {
+        if inode.has_long_name() {
+            self.free_dnode(inode.name_dnode());
+        }
+
+        let index = inode.data;
+
+        // empty file
+        if index == 0 {
+            return;
+        }
+
+        for i in 0..NUM_DIRECT {
+            let dnode = self.read_ptr(index, i);
+            if dnode != 0 {
+                self.free_dnode(dnode);
+            }
+        }
+
+        let indirect = self.read_ptr(index, INDIRECT);
+        self.free_indirect(indirect, 1);
+
+        let double_indirect = self.read_ptr(index, DOUBLE_INDIRECT);
+        self.free_indirect(double_indirect, 2);
+
+        self.free_dnode(index);
+    }
+
+    /// Free the given indirect dnode and the dnodes it refers to, `levels` levels deep
+    fn free_indirect(&mut self, dnode: usize, levels: usize) {
+        if dnode == 0 {
+            return;
+        }
+
+        for i in 0..PTRS_PER_DNODE {
+            let child = self.read_ptr(dnode, i);
+            if levels > 1 {
+                self.free_indirect(child, levels - 1);
+            } else if child != 0 {
+                self.free_dnode(child);
+            }
+        }
+
+        self.free_dnode(dnode);
+    }
+
+    /// Read from the given file at `offset` into `buf`. Returns the number of bytes read, which is
+    /// less than `buf.len()` only at the end of the file.
+    pub fn read_file(&mut self, inode: &Inode, offset: usize, buf: &mut [u8]) -> usize {
+        if offset >= inode.size {
+            return 0;
+        }
+
+        let len = min(buf.len(), inode.size - offset);
+        let mut done = 0;
+
+        while done < len {
+            let pos = offset + done;
+            let dnode_offset = pos % DNODE_SIZE;
+            let n = min(DNODE_SIZE - dnode_offset, len - done);
+
+            match self.map_block(inode, pos / DNODE_SIZE) {
+                Some(dnode) => {
+                    let block = self.dnode_block(dnode);
+                    self.read_bytes(block, dnode_offset, &mut buf[done..done + n]);
+                }
+                None => {
+                    // no dnode => zeros
+                    for byte in buf[done..done + n].iter_mut() {
+                        *byte = 0;
+                    }
+                }
+            }
+
+            done += n;
+        }
+
+        len
+    }
+
+    /// Write `buf` to the given file at `offset`, extending the file if needed. The inode is
+    /// updated, but it is up to the caller to write it back.
+    ///
+    /// If this fails, part of `buf` may have been written, and the inode may refer to new dnodes.
+    pub fn write_file(
+        &mut self,
+        inode: &mut Inode,
+        offset: usize,
+        buf: &[u8],
+    ) -> Result<(), Error> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = offset + done;
+            let dnode_offset = pos % DNODE_SIZE;
+            let n = min(DNODE_SIZE - dnode_offset, buf.len() - done);
+
+            let dnode = self.map_block_alloc(inode, pos / DNODE_SIZE)?;
+            let block = self.dnode_block(dnode);
+            self.write_bytes(block, dnode_offset, &buf[done..done + n]);
+
+            done += n;
+        }
+
+        if offset + buf.len() > inode.size {
+            inode.size = offset + buf.len();
+        }
+
+        Ok(())
+    }
+
+    /// Read the name of the given file into `buf` and return its length
+    pub fn read_name(&mut self, inode: &Inode, buf: &mut [u8; NAME_MAX]) -> usize {
+        if !inode.has_long_name() {
+            let name = inode.short_name();
+            buf[..name.len()].copy_from_slice(name);
+            return name.len();
+        }
+
+        // the name is NUL-terminated in its dnode
+        let block = self.dnode_block(inode.name_dnode());
+        self.read_bytes(block, 0, buf);
+
+        buf.iter().position(|&b| b == 0).unwrap_or(NAME_MAX)
+    }
+
+    /// Set the name of the given file. Names longer than `SHORT_NAME_MAX` are stored in a name
+    /// dnode. The inode is updated, but it is up to the caller to write it back.
+    pub fn set_name(&mut self, inode: &mut Inode, name: &[u8]) -> Result<(), Error> {
+        if name.len() > NAME_MAX {
+            return Err(Error::NameTooLong);
+        }
+
+        if inode.has_long_name() {
+            self.free_dnode(inode.name_dnode());
+            inode.flags &= !FLAG_LONG_NAME;
+        }
+
+        inode.name = [0; SHORT_NAME_MAX];
+
+        if name.len() <= SHORT_NAME_MAX {
+            inode.name[..name.len()].copy_from_slice(name);
+            return Ok(());
+        }
+
+        let name_dnode = self.alloc_dnode()?;
+        let block = self.dnode_block(name_dnode);
+        self.write_bytes(block, 0, name);
+
+        put_word(&mut inode.name, 0, name_dnode);
+        inode.flags |= FLAG_LONG_NAME;
+
+        Ok(())
+    }
+
+    /// Return the inode number of the file with the given name linked from the `inode`th inode,
+    /// or None if there is no such file
+    pub fn find_link(&mut self, inode: usize, name: &[u8]) -> Option<usize> {
+        let links = self.get_inode(inode).links;
+        let mut buf = [0; NAME_MAX];
+
+        for &link in links.iter().filter(|&&link| link != 0) {
+            if self.is_free_inode(link) {
+                continue;
+            }
+
+            let linked = self.get_inode(link);
+            let len = self.read_name(&linked, &mut buf);
+            if &buf[..len] == name {
+                return Some(link);
+            }
+        }
+
+        None
+    }
+
+    /// Link the `a`th inode to the `b`th inode, and count the link in `b`. Returns false if `a`
+    /// is already linked to `b` or has no free link slots left.
+    pub fn add_link(&mut self, a: usize, b: usize) -> bool {
+        let mut inode = self.get_inode(a);
+        if inode.is_linked(b) || !inode.add_link(b) {
+            return false;
+        }
+        self.write_inode(a, &inode);
+
+        let mut linked = self.get_inode(b);
+        linked.nlink += 1;
+        self.write_inode(b, &linked);
+
+        true
+    }
+
+    /// Remove the link from the `a`th inode to the `b`th inode, and uncount it in `b`. Returns
+    /// false if there is no such link. The `b`th inode is not freed, even if nothing links to it
+    /// anymore.
+    pub fn remove_link(&mut self, a: usize, b: usize) -> bool {
+        let mut inode = self.get_inode(a);
+        if !inode.remove_link(b) {
+            return false;
+        }
+        self.write_inode(a, &inode);
+
+        let mut linked = self.get_inode(b);
+        linked.nlink = linked.nlink.saturating_sub(1);
+        self.write_inode(b, &linked);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device::MemDevice;
+
+    fn new_volume(num_inode: usize, num_dnode: usize) -> Volume<MemDevice> {
+        let meta = Metadata::new(num_inode, num_dnode, 8);
+        let device = MemDevice::new(Volume::<MemDevice>::size_in_blocks(&meta));
+        Volume::format(device, meta)
+    }
+
+    fn num_free_dnodes(volume: &mut Volume<MemDevice>) -> usize {
+        (0..volume.meta.num_dnode)
+            .filter(|&d| volume.is_free_dnode(d))
+            .count()
+    }
+
+    /// A recognizable byte at each offset of a file
+    fn pattern(len: usize) -> std::vec::Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn format_and_open() {
+        let volume = new_volume(4096, 4096);
+        let journal_block = volume.journal_block();
+        let meta = volume.meta.clone();
+
+        assert_eq!(volume.dnode_bitmap_block(), 2);
+        assert_eq!(volume.first_inode_block(), 3);
+        assert_eq!(volume.first_dnode_block(), 3 + 1024);
+        assert_eq!(journal_block, 3 + 1024 + 4096);
+
+        let volume = Volume::open(volume.device).ok().unwrap();
+        assert_eq!(volume.meta, meta);
+    }
+
+    #[test]
+    fn open_checks_magic_and_version() {
+        let mut device = MemDevice::new(1);
+        assert_eq!(Volume::open(device).err(), Some(Error::NotOFS));
+
+        device = MemDevice::new(1);
+        let mut meta = Metadata::new(8, 8, 0);
+        meta.version = 1;
+        let mut block = [0; BLOCK_SIZE];
+        meta.encode(&mut block);
+        device.write_block(0, &block);
+
+        assert_eq!(
+            Volume::open(device).err(),
+            Some(Error::UnsupportedVersion(1))
+        );
+    }
+
+    #[test]
+    fn dnode_zero_is_reserved() {
+        let mut volume = new_volume(8, 4096);
+
+        assert!(!volume.is_free_dnode(0));
+        assert_eq!(volume.alloc_dnode(), Ok(1));
+    }
+
+    #[test]
+    fn alloc_until_full() {
+        let mut volume = new_volume(8, 4096);
+
+        for i in 0..8 {
+            assert_eq!(volume.alloc_inode(), Ok(i));
+        }
+        assert_eq!(volume.alloc_inode(), Err(Error::OutOfInodes));
+
+        volume.free_inode(5);
+        assert!(volume.is_free_inode(5));
+        assert_eq!(volume.alloc_inode(), Ok(5));
+
+        // inodes past the end are never in use
+        assert!(volume.is_free_inode(8));
+    }
+
+    #[test]
+    fn out_of_dnodes() {
+        let mut volume = new_volume(8, 64);
+        let mut inode = Inode::new();
+
+        // one dnode is reserved, and one is the index dnode
+        let room = (64 - 2) * DNODE_SIZE;
+        assert_eq!(volume.write_file(&mut inode, 0, &pattern(room)), Ok(()));
+        assert_eq!(
+            volume.write_file(&mut inode, room, &[1]),
+            Err(Error::OutOfDnodes)
+        );
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let mut volume = new_volume(8, 4096);
+
+        // direct, indirect, and double indirect dnodes, and a partial last dnode
+        for &len in &[
+            0,
+            1,
+            511,
+            512,
+            513,
+            NUM_DIRECT * DNODE_SIZE + 7,
+            300 * DNODE_SIZE + 3,
+        ] {
+            let mut inode = Inode::new();
+            let contents = pattern(len);
+
+            assert_eq!(volume.write_file(&mut inode, 0, &contents), Ok(()));
+            assert_eq!(inode.size, len);
+            assert_eq!(inode.data == 0, len == 0);
+
+            let mut read = vec![0xAA; len + 10];
+            assert_eq!(volume.read_file(&inode, 0, &mut read), len);
+            assert_eq!(&read[..len], &contents[..]);
+
+            volume.free_dnodes(&inode);
+        }
+    }
+
+    #[test]
+    fn unaligned_reads_and_writes() {
+        let mut volume = new_volume(8, 4096);
+        let mut inode = Inode::new();
+
+        volume.write_file(&mut inode, 0, &pattern(2000)).unwrap();
+        volume.write_file(&mut inode, 500, &[0xFF; 30]).unwrap();
+        assert_eq!(inode.size, 2000);
+
+        let mut read = [0; 40];
+        assert_eq!(volume.read_file(&inode, 495, &mut read), 40);
+        assert_eq!(&read[..5], &pattern(500)[495..]);
+        assert_eq!(&read[5..35], &[0xFF; 30][..]);
+        assert_eq!(&read[35..], &pattern(2000)[530..535]);
+
+        // reads stop at the end of the file
+        assert_eq!(volume.read_file(&inode, 1990, &mut read), 10);
+        assert_eq!(volume.read_file(&inode, 2000, &mut read), 0);
+    }
+
+    #[test]
+    fn holes_read_as_zeros() {
+        let mut volume = new_volume(8, 4096);
+        let mut inode = Inode::new();
+
+        volume
+            .write_file(&mut inode, 10 * DNODE_SIZE, &[1, 2, 3])
+            .unwrap();
+        assert_eq!(inode.size, 10 * DNODE_SIZE + 3);
+        assert_eq!(volume.map_block(&inode, 0), None);
+        assert!(volume.map_block(&inode, 10).is_some());
+
+        let mut read = vec![0xAA; inode.size];
+        volume.read_file(&inode, 0, &mut read);
+        assert!(read[..10 * DNODE_SIZE].iter().all(|&b| b == 0));
+        assert_eq!(&read[10 * DNODE_SIZE..], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn file_size_is_limited() {
+        let mut volume = new_volume(8, 4096);
+        let mut inode = Inode::new();
+
+        assert_eq!(
+            volume.write_file(&mut inode, MAX_FILE_BLOCKS * DNODE_SIZE, &[1]),
+            Err(Error::FileTooLarge)
+        );
+    }
+
+    #[test]
+    fn free_dnodes_frees_everything() {
+        let mut volume = new_volume(8, 4096);
+        let free = num_free_dnodes(&mut volume);
+
+        let mut inode = Inode::new();
+        volume
+            .set_name(&mut inode, b"a file with a long name")
+            .unwrap();
+        volume
+            .write_file(&mut inode, 0, &pattern(400 * DNODE_SIZE))
+            .unwrap();
+        assert!(num_free_dnodes(&mut volume) < free - 400);
+
+        volume.free_dnodes(&inode);
+        assert_eq!(num_free_dnodes(&mut volume), free);
+    }
+
+    #[test]
+    fn names() {
+        let mut volume = new_volume(8, 4096);
+        let mut inode = Inode::new();
+        let mut buf = [0; NAME_MAX];
+
+        volume.set_name(&mut inode, b"short").unwrap();
+        assert!(!inode.has_long_name());
+        assert_eq!(volume.read_name(&inode, &mut buf), 5);
+        assert_eq!(&buf[..5], b"short");
+
+        let long = [b'x'; NAME_MAX];
+        volume.set_name(&mut inode, &long).unwrap();
+        assert!(inode.has_long_name());
+        assert_eq!(volume.read_name(&inode, &mut buf), NAME_MAX);
+        assert_eq!(&buf[..], &long[..]);
+
+        // renaming frees the name dnode
+        let name_dnode = inode.name_dnode();
+        volume.set_name(&mut inode, b"short again").unwrap();
+        assert!(volume.is_free_dnode(name_dnode));
+
+        assert_eq!(
+            volume.set_name(&mut inode, &[b'x'; NAME_MAX + 1]),
+            Err(Error::NameTooLong)
+        );
+    }
+
+    #[test]
+    fn find_link_by_name() {
+        let mut volume = new_volume(8, 4096);
+
+        // the root is inode 0, which can't be linked to
+        let root_num = volume.alloc_inode().unwrap();
+        let mut root = Inode::new();
+        let mut names = std::vec::Vec::new();
+        for name in &[&b"a"[..], &b"twelve_bytes"[..], &b"thirteen_byte"[..]] {
+            let num = volume.alloc_inode().unwrap();
+            let mut inode = Inode::new();
+            volume.set_name(&mut inode, name).unwrap();
+            volume.write_inode(num, &inode);
+            root.add_link(num);
+            names.push((num, *name));
+        }
+        volume.write_inode(root_num, &root);
+
+        for &(num, name) in &names {
+            assert_eq!(volume.find_link(root_num, name), Some(num));
+        }
+        assert_eq!(volume.find_link(root_num, b"twelve"), None);
+        assert_eq!(volume.find_link(root_num, b""), None);
+
+        // links to free inodes are ignored
+        volume.free_inode(names[0].0);
+        assert_eq!(volume.find_link(root_num, b"a"), None);
+    }
+
+    #[test]
+    fn links_are_counted() {
+        let mut volume = new_volume(8, 4096);
+        for _ in 0..3 {
+            let num = volume.alloc_inode().unwrap();
+            volume.write_inode(num, &Inode::new());
+        }
+
+        assert!(volume.add_link(0, 2));
+        assert!(volume.add_link(1, 2));
+        assert!(!volume.add_link(1, 2));
+        assert_eq!(volume.get_inode(2).nlink, 2);
+
+        assert!(volume.remove_link(0, 2));
+        assert!(!volume.remove_link(0, 2));
+        assert_eq!(volume.get_inode(2).nlink, 1);
+        assert_eq!(&volume.get_inode(1).links[..2], &[2, 0]);
+    }
+}
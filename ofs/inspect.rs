@@ -0,0 +1,512 @@
+//! Reading files and their attributes from an OFS image
+
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use ofs_core::*;
+
+/// Find the file named by `arg`: either an inode number, or an absolute path whose components
+/// name files linked from the file before them (see `fs::lookup` in the kernel). Returns None if
+/// there is no such file.
+pub fn resolve(volume: &mut Volume<MemDevice>, arg: &str) -> Option<usize> {
+    if !arg.starts_with('/') {
+        let inode = arg.parse().ok()?;
+        return if volume.is_free_inode(inode) {
+            None
+        } else {
+            Some(inode)
+        };
+    }
+
+    let mut current = 0;
+    let mut stack = Vec::new();
+
+    for name in arg.split('/').filter(|name| !name.is_empty()) {
+        match name {
+            "." => {}
+            ".." => {
+                // the root is its own parent
+                if let Some(parent) = stack.pop() {
+                    current = parent;
+                }
+            }
+            _ => {
+                let next = volume.find_link(current, name.as_bytes())?;
+                stack.push(current);
+                current = next;
+            }
+        }
+    }
+
+    Some(current)
+}
+
+/// Get the name of the given file
+pub fn name(volume: &mut Volume<MemDevice>, inode: &Inode) -> String {
+    let mut name = [0; NAME_MAX];
+    let len = volume.read_name(inode, &mut name);
+
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+/// Read the whole contents of the given file
+pub fn contents(volume: &mut Volume<MemDevice>, inode: &Inode) -> Vec<u8> {
+    let mut buf = vec![0; inode.size];
+    volume.read_file(inode, 0, &mut buf);
+    buf
+}
+
+/// Format the type and permissions of the given file like `ls -l` does (e.g. `drwxr-x---`)
+pub fn perms(inode: &Inode) -> String {
+    let mut s = String::new();
+    s.push(if inode.is_dir() {
+        'd'
+    } else if inode.is_symlink() {
+        'l'
+    } else {
+        '-'
+    });
+
+    for &perm in &[inode.user_perm, inode.group_perm, inode.all_perm] {
+        s.push(if perm & 4 != 0 { 'r' } else { '-' });
+        s.push(if perm & 2 != 0 { 'w' } else { '-' });
+        s.push(if perm & 1 != 0 { 'x' } else { '-' });
+    }
+
+    s
+}
+
+/// Format a date as `YYYY-MM-DD hh:mm:ss` (UTC)
+pub fn date(date: OFSDate) -> String {
+    let secs = date.0 as u64;
+    let days = secs / 86400;
+    let time = secs % 86400;
+
+    // the proleptic Gregorian calendar, in 400 year eras starting on 0000-03-01
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Print the metadata of the volume and how much of it is used
+pub fn info(volume: &mut Volume<MemDevice>) {
+    let meta = volume.meta.clone();
+
+    let mut used_inodes = 0;
+    let mut bytes = 0;
+    for i in 0..meta.num_inode {
+        if !volume.is_free_inode(i) {
+            used_inodes += 1;
+            bytes += volume.get_inode(i).size;
+        }
+    }
+
+    let used_dnodes = (0..meta.num_dnode)
+        .filter(|&d| !volume.is_free_dnode(d))
+        .count();
+
+    println!("version       {}", meta.version);
+    println!(
+        "inodes        {} used of {} ({} free)",
+        used_inodes,
+        meta.num_inode,
+        meta.num_inode - used_inodes
+    );
+    println!(
+        "dnodes        {} used of {} ({} free, {}B each, dnode 0 reserved)",
+        used_dnodes,
+        meta.num_dnode,
+        meta.num_dnode - used_dnodes,
+        DNODE_SIZE
+    );
+    println!("file data     {}B", bytes);
+    println!(
+        "journal       {} blocks{}",
+        meta.num_journal,
+        if meta.num_journal > 1 {
+            ""
+        } else {
+            " (read-only)"
+        }
+    );
+    println!();
+    println!("block         contents");
+    println!("0             metadata");
+    println!("{:<13} inode bitmap", volume.inode_bitmap_block());
+    println!("{:<13} dnode bitmap", volume.dnode_bitmap_block());
+    println!("{:<13} inodes", volume.first_inode_block());
+    println!("{:<13} dnodes", volume.first_dnode_block());
+    println!("{:<13} journal", volume.journal_block());
+    println!("{:<13} end", Volume::<MemDevice>::size_in_blocks(&meta));
+}
+
+/// Print the header of the table printed by `ls`
+pub fn ls_header() {
+    println!(
+        "{:>6}  {:10}  {:>5}  {:>5}  {:>5}  {:>9}  {:19}  name -> links",
+        "inode", "perms", "nlink", "uid", "gid", "size", "modified"
+    );
+}
+
+/// Print a line of the table printed by `ls` for the `i`th inode
+pub fn ls_line(volume: &mut Volume<MemDevice>, i: usize) {
+    let inode = volume.get_inode(i);
+    let links: Vec<String> = inode
+        .links
+        .iter()
+        .filter(|&&link| link != 0)
+        .map(|link| link.to_string())
+        .collect();
+
+    println!(
+        "{:>6}  {}  {:>5}  {:>5}  {:>5}  {:>9}  {}  {} -> {}",
+        i,
+        perms(&inode),
+        inode.nlink,
+        inode.uid,
+        inode.gid,
+        inode.size,
+        date(inode.modified),
+        name(volume, &inode),
+        links.join(" ")
+    );
+}
+
+/// Print everything about the `i`th inode
+pub fn stat(volume: &mut Volume<MemDevice>, i: usize) {
+    let inode = volume.get_inode(i);
+    let num_blocks = div_round_up(inode.size, DNODE_SIZE);
+    let mapped = (0..num_blocks)
+        .filter(|&b| volume.map_block(&inode, b).is_some())
+        .count();
+
+    println!("inode         {}", i);
+    println!("name          {}", name(volume, &inode));
+    if inode.has_long_name() {
+        println!("name dnode    {}", inode.name_dnode());
+    }
+    println!("size          {}B", inode.size);
+    println!(
+        "dnodes        {} of {} blocks mapped, index dnode {}",
+        mapped, num_blocks, inode.data
+    );
+    println!("owner         uid {}, gid {}", inode.uid, inode.gid);
+    println!("permissions   {}", perms(&inode));
+    println!("flags         {:#04x}", inode.flags);
+    println!("nlink         {}", inode.nlink);
+    if inode.is_symlink() {
+        let target = contents(volume, &inode);
+        println!("target        {}", String::from_utf8_lossy(&target));
+    }
+    println!("created       {}", date(inode.created));
+    println!("modified      {}", date(inode.modified));
+
+    for &link in inode.links.iter().filter(|&&link| link != 0) {
+        if volume.is_free_inode(link) {
+            println!("link          {} (free inode)", link);
+            continue;
+        }
+
+        let linked = volume.get_inode(link);
+        println!("link          {} ({})", link, name(volume, &linked));
+    }
+}
+
+/// Print the files reachable from the `i`th inode as a tree. Files that were already printed are
+/// marked with `*` and not followed again, so cycles end.
+pub fn tree(volume: &mut Volume<MemDevice>, i: usize) {
+    let mut seen = vec![false; volume.meta.num_inode];
+    tree_from(volume, i, "", &mut seen);
+}
+
+fn tree_from(volume: &mut Volume<MemDevice>, i: usize, indent: &str, seen: &mut Vec<bool>) {
+    let inode = volume.get_inode(i);
+    let name = name(volume, &inode);
+
+    if seen[i] {
+        println!("{}{} [{}] *", indent, name, i);
+        return;
+    }
+
+    println!("{}{} [{}]", indent, name, i);
+    seen[i] = true;
+
+    let child_indent = format!("{}    ", indent);
+    for &link in inode.links.iter().filter(|&&link| link != 0) {
+        if !volume.is_free_inode(link) {
+            tree_from(volume, link, &child_indent, seen);
+        }
+    }
+}
+
+/// Write the contents of the `i`th inode to stdout
+pub fn cat(volume: &mut Volume<MemDevice>, i: usize) -> io::Result<()> {
+    let inode = volume.get_inode(i);
+    let contents = contents(volume, &inode);
+
+    io::stdout().write_all(&contents)
+}
+
+/// Copy the `i`th inode to `dest` on the host. A directory, or any other file with links, becomes
+/// a directory holding the linked files, like `mkfs` expects, so extracting the root gives back
+/// the tree the image was made from. Symbolic links become symbolic links on the host. Owners and
+/// dates are not restored.
+///
+/// Nothing on the host is overwritten: `dest` must not exist yet, and a file whose name was
+/// already extracted into the same directory is skipped.
+pub fn extract(volume: &mut Volume<MemDevice>, i: usize, dest: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(dest).is_ok() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("'{}' already exists", dest.display()),
+        ));
+    }
+
+    let mut seen = vec![false; volume.meta.num_inode];
+    extract_from(volume, i, dest, &mut seen)
+}
+
+fn extract_from(
+    volume: &mut Volume<MemDevice>,
+    i: usize,
+    dest: &Path,
+    seen: &mut Vec<bool>,
+) -> io::Result<()> {
+    // an earlier file with the same name may have been a symbolic link, which must not be written
+    // through
+    if fs::symlink_metadata(dest).is_ok() {
+        println!(
+            "Warning! Skipping '{}', which was extracted already.",
+            dest.display()
+        );
+        return Ok(());
+    }
+
+    let inode = volume.get_inode(i);
+
+    if inode.is_symlink() {
+        let target = contents(volume, &inode);
+        symlink(OsStr::from_bytes(&target), dest)?;
+        println!("Extracted '{}'.", dest.display());
+        return Ok(());
+    }
+
+    if !inode.is_dir() && !inode.links.iter().any(|&link| link != 0) {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dest)?
+            .write_all(&contents(volume, &inode))?;
+        println!("Extracted '{}'.", dest.display());
+        return Ok(());
+    }
+
+    if inode.size > 0 {
+        println!(
+            "Warning! Skipping the contents of '{}', which has links.",
+            dest.display()
+        );
+    }
+
+    // a cycle
+    if seen[i] {
+        println!(
+            "Warning! Skipping '{}', which was extracted already.",
+            dest.display()
+        );
+        return Ok(());
+    }
+    seen[i] = true;
+
+    fs::create_dir_all(dest)?;
+
+    for &link in inode.links.iter().filter(|&&link| link != 0) {
+        if volume.is_free_inode(link) {
+            continue;
+        }
+
+        let linked = volume.get_inode(link);
+        let child = name(volume, &linked);
+        if child.is_empty() || child == "." || child == ".." || child.contains('/') {
+            println!(
+                "Warning! Skipping inode {}, whose name is not a host file name.",
+                link
+            );
+            continue;
+        }
+
+        extract_from(volume, link, &dest.join(child), seen)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    /// A volume with the files /a, /a/b, and /c, where /c links back to /a
+    fn new_volume() -> Volume<MemDevice> {
+        let meta = Metadata::new(16, 64, 0);
+        let device = MemDevice::new(Volume::<MemDevice>::size_in_blocks(&meta));
+        let mut volume = Volume::format(device, meta);
+
+        let mut inodes = Vec::new();
+        for name in &["root", "a", "b", "c"] {
+            let num = volume.alloc_inode().unwrap();
+            let mut inode = Inode::new();
+            volume.set_name(&mut inode, name.as_bytes()).unwrap();
+            inodes.push((num, inode));
+        }
+
+        inodes[0].1.add_link(1);
+        inodes[0].1.add_link(3);
+        inodes[1].1.add_link(2);
+        inodes[3].1.add_link(1);
+
+        for (num, inode) in inodes {
+            volume.write_inode(num, &inode);
+        }
+
+        volume
+    }
+
+    #[test]
+    fn resolve_paths() {
+        let mut volume = new_volume();
+
+        assert_eq!(resolve(&mut volume, "/"), Some(0));
+        assert_eq!(resolve(&mut volume, "/a/b"), Some(2));
+        assert_eq!(resolve(&mut volume, "/c/a/b"), Some(2));
+        assert_eq!(resolve(&mut volume, "/a/b/../../c/."), Some(3));
+        assert_eq!(resolve(&mut volume, "/.."), Some(0));
+        assert_eq!(resolve(&mut volume, "/b"), None);
+    }
+
+    #[test]
+    fn resolve_inode_numbers() {
+        let mut volume = new_volume();
+
+        assert_eq!(resolve(&mut volume, "2"), Some(2));
+        assert_eq!(resolve(&mut volume, "7"), None);
+        assert_eq!(resolve(&mut volume, "99"), None);
+        assert_eq!(resolve(&mut volume, "a"), None);
+    }
+
+    /// A scratch directory on the host, removed when dropped
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let path = env::temp_dir().join(format!("ofs-{}-{}", process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir(&path).unwrap();
+            Scratch(path)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn extract_tree() {
+        let mut volume = new_volume();
+        let scratch = Scratch::new("extract-tree");
+        let dest = scratch.0.join("out");
+
+        extract(&mut volume, 0, &dest).unwrap();
+        assert!(dest.join("a").is_dir());
+        assert!(dest.join("a/b").is_file());
+        assert!(dest.join("c").is_dir());
+
+        // the destination is never overwritten
+        let err = extract(&mut volume, 0, &dest).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn extract_duplicate_names() {
+        let mut volume = new_volume();
+        let scratch = Scratch::new("extract-duplicates");
+        let outside = scratch.0.join("outside");
+
+        // /a gets a symbolic link named x out of the tree, and then a file named x
+        let mut link = Inode::new();
+        link.flags = FLAG_SYMLINK;
+        volume.set_name(&mut link, b"x").unwrap();
+        volume
+            .write_file(&mut link, 0, outside.to_str().unwrap().as_bytes())
+            .unwrap();
+
+        let mut file = Inode::new();
+        volume.set_name(&mut file, b"x").unwrap();
+        volume.write_file(&mut file, 0, b"data").unwrap();
+
+        for inode in &[link, file] {
+            let num = volume.alloc_inode().unwrap();
+            volume.write_inode(num, inode);
+            assert!(volume.add_link(1, num));
+        }
+
+        let dest = scratch.0.join("out");
+        extract(&mut volume, 1, &dest).unwrap();
+
+        assert!(fs::symlink_metadata(dest.join("x"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert!(fs::symlink_metadata(&outside).is_err());
+    }
+
+    #[test]
+    fn format_dates() {
+        assert_eq!(date(OFSDate(0)), "1970-01-01 00:00:00");
+        assert_eq!(date(OFSDate(951_782_400)), "2000-02-29 00:00:00");
+        assert_eq!(date(OFSDate(1_577_923_200 + 3723)), "2020-01-02 01:02:03");
+        assert_eq!(date(OFSDate(u32::MAX)), "2106-02-07 06:28:15");
+    }
+
+    #[test]
+    fn format_perms() {
+        let mut inode = Inode::new();
+        inode.user_perm = 7;
+        inode.group_perm = 5;
+        inode.all_perm = 2;
+
+        assert_eq!(perms(&inode), "-rwxr-x-w-");
+
+        inode.flags = FLAG_DIR;
+        assert_eq!(perms(&inode), "drwxr-x-w-");
+
+        inode.flags = FLAG_SYMLINK;
+        assert_eq!(perms(&inode), "lrwxr-x-w-");
+    }
+}
@@ -0,0 +1,130 @@
+//! A tool for inspecting OFS images on the host
+//!
+//! ```text
+//! ofs <image> info                 the metadata and how much of the volume is used
+//! ofs <image> ls [<file>]          all files, or the files linked from <file>
+//! ofs <image> tree [<file>]        the files reachable from the root (or <file>)
+//! ofs <image> stat <file>          everything about <file>
+//! ofs <image> cat <file>           the contents of <file>
+//! ofs <image> extract <file> <to>  copy <file> (and the files linked from it) to the host
+//! ```
+//!
+//! A `<file>` is either an absolute path (e.g. `/kernel/lib.rs`) or an inode number.
+
+extern crate ofs_core;
+
+mod inspect;
+
+use std::env::args;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+use ofs_core::{Error, MemDevice, Volume};
+
+fn usage() -> ! {
+    println!("Usage: ofs <image> info");
+    println!("       ofs <image> ls [<file>]");
+    println!("       ofs <image> tree [<file>]");
+    println!("       ofs <image> stat <file>");
+    println!("       ofs <image> cat <file>");
+    println!("       ofs <image> extract <file> <destination>");
+    println!("A <file> is an absolute path or an inode number.");
+    exit(1);
+}
+
+/// Find the file named by `arg`, or exit if there is none
+fn resolve(volume: &mut Volume<MemDevice>, arg: &str) -> usize {
+    inspect::resolve(volume, arg).unwrap_or_else(|| {
+        println!("Error! No such file '{}'.", arg);
+        exit(1);
+    })
+}
+
+pub fn main() {
+    // read command line args
+    let args: Vec<String> = args().skip(1).collect();
+    if args.len() < 2 {
+        usage();
+    }
+
+    let image = &args[0];
+    let bytes = fs::read(image).unwrap_or_else(|e| {
+        println!("Error! Cannot read '{}': {}.", image, e);
+        exit(1);
+    });
+
+    let mut volume = match Volume::open(MemDevice::from_bytes(bytes)) {
+        Ok(volume) => volume,
+        Err(Error::UnsupportedVersion(version)) => {
+            println!(
+                "Error! '{}' has OFS version {}, but ofs only knows version {}.",
+                image,
+                version,
+                ofs_core::OFS_VERSION
+            );
+            exit(1);
+        }
+        Err(_) => {
+            println!("Error! '{}' is not an OFS volume.", image);
+            exit(1);
+        }
+    };
+
+    let args: Vec<&str> = args[1..].iter().map(|arg| arg.as_str()).collect();
+
+    let result = match args[..] {
+        ["info"] => {
+            inspect::info(&mut volume);
+            Ok(())
+        }
+        ["ls"] => {
+            inspect::ls_header();
+            for i in 0..volume.meta.num_inode {
+                if !volume.is_free_inode(i) {
+                    inspect::ls_line(&mut volume, i);
+                }
+            }
+            Ok(())
+        }
+        ["ls", file] => {
+            let i = resolve(&mut volume, file);
+            inspect::ls_header();
+            for &link in volume.get_inode(i).links.iter().filter(|&&link| link != 0) {
+                // dangling links are left to ofsck
+                if !volume.is_free_inode(link) {
+                    inspect::ls_line(&mut volume, link);
+                }
+            }
+            Ok(())
+        }
+        ["tree"] => {
+            inspect::tree(&mut volume, 0);
+            Ok(())
+        }
+        ["tree", file] => {
+            let i = resolve(&mut volume, file);
+            inspect::tree(&mut volume, i);
+            Ok(())
+        }
+        ["stat", file] => {
+            let i = resolve(&mut volume, file);
+            inspect::stat(&mut volume, i);
+            Ok(())
+        }
+        ["cat", file] => {
+            let i = resolve(&mut volume, file);
+            inspect::cat(&mut volume, i)
+        }
+        ["extract", file, dest] => {
+            let i = resolve(&mut volume, file);
+            inspect::extract(&mut volume, i, Path::new(dest))
+        }
+        _ => usage(),
+    };
+
+    if let Err(e) = result {
+        println!("Error! {}.", e);
+        exit(1);
+    }
+}
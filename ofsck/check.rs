@@ -0,0 +1,716 @@
+//! The consistency checks of ofsck
+//!
+//! The checks make a single pass over all allocated inodes, claiming every dnode a file refers to
+//! (its name dnode, index dnode, indirect dnodes, and data dnodes), and then compare the claimed
+//! dnodes to the dnode bitmap. The inode bitmap is taken to be correct: an inode is a file iff its
+//! bit is set.
+//!
+//! When repairing, a bad pointer is cleared (so that part of the file reads as zeros), bad links
+//! are removed, and the bitmap is made to agree with the files. Dnodes that are no longer
+//! referred to are freed by the bitmap check. Link counts are made to agree with the links that
+//! are left.
+
+use ofs_core::*;
+
+/// The number of problems found and fixed by `check`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// The number of problems found
+    pub found: usize,
+
+    /// The number of problems that were repaired
+    pub fixed: usize,
+
+    /// The metadata is too broken for anything else to be checked (see `check_metadata`)
+    pub unusable: bool,
+}
+
+/// The state of a check of a volume
+struct Checker<'v> {
+    /// The volume being checked
+    volume: &'v mut Volume<MemDevice>,
+
+    /// Repair problems rather than just reporting them?
+    repair: bool,
+
+    /// The inode claiming each dnode, if any. Empty until the metadata has been checked, since
+    /// its size comes from the metadata.
+    claimed: Vec<Option<usize>>,
+
+    /// The problems found so far
+    report: Report,
+}
+
+/// Check the given volume, repairing it if `repair` is true, and report the problems found.
+pub fn check(volume: &mut Volume<MemDevice>, repair: bool) -> Report {
+    let mut checker = Checker {
+        volume,
+        repair,
+        claimed: Vec::new(),
+        report: Report::default(),
+    };
+
+    if !checker.check_metadata() {
+        checker.report.unusable = true;
+    } else if checker.check_journal() {
+        checker.claimed = vec![None; checker.volume.meta.num_dnode];
+
+        checker.check_root();
+        checker.check_inodes();
+        checker.check_dnode_bitmap();
+        checker.check_link_counts();
+        checker.check_reachable();
+    }
+
+    checker.report
+}
+
+impl<'v> Checker<'v> {
+    /// Report a problem that can be repaired. Returns true if it should be repaired now.
+    fn problem(&mut self, msg: String) -> bool {
+        self.report.found += 1;
+
+        if self.repair {
+            self.report.fixed += 1;
+            println!("Error! {} (fixed)", msg);
+        } else {
+            println!("Error! {}", msg);
+        }
+
+        self.repair
+    }
+
+    /// Report a problem that cannot be repaired
+    fn fatal(&mut self, msg: String) {
+        self.report.found += 1;
+        println!("Error! {}", msg);
+    }
+
+    /// Check that the volume fits on the device and has a root. Returns false if the volume is too
+    /// broken to check any further.
+    fn check_metadata(&mut self) -> bool {
+        let needed = Volume::<MemDevice>::size_in_blocks(&self.volume.meta);
+        let num_blocks = self.volume.device.num_blocks();
+
+        if self.volume.meta.num_inode == 0 || self.volume.meta.num_dnode == 0 {
+            self.fatal(format!(
+                "The volume has {} inodes and {} dnodes.",
+                self.volume.meta.num_inode, self.volume.meta.num_dnode
+            ));
+            return false;
+        }
+
+        if num_blocks < needed {
+            self.fatal(format!(
+                "The image has {} blocks, but the metadata needs {}.",
+                num_blocks, needed
+            ));
+            return false;
+        }
+
+        if num_blocks > needed {
+            println!(
+                "Warning! The image has {} blocks, but only {} are used.",
+                num_blocks, needed
+            );
+        }
+
+        if self.volume.is_free_inode(0) {
+            self.fatal("The root (inode 0) is not allocated.".to_string());
+            return false;
+        }
+
+        true
+    }
+
+    /// Check that the journal does not hold a transaction that still has to be replayed. Returns
+    /// false if it does, since the volume is only consistent after the replay.
+    fn check_journal(&mut self) -> bool {
+        if self.volume.meta.num_journal == 0 {
+            return true;
+        }
+
+        let mut header = [0; BLOCK_SIZE];
+        let journal_block = self.volume.journal_block();
+        self.volume.device.read_block(journal_block, &mut header);
+
+        let count = get_word(&header, 4);
+        if get_word(&header, 0) == JOURNAL_MAGIC && count > 0 {
+            self.fatal(format!(
+                "The journal holds a committed transaction of {} blocks. Mount the volume to \
+                 replay it first.",
+                count
+            ));
+            return false;
+        }
+
+        true
+    }
+
+    /// Check that the root is a directory
+    fn check_root(&mut self) {
+        let mut root = self.volume.get_inode(0);
+
+        if !root.is_dir() && self.problem("The root (inode 0) is not a directory.".to_string()) {
+            root.flags |= FLAG_DIR;
+            self.volume.write_inode(0, &root);
+        }
+    }
+
+    /// Check the type, name, size, dnodes, and links of every file
+    fn check_inodes(&mut self) {
+        for i in 0..self.volume.meta.num_inode {
+            if self.volume.is_free_inode(i) {
+                continue;
+            }
+
+            let mut inode = self.volume.get_inode(i);
+            let before = inode.clone();
+
+            self.check_type(i, &mut inode);
+            self.check_name(i, &mut inode);
+            self.check_size(i, &mut inode);
+            self.check_data(i, &mut inode);
+            self.check_links(i, &mut inode);
+
+            if inode != before {
+                self.volume.write_inode(i, &inode);
+            }
+        }
+    }
+
+    /// Try to claim the `dnode`th dnode for the `inode`th inode. Reports a problem and returns
+    /// false if there is no such dnode or it is already claimed, in which case the caller has to
+    /// remove the reference to it when repairing. Dnode 0 is reserved, so no inode can claim it.
+    fn claim(&mut self, inode: usize, dnode: usize, what: &str) -> bool {
+        if dnode == 0 || dnode >= self.volume.meta.num_dnode {
+            self.problem(format!(
+                "The {} of inode {} is dnode {}, which does not exist.",
+                what, inode, dnode
+            ));
+            return false;
+        }
+
+        if let Some(other) = self.claimed[dnode] {
+            self.problem(format!(
+                "The {} of inode {} is dnode {}, which is already used by inode {}.",
+                what, inode, dnode, other
+            ));
+            return false;
+        }
+
+        self.claimed[dnode] = Some(inode);
+        true
+    }
+
+    /// Check that the `i`th inode is not both a directory and a symbolic link. It is kept as a
+    /// directory, since its links may be all that leads to some files.
+    fn check_type(&mut self, i: usize, inode: &mut Inode) {
+        if inode.is_dir()
+            && inode.is_symlink()
+            && self.problem(format!(
+                "Inode {} is both a directory and a symbolic link.",
+                i
+            ))
+        {
+            inode.flags &= !FLAG_SYMLINK;
+        }
+    }
+
+    /// Check the name dnode of the `i`th inode. A bad name is replaced by the inode number.
+    fn check_name(&mut self, i: usize, inode: &mut Inode) {
+        if inode.has_long_name() && !self.claim(i, inode.name_dnode(), "name dnode") && self.repair
+        {
+            let name = format!("inode{}", i);
+
+            inode.name = [0; SHORT_NAME_MAX];
+            inode.name[..name.len()].copy_from_slice(name.as_bytes());
+            inode.flags &= !FLAG_LONG_NAME;
+        }
+    }
+
+    /// Check that the size of the `i`th inode is possible
+    fn check_size(&mut self, i: usize, inode: &mut Inode) {
+        let max_size = MAX_FILE_BLOCKS * DNODE_SIZE;
+
+        if inode.size > max_size
+            && self.problem(format!(
+                "Inode {} has size {}, which is larger than the maximum {}.",
+                i, inode.size, max_size
+            ))
+        {
+            inode.size = max_size;
+        }
+    }
+
+    /// Check the index of the `i`th inode and claim all of its dnodes
+    fn check_data(&mut self, i: usize, inode: &mut Inode) {
+        if inode.data == 0 {
+            return;
+        }
+
+        if !self.claim(i, inode.data, "index dnode") {
+            if self.repair {
+                // the contents are lost
+                inode.data = 0;
+                inode.size = 0;
+            }
+            return;
+        }
+
+        let num_blocks = div_round_up(inode.size, DNODE_SIZE);
+
+        for slot in 0..NUM_DIRECT {
+            self.check_ptr(i, inode.data, slot, slot, 0, Some(num_blocks));
+        }
+
+        self.check_ptr(i, inode.data, INDIRECT, NUM_DIRECT, 1, Some(num_blocks));
+        self.check_ptr(
+            i,
+            inode.data,
+            DOUBLE_INDIRECT,
+            NUM_DIRECT + PTRS_PER_DNODE,
+            2,
+            Some(num_blocks),
+        );
+    }
+
+    /// Check the `slot`th pointer in the `parent`th dnode, which belongs to the `i`th inode. The
+    /// pointer is to a dnode `levels` levels above the data dnodes, which maps the file starting
+    /// at its `first_block`th block. `num_blocks` is the number of blocks of the file, or None if
+    /// blocks past the end of the file have already been reported.
+    fn check_ptr(
+        &mut self,
+        i: usize,
+        parent: usize,
+        slot: usize,
+        first_block: usize,
+        levels: u32,
+        num_blocks: Option<usize>,
+    ) {
+        let dnode = self.volume.read_ptr(parent, slot);
+        if dnode == 0 {
+            return;
+        }
+
+        let what = if levels == 0 {
+            "data dnode"
+        } else {
+            "indirect dnode"
+        };
+        if !self.claim(i, dnode, what) {
+            if self.repair {
+                self.volume.write_ptr(parent, slot, 0);
+            }
+            return;
+        }
+
+        let mut num_blocks = num_blocks;
+        if num_blocks.is_some_and(|n| first_block >= n) {
+            if self.problem(format!(
+                "Inode {} has dnode {} past the end of the file.",
+                i, dnode
+            )) {
+                // the bitmap check frees it and anything it refers to
+                self.claimed[dnode] = None;
+                self.volume.write_ptr(parent, slot, 0);
+                return;
+            }
+
+            num_blocks = None;
+        }
+
+        if levels == 0 {
+            return;
+        }
+
+        let blocks_per_ptr = PTRS_PER_DNODE.pow(levels - 1);
+        for child in 0..PTRS_PER_DNODE {
+            let child_first = first_block + child * blocks_per_ptr;
+            self.check_ptr(i, dnode, child, child_first, levels - 1, num_blocks);
+        }
+    }
+
+    /// Check that the links of the `i`th inode are to allocated inodes, are not repeated, and are
+    /// kept at the front of the list.
+    fn check_links(&mut self, i: usize, inode: &mut Inode) {
+        let mut links = [0; NUM_LINKS];
+        let mut num_links = 0;
+        let mut compact = true;
+
+        for (slot, &link) in inode.links.iter().enumerate() {
+            if link == 0 {
+                continue;
+            }
+
+            if self.volume.is_free_inode(link) {
+                if self.problem(format!(
+                    "Inode {} links to inode {}, which is not allocated.",
+                    i, link
+                )) {
+                    continue;
+                }
+            } else if links[..num_links].contains(&link) {
+                if self.problem(format!("Inode {} links to inode {} twice.", i, link)) {
+                    continue;
+                }
+            } else if slot != num_links && compact {
+                // the links are all moved to the front below, so only report this once
+                compact = false;
+                self.problem(format!(
+                    "Inode {} has unused link slots before its links.",
+                    i
+                ));
+            }
+
+            links[num_links] = link;
+            num_links += 1;
+        }
+
+        if self.repair {
+            inode.links = links;
+        }
+    }
+
+    /// Check that exactly the claimed dnodes (and the reserved dnode 0) are marked as in use
+    fn check_dnode_bitmap(&mut self) {
+        for dnode in 0..self.volume.meta.num_dnode {
+            let used = !self.volume.is_free_dnode(dnode);
+            let claimed = dnode == 0 || self.claimed[dnode].is_some();
+
+            if used && !claimed {
+                if self.problem(format!(
+                    "Dnode {} is marked as in use, but no file uses it.",
+                    dnode
+                )) {
+                    self.volume.free_dnode(dnode);
+                }
+            } else if !used
+                && claimed
+                && self.problem(format!("Dnode {} is in use, but marked as free.", dnode))
+            {
+                self.volume.use_dnode(dnode);
+            }
+        }
+    }
+
+    /// Check that the link count of every file is the number of files linking to it. Only links
+    /// that `check_links` keeps are counted. Directories linked from more than one file are only
+    /// warned about, since there is no telling which link is the right one.
+    fn check_link_counts(&mut self) {
+        let num_inode = self.volume.meta.num_inode;
+        let mut counts = vec![0; num_inode];
+
+        for i in 0..num_inode {
+            if self.volume.is_free_inode(i) {
+                continue;
+            }
+
+            let links = self.volume.get_inode(i).links;
+            for (slot, &link) in links.iter().enumerate() {
+                if link != 0 && !self.volume.is_free_inode(link) && !links[..slot].contains(&link) {
+                    counts[link] += 1;
+                }
+            }
+        }
+
+        for (i, &count) in counts.iter().enumerate() {
+            if self.volume.is_free_inode(i) {
+                continue;
+            }
+
+            let mut inode = self.volume.get_inode(i);
+
+            if inode.nlink != count
+                && self.problem(format!(
+                    "Inode {} has a link count of {}, but {} files link to it.",
+                    i, inode.nlink, count
+                ))
+            {
+                inode.nlink = count;
+                self.volume.write_inode(i, &inode);
+            }
+
+            if inode.is_dir() && count > 1 {
+                println!("Warning! Directory {} is linked from {} files.", i, count);
+            }
+        }
+    }
+
+    /// Warn about files that cannot be reached from the root by following links. They are not
+    /// corrupt, but they can only be opened by inode number.
+    fn check_reachable(&mut self) {
+        let num_inode = self.volume.meta.num_inode;
+        let mut reached = vec![false; num_inode];
+        let mut todo = vec![0];
+        reached[0] = true;
+
+        while let Some(i) = todo.pop() {
+            for &link in self.volume.get_inode(i).links.iter() {
+                if link != 0 && link < num_inode && !reached[link] {
+                    reached[link] = true;
+                    todo.push(link);
+                }
+            }
+        }
+
+        for (i, &reached) in reached.iter().enumerate() {
+            if !reached && !self.volume.is_free_inode(i) {
+                println!("Warning! Inode {} cannot be reached from the root.", i);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A volume with a root linking to one file of the given size, whose name is long if
+    /// `long_name` is true. Returns the volume and the inode number of the file.
+    fn new_volume(size: usize, long_name: bool) -> (Volume<MemDevice>, usize) {
+        let meta = Metadata::new(64, 4096, 8);
+        let device = MemDevice::new(Volume::<MemDevice>::size_in_blocks(&meta));
+        let mut volume = Volume::format(device, meta);
+
+        let root = volume.alloc_inode().unwrap();
+        let file = volume.alloc_inode().unwrap();
+
+        let mut inode = Inode::new();
+        let name: &[u8] = if long_name {
+            b"a rather long name"
+        } else {
+            b"file"
+        };
+        volume.set_name(&mut inode, name).unwrap();
+        volume.write_file(&mut inode, 0, &vec![7; size]).unwrap();
+        volume.write_inode(file, &inode);
+
+        let mut inode = Inode::new();
+        inode.flags = FLAG_DIR;
+        volume.write_inode(root, &inode);
+        volume.add_link(root, file);
+
+        (volume, file)
+    }
+
+    fn found(found: usize, fixed: usize) -> Report {
+        Report {
+            found,
+            fixed,
+            unusable: false,
+        }
+    }
+
+    #[test]
+    fn clean_volumes_pass() {
+        for &size in &[0, 1, 512, 100 * DNODE_SIZE, 400 * DNODE_SIZE + 1] {
+            let (mut volume, _) = new_volume(size, true);
+            assert_eq!(check(&mut volume, true), found(0, 0));
+        }
+    }
+
+    #[test]
+    fn huge_volumes_are_reported() {
+        let (mut volume, _) = new_volume(0, false);
+        volume.meta.num_dnode = 0xFFFF_FFF0;
+
+        let report = check(&mut volume, true);
+        assert_eq!((report.found, report.fixed), (1, 0));
+        assert!(report.unusable);
+    }
+
+    #[test]
+    fn leaked_dnodes_are_freed() {
+        let (mut volume, _) = new_volume(1000, false);
+        let leaked = volume.alloc_dnode().unwrap();
+
+        assert_eq!(check(&mut volume, false), found(1, 0));
+        assert!(!volume.is_free_dnode(leaked));
+
+        assert_eq!(check(&mut volume, true), found(1, 1));
+        assert!(volume.is_free_dnode(leaked));
+        assert_eq!(check(&mut volume, true), found(0, 0));
+    }
+
+    #[test]
+    fn used_dnodes_are_marked() {
+        let (mut volume, file) = new_volume(1000, false);
+        let inode = volume.get_inode(file);
+        let dnode = volume.map_block(&inode, 1).unwrap();
+        volume.free_dnode(dnode);
+
+        assert_eq!(check(&mut volume, true), found(1, 1));
+        assert!(!volume.is_free_dnode(dnode));
+        assert_eq!(check(&mut volume, true), found(0, 0));
+    }
+
+    #[test]
+    fn shared_dnodes_are_unshared() {
+        let (mut volume, file) = new_volume(1000, false);
+
+        // a second file sharing the first dnode of the first one
+        let inode = volume.get_inode(file);
+        let shared = volume.map_block(&inode, 0).unwrap();
+        let mut other = Inode::new();
+        volume.write_file(&mut other, 0, &[1]).unwrap();
+        let extra = volume.map_block(&other, 0).unwrap();
+        volume.write_ptr(other.data, 0, shared);
+        let other_num = volume.alloc_inode().unwrap();
+        volume.write_inode(other_num, &other);
+
+        // the shared dnode, and the dnode the second file no longer uses
+        assert_eq!(check(&mut volume, true), found(2, 2));
+        let other = volume.get_inode(other_num);
+        assert_eq!(volume.map_block(&other, 0), None);
+        assert!(volume.is_free_dnode(extra));
+        assert_eq!(check(&mut volume, true), found(0, 0));
+    }
+
+    #[test]
+    fn dnodes_past_the_end_are_dropped() {
+        let (mut volume, file) = new_volume(300 * DNODE_SIZE, false);
+
+        // the file now ends in its direct dnodes, but still has indirect dnodes
+        let mut inode = volume.get_inode(file);
+        inode.size = 10;
+        volume.write_inode(file, &inode);
+
+        let report = check(&mut volume, true);
+        assert_eq!(report.found, report.fixed);
+        assert!(report.found > 2);
+
+        let inode = volume.get_inode(file);
+        assert_eq!(volume.map_block(&inode, 1), None);
+        assert_eq!(volume.map_block(&inode, NUM_DIRECT), None);
+        assert_eq!(check(&mut volume, true), found(0, 0));
+    }
+
+    #[test]
+    fn bad_pointers_are_cleared() {
+        let (mut volume, file) = new_volume(1000, true);
+
+        let mut inode = volume.get_inode(file);
+        volume.write_ptr(inode.data, 1, 1 << 20);
+        put_word(&mut inode.name, 0, 1 << 20);
+        volume.write_inode(file, &inode);
+
+        // the name dnode and the second data dnode are no longer used
+        assert_eq!(check(&mut volume, true), found(4, 4));
+
+        let inode = volume.get_inode(file);
+        let mut name = [0; NAME_MAX];
+        let len = volume.read_name(&inode, &mut name);
+        assert_eq!(&name[..len], format!("inode{}", file).as_bytes());
+        assert_eq!(volume.map_block(&inode, 1), None);
+        assert_eq!(check(&mut volume, true), found(0, 0));
+    }
+
+    #[test]
+    fn zero_name_dnodes_are_cleared() {
+        let (mut volume, file) = new_volume(0, true);
+
+        let mut inode = volume.get_inode(file);
+        put_word(&mut inode.name, 0, 0);
+        volume.write_inode(file, &inode);
+
+        // the name dnode is no longer used
+        assert_eq!(check(&mut volume, true), found(2, 2));
+
+        let inode = volume.get_inode(file);
+        assert!(!inode.has_long_name());
+        assert_eq!(check(&mut volume, true), found(0, 0));
+    }
+
+    #[test]
+    fn bad_links_are_removed() {
+        let (mut volume, file) = new_volume(0, false);
+
+        let mut root = volume.get_inode(0);
+        root.links = [0; NUM_LINKS];
+        root.links[2] = file;
+        root.links[3] = 40;
+        root.links[4] = file;
+        volume.write_inode(0, &root);
+
+        // out of order, a free inode, and a repeated link
+        assert_eq!(check(&mut volume, true), found(3, 3));
+
+        let root = volume.get_inode(0);
+        assert_eq!(&root.links[..2], &[file, 0]);
+        assert_eq!(check(&mut volume, true), found(0, 0));
+    }
+
+    #[test]
+    fn link_counts_are_fixed() {
+        let (mut volume, file) = new_volume(0, false);
+
+        let mut inode = volume.get_inode(file);
+        inode.nlink = 3;
+        volume.write_inode(file, &inode);
+
+        let mut root = volume.get_inode(0);
+        root.nlink = 1;
+        volume.write_inode(0, &root);
+
+        assert_eq!(check(&mut volume, false), found(2, 0));
+        assert_eq!(check(&mut volume, true), found(2, 2));
+        assert_eq!(volume.get_inode(file).nlink, 1);
+        assert_eq!(volume.get_inode(0).nlink, 0);
+        assert_eq!(check(&mut volume, true), found(0, 0));
+    }
+
+    #[test]
+    fn root_is_a_directory() {
+        let (mut volume, _) = new_volume(0, false);
+
+        let mut root = volume.get_inode(0);
+        root.flags = 0;
+        volume.write_inode(0, &root);
+
+        assert_eq!(check(&mut volume, true), found(1, 1));
+        assert!(volume.get_inode(0).is_dir());
+        assert_eq!(check(&mut volume, true), found(0, 0));
+    }
+
+    #[test]
+    fn symlinks_are_not_directories() {
+        let (mut volume, file) = new_volume(0, false);
+
+        let mut inode = volume.get_inode(file);
+        inode.flags |= FLAG_DIR | FLAG_SYMLINK;
+        volume.write_inode(file, &inode);
+
+        assert_eq!(check(&mut volume, false), found(1, 0));
+        assert_eq!(check(&mut volume, true), found(1, 1));
+
+        let inode = volume.get_inode(file);
+        assert!(inode.is_dir() && !inode.is_symlink());
+        assert_eq!(check(&mut volume, true), found(0, 0));
+    }
+
+    #[test]
+    fn truncated_images_are_not_repaired() {
+        let (volume, _) = new_volume(0, false);
+        let mut bytes = volume.device.as_bytes().to_vec();
+        bytes.truncate(bytes.len() - BLOCK_SIZE);
+
+        let mut volume = Volume::open(MemDevice::from_bytes(bytes)).unwrap();
+        let report = check(&mut volume, true);
+        assert_eq!((report.found, report.fixed), (1, 0));
+        assert!(report.unusable);
+    }
+
+    #[test]
+    fn pending_journal_stops_the_check() {
+        let (mut volume, _) = new_volume(0, false);
+
+        let mut header = [0; BLOCK_SIZE];
+        put_word(&mut header, 0, JOURNAL_MAGIC);
+        put_word(&mut header, 4, 1);
+        let journal_block = volume.journal_block();
+        volume.device.write_block(journal_block, &header);
+
+        assert_eq!(check(&mut volume, true), found(1, 0));
+    }
+}
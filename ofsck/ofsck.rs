@@ -0,0 +1,89 @@
+//! A tool for checking and repairing OFS images
+//!
+//! ```text
+//! ofsck [--repair] <image>
+//! ```
+//!
+//! Without `--repair`, the image is only checked. The exit status follows fsck:
+//!
+//! - 0: no problems
+//! - 1: problems were found and repaired
+//! - 4: problems were found and left as they are
+//! - 8: usage or I/O error, or the image is not a usable OFS volume
+
+extern crate ofs_core;
+
+mod check;
+
+use std::env::args;
+use std::fs;
+use std::process::exit;
+
+use ofs_core::{Error, MemDevice, Volume};
+
+fn usage() -> ! {
+    println!("Usage: ofsck [--repair] <image>");
+    exit(8);
+}
+
+pub fn main() {
+    // read command line args
+    let mut repair = false;
+    let mut file = None;
+
+    for arg in args().skip(1) {
+        match arg.as_str() {
+            "-r" | "--repair" => repair = true,
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let file = file.unwrap_or_else(|| usage());
+
+    let bytes = fs::read(&file).unwrap_or_else(|e| {
+        println!("Error! Cannot read '{}': {}.", file, e);
+        exit(8);
+    });
+
+    let mut volume = match Volume::open(MemDevice::from_bytes(bytes)) {
+        Ok(volume) => volume,
+        Err(Error::UnsupportedVersion(version)) => {
+            println!(
+                "Error! '{}' has OFS version {}, but ofsck only knows version {}.",
+                file,
+                version,
+                ofs_core::OFS_VERSION
+            );
+            exit(8);
+        }
+        Err(_) => {
+            println!("Error! '{}' is not an OFS volume.", file);
+            exit(8);
+        }
+    };
+
+    let report = check::check(&mut volume, repair);
+
+    if report.fixed > 0 {
+        fs::write(&file, volume.device.as_bytes()).unwrap_or_else(|e| {
+            println!("Error! Cannot write '{}': {}.", file, e);
+            exit(8);
+        });
+    }
+
+    println!(
+        "Checked '{}': {} problems found, {} repaired.",
+        file, report.found, report.fixed
+    );
+
+    if report.unusable {
+        exit(8);
+    } else if report.found == 0 {
+        exit(0);
+    } else if report.found == report.fixed {
+        exit(1);
+    } else {
+        exit(4);
+    }
+}
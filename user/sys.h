@@ -1,7 +1,121 @@
 #ifndef _SYS_H_
 #define _SYS_H_
 
+/*
+ * A failing system call returns the negated error number, so any negative
+ * return value is an error. These must match kernel/errno.rs.
+ */
+#define EPERM         1
+#define ENOENT        2
+#define ESRCH         3
+#define EIO           5
+#define E2BIG         7
+#define ENOEXEC       8
+#define EBADF         9
+#define ECHILD       10
+#define ENOMEM       12
+#define EACCES       13
+#define EFAULT       14
+#define EBUSY        16
+#define EEXIST       17
+#define EXDEV        18
+#define ENOTDIR      20
+#define EISDIR       21
+#define EINVAL       22
+#define EMFILE       24
+#define EFBIG        27
+#define ENOSPC       28
+#define ESPIPE       29
+#define EROFS        30
+#define ENAMETOOLONG 36
+#define ENOSYS       38
+#define ENOTEMPTY    39
+#define ELOOP        40
+
+/*
+ * flags for open; with O_CREAT, a missing file is created with the
+ * permissions in the mode argument (e.g. 0640)
+ */
+#define O_RDONLY 0
+#define O_WRONLY 1
+#define O_RDWR   2
+#define O_CREAT  0100
+
+/* values of whence for seek */
+#define SEEK_SET 0
+#define SEEK_CUR 1
+#define SEEK_END 2
+
+/*
+ * an entry returned by getdents, padded to a multiple of 4 bytes;
+ * "." and ".." are not listed
+ */
+struct dirent {
+    unsigned int d_ino;
+    unsigned short d_reclen;
+    unsigned char d_type;
+    char d_name[];
+};
+
+/* values of d_type */
+#define DT_DIR 4
+#define DT_REG 8
+#define DT_LNK 10
+
+/* the time filled in by gettimeofday, since the Unix epoch */
+struct timeval {
+    long tv_sec;
+    long tv_usec;
+};
+
+/* options for waitpid */
+#define WNOHANG 1
+
+/* signals that kill a process (see WTERMSIG) */
+#define SIGILL   4
+#define SIGTRAP  5
+#define SIGBUS   7
+#define SIGFPE   8
+#define SIGSEGV 11
+
+/* decoding the status returned by wait and waitpid */
+#define WIFEXITED(status)   (((status) & 0x7f) == 0)
+#define WEXITSTATUS(status) (((status) >> 8) & 0xff)
+#define WIFSIGNALED(status) (((status) & 0x7f) != 0)
+#define WTERMSIG(status)    ((status) & 0x7f)
+
 extern void exit(long status);
 extern void start_tests(void);
 
+extern int open(const char *path, int flags, ...);
+extern int close(int fd);
+extern int read(int fd, void *buf, int len);
+extern int write(int fd, const void *buf, int len);
+extern int seek(int fd, int offset, int whence);
+
+extern int getdents(int fd, struct dirent *buf, int len);
+extern int mkdir(const char *path);
+extern int rmdir(const char *path);
+extern int symlink(const char *target, const char *path);
+extern int readlink(const char *path, char *buf, int len);
+extern int link(const char *path, const char *dir);
+extern int unlink(const char *path);
+extern int chmod(const char *path, int mode);
+extern int chown(const char *path, int uid, int gid);
+
+extern int getuid(void);
+extern int geteuid(void);
+extern int getgid(void);
+extern int getegid(void);
+extern int setuid(int uid);
+extern int setgid(int gid);
+
+extern long time(long *t);
+extern int gettimeofday(struct timeval *tv, void *tz);
+
+extern int fork(void);
+extern int execve(const char *path, char *const argv[], char *const envp[]);
+extern int waitpid(int pid, int *status, int options);
+extern int wait(int *status);
+
 #endif